@@ -4,14 +4,27 @@
 pub mod addr;
 pub mod cmd;
 pub mod comp;
+pub mod dbg;
 pub mod dec;
 pub mod dest;
+pub mod diag;
+pub mod directive;
+pub mod emu;
+pub mod expr;
+pub mod format;
 pub mod inst;
 pub mod jump;
+pub mod kbd;
 pub mod label;
+pub mod lsp;
+pub mod macros;
+pub mod owned;
 pub mod parser;
 pub mod prog;
+pub mod screen;
+pub mod source;
 pub mod sym;
+pub mod tst;
 
 pub use addr::Addr;
 pub use addr::Err as AddrErr;
@@ -19,19 +32,47 @@ pub use cmd::Cmd;
 pub use cmd::Err as CmdErr;
 pub use comp::Comp;
 pub use comp::Err as CompErr;
+pub use dbg::Debugger;
+pub use dbg::Err as DbgErr;
 pub use dest::Dest;
 pub use dest::Err as DestErr;
+pub use diag::Diagnostic;
+pub use directive::Directive;
+pub use directive::Err as DirectiveErr;
+pub use emu::Emu;
+pub use emu::Err as EmuErr;
+pub use expr::Err as ExprErr;
+pub use expr::Expr;
 pub use inst::DecodeErr as InstDecodeErr;
 pub use inst::Err as InstErr;
 pub use inst::Inst;
 pub use jump::Err as JumpErr;
 pub use jump::Jump;
+pub use kbd::Err as KbdErr;
+pub use kbd::Keyboard;
+pub use label::Anon;
 pub use label::Err as LabelErr;
 pub use label::Label;
+pub use lsp::Err as LspErr;
+pub use lsp::Server as LspServer;
+pub use macros::Call;
+pub use macros::Err as MacroErr;
+pub use macros::Macro;
+pub use owned::Interner;
+pub use owned::Label as OwnedLabel;
+pub use owned::Prog as OwnedProg;
+pub use parser::Element;
+pub use parser::ElementKind;
 pub use parser::Err as ParserErr;
+pub use parser::Lossless;
 pub use parser::Parser;
 pub use parser::Token;
 pub use parser::TokenKind;
 pub use prog::Err as ProgErr;
 pub use prog::Prog;
+pub use screen::Screen;
+pub use source::Source;
 pub use sym::Sym;
+pub use tst::Err as TstErr;
+pub use tst::RunErr as TstRunErr;
+pub use tst::Script as TstScript;
@@ -1,5 +1,8 @@
 //! Numerical and named addresses for the HACK assembly language.
 
+use crate::hack::Anon;
+use crate::hack::Expr;
+use crate::hack::ExprErr;
 use crate::hack::Label;
 use crate::hack::Sym;
 use crate::parser;
@@ -20,6 +23,8 @@ use std::convert::TryFrom;
 ///
 /// Numerical address objects can be created from [u16] values and
 /// named address objects from (labels)[Label] or (symbols)[Sym].
+/// Addresses can also be [constant expressions](Expr), which are
+/// evaluated at assembly time.
 ///
 /// # Examples
 ///
@@ -54,6 +59,10 @@ pub enum Addr<'b> {
   /// Predefined symbol address.
   #[display(fmt = "{}", _0)]
   Sym(Sym),
+
+  /// Constant expression address.
+  #[display(fmt = "{}", _0)]
+  Expr(Expr<'b>),
 }
 
 /// Build an [Addr] object from a [u16] address value.
@@ -78,7 +87,7 @@ impl TryFrom<u16> for Addr<'_> {
   fn try_from(addr: u16) -> Result<Self, Self::Error> {
     // 32767 (15 bits of address value)
     if addr > 32767 {
-      return Err(Err::Range(addr));
+      return Err(Err::Range(i64::from(addr)));
     }
 
     Ok(Self::Num(addr))
@@ -95,7 +104,11 @@ pub enum Err {
 
   /// Value is outside the 15-bits range.
   #[display(fmt = "address `{}` is outside the 15-bits range", _0)]
-  Range(u16),
+  Range(i64),
+
+  /// Invalid constant expression.
+  #[display(fmt = "{}", _0)]
+  Expr(ExprErr),
 
   /// Invalid label name.
   #[display(fmt = "invalid named address `{}`", _0)]
@@ -125,6 +138,57 @@ impl Err {
 }
 
 impl<'b> Addr<'b> {
+  /// Returns the value of an address.
+  ///
+  /// Labels, and names in expressions, are resolved with `lookup`.
+  /// The value of an expression must be within the 15-bits range.
+  ///
+  /// # Arguments
+  ///
+  /// * `lookup` - Returns the value of a label, variable or constant,
+  ///   or `None` if it is undefined.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Addr;
+  /// use has::hack::AddrErr;
+  /// use has::hack::ExprErr;
+  ///
+  /// let value = |text: &str| {
+  ///   let (addr, _, _) = Addr::read_from(text.as_bytes()).unwrap();
+  ///   addr.value(|label| if label.name() == "ROW" { Some(3) } else { None })
+  /// };
+  ///
+  /// assert_eq!(value("KBD"), Ok(24576));
+  /// assert_eq!(value("ROW"), Ok(3));
+  /// assert_eq!(value("SCREEN+ROW*32"), Ok(16480));
+  /// assert_eq!(value("ROW-4"), Err(AddrErr::Range(-1)));
+  /// assert_eq!(value("COL"), Err(AddrErr::Expr(ExprErr::Undefined(String::from("COL")))));
+  /// ```
+  pub fn value<F>(&self, mut lookup: F) -> Result<u16, Err>
+  where
+    F: FnMut(Label<'b>) -> Option<u16>,
+  {
+    match *self {
+      Addr::Num(addr) => Ok(addr),
+      Addr::Sym(sym) => Ok(u16::from(sym)),
+      Addr::Label(label) => {
+        lookup(label).ok_or_else(|| Err::Expr(ExprErr::Undefined(label.to_string())))
+      }
+      Addr::Expr(expr) => Self::check_range(expr.eval(lookup).map_err(Err::Expr)?),
+    }
+  }
+
+  /// Check that the value of an expression is within the 15-bits
+  /// range.
+  fn check_range(value: i64) -> Result<u16, Err> {
+    match u16::try_from(value) {
+      Ok(value) if value <= 32767 => Ok(value),
+      _ => Err(Err::Range(value)),
+    }
+  }
+
   /// Read an address object from a buffer.
   ///
   /// Returns an address object, the remainder of the input buffer and
@@ -148,6 +212,9 @@ impl<'b> Addr<'b> {
   /// let expected = AddrErr::InvalidNum(String::from("123Foo"));
   /// assert_eq!(Addr::read_from("123Foo".as_bytes()), Err(expected));
   ///
+  /// assert_eq!(Addr::read_from("32768".as_bytes()), Err(AddrErr::Range(32768)));
+  /// assert_eq!(Addr::read_from("0x4000*2".as_bytes()), Err(AddrErr::Range(32768)));
+  ///
   /// let expected = (Addr::Num(123), "".as_bytes(), 3);
   /// assert_eq!(Addr::read_from("123".as_bytes()), Ok(expected));
   ///
@@ -170,22 +237,29 @@ impl<'b> Addr<'b> {
   /// let sym = Sym::try_from("R0".as_bytes()).unwrap();
   /// let expected = (Addr::Sym(sym), "".as_bytes(), 2);
   /// assert_eq!(Addr::read_from("R0".as_bytes()), Ok(expected));
+  ///
+  /// let (addr, rem, len) = Addr::read_from("SCREEN+32 // Row 1".as_bytes()).unwrap();
+  /// assert!(matches!(addr, Addr::Expr(_)));
+  /// assert_eq!((addr.to_string().as_str(), rem, len), ("@SCREEN+32", " // Row 1".as_bytes(), 9));
+  ///
+  /// let label = Label::anonymous("1f".as_bytes()).unwrap();
+  /// assert_eq!(Addr::read_from("1f".as_bytes()), Ok((Addr::Label(label), "".as_bytes(), 2)));
+  ///
+  /// let (addr, _, _) = Addr::read_from("'A'".as_bytes()).unwrap();
+  /// assert_eq!(addr.value(|_| None), Ok(65));
   /// ```
   pub fn read_from(buf: Buf<'b>) -> Result<(Self, Buf<'b>, usize), Err> {
-    if parser::read_digit(buf).is_some() {
-      let (num, rem) = parser::read_until_ws(buf);
+    let (txt, rem) = parser::read_until_ws(buf);
 
-      match u16::from_radix_10_checked(num) {
-        (Some(addr), used) if used == num.len() => {
-          let addr = Self::try_from(addr).map_err(|_| Err::Range(addr))?;
-          return Ok((addr, rem, num.len()));
+    if parser::read_digit(buf).is_some() {
+      if let (Some(addr), used) = u16::from_radix_10_checked(txt) {
+        if used == txt.len() {
+          let addr = Self::try_from(addr)?;
+          return Ok((addr, rem, txt.len()));
         }
-        (_, _) => return Err(Err::invalid_num(num)),
       }
     }
 
-    let (txt, rem) = parser::read_until_ws(buf);
-
     if let Ok(sym) = Sym::try_from(txt) {
       return Ok((Self::from(sym), rem, txt.len()));
     }
@@ -194,6 +268,29 @@ impl<'b> Addr<'b> {
       return Ok((Self::from(label), rem, txt.len()));
     }
 
-    Err(Err::invalid_name(txt))
+    if let Some(label) = Label::anonymous(txt) {
+      if !matches!(label.anon(), Some(Anon::Decl(_))) {
+        return Ok((Self::from(label), rem, txt.len()));
+      }
+    }
+
+    let is_expr = txt.first().is_some_and(|&b| {
+      b.is_ascii_digit() || Label::is_label_start(b) || b == b'(' || b == b'\''
+    });
+
+    match Expr::read_from(buf) {
+      Ok((expr, rem, len)) if is_expr => {
+        if let Some(value) = expr.constant() {
+          Self::check_range(value.map_err(Err::Expr)?)?;
+        }
+
+        Ok((Self::Expr(expr), rem, len))
+      }
+      Err(e) if is_expr && txt.iter().any(|b| b"+-*/%()'".contains(b)) => {
+        Err(Err::Expr(e))
+      }
+      _ if parser::read_digit(buf).is_some() => Err(Err::invalid_num(txt)),
+      _ => Err(Err::invalid_name(txt)),
+    }
   }
 }
@@ -50,6 +50,40 @@ impl Err {
   }
 }
 
+/// Deserialize a [Cmd] object from [u16].
+///
+/// A-instructions always decode to a numerical [address](Addr). The
+/// bits that are ignored by the HACK CPU in C-instructions are not
+/// checked.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::Addr;
+/// use has::hack::Cmd;
+/// use has::hack::Comp;
+/// use has::hack::Dest;
+/// use has::hack::Inst;
+/// use has::hack::Jump;
+/// use std::convert::TryFrom;
+///
+/// assert_eq!(Cmd::try_from(0b0100_0000_0000_0000), Ok(Cmd::Addr(Addr::Num(16384))));
+///
+/// let inst = Inst::new(Dest::D, Comp::DPlus1, Jump::Null).unwrap();
+/// assert_eq!(Cmd::try_from(0b111_0011111_010_000), Ok(Cmd::Inst(inst)));
+/// ```
+impl TryFrom<u16> for Cmd<'_> {
+  type Error = InstDecodeErr;
+
+  fn try_from(value: u16) -> Result<Self, Self::Error> {
+    if value & 0b1000_0000_0000_0000 == 0 {
+      Ok(Cmd::Addr(Addr::Num(value)))
+    } else {
+      Ok(Cmd::Inst(Inst::try_from(value & 0b0001_1111_1111_1111)?))
+    }
+  }
+}
+
 impl<'b> Cmd<'b> {
   /// Create a new command from a binary encoding.
   ///
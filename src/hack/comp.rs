@@ -291,7 +291,9 @@ impl TryFrom<u16> for Comp {
 
 /// Parse a [Comp] object from a (byte buffer)(Buf).
 ///
-/// An unrecognized input produces an `Err(())`.
+/// An unrecognized input produces an `Err(())`. The operands of
+/// commutative operations may be given in any order (e.g. `1+D` for
+/// [Comp::DPlus1]).
 ///
 /// # Examples
 ///
@@ -327,6 +329,11 @@ impl TryFrom<u16> for Comp {
 /// assert_eq!(Comp::try_from("M-D".as_bytes()), Ok(Comp::MMinusD));
 /// assert_eq!(Comp::try_from("D&M".as_bytes()), Ok(Comp::DAndM));
 /// assert_eq!(Comp::try_from("D|M".as_bytes()), Ok(Comp::DOrM));
+///
+/// assert_eq!(Comp::try_from("1+D".as_bytes()), Ok(Comp::DPlus1));
+/// assert_eq!(Comp::try_from("A+D".as_bytes()), Ok(Comp::DPlusA));
+/// assert_eq!(Comp::try_from("M&D".as_bytes()), Ok(Comp::DAndM));
+/// assert_eq!(Comp::try_from("1-D".as_bytes()), Err(()));
 /// ```
 impl TryFrom<Buf<'_>> for Comp {
   type Error = ();
@@ -361,6 +368,15 @@ impl TryFrom<Buf<'_>> for Comp {
       b"M-D" => Ok(Comp::MMinusD),
       b"D&M" => Ok(Comp::DAndM),
       b"D|M" => Ok(Comp::DOrM),
+      b"1+D" => Ok(Comp::DPlus1),
+      b"1+A" => Ok(Comp::APlus1),
+      b"A+D" => Ok(Comp::DPlusA),
+      b"A&D" => Ok(Comp::DAndA),
+      b"A|D" => Ok(Comp::DOrA),
+      b"1+M" => Ok(Comp::MPlus1),
+      b"M+D" => Ok(Comp::DPlusM),
+      b"M&D" => Ok(Comp::DAndM),
+      b"M|D" => Ok(Comp::DOrM),
       _ => Err(()),
     }
   }
@@ -622,4 +638,73 @@ impl Comp {
       Err(_) => Err(Err::unknown(b)),
     }
   }
+
+  /// Whether a computation reads the `M` register (its `a` bit is
+  /// set).
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Comp;
+  ///
+  /// assert!(Comp::DPlusM.has_m());
+  /// assert!(!Comp::DPlusA.has_m());
+  /// assert!(!Comp::Zero.has_m());
+  /// ```
+  pub fn has_m(&self) -> bool {
+    u16::from(*self) & 0b100_0000 != 0
+  }
+
+  /// Evaluate a computation as the HACK ALU would.
+  ///
+  /// Returns the result of the computation given the values of the
+  /// Data register `d`, the Address register `a` and the Memory
+  /// register `m`. Arithmetic wraps around on overflow.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Comp;
+  ///
+  /// assert_eq!(Comp::Zero.eval(7, 8, 9), 0);
+  /// assert_eq!(Comp::Neg1.eval(7, 8, 9), 0xFFFF);
+  /// assert_eq!(Comp::NotD.eval(0b1010, 8, 9), !0b1010);
+  /// assert_eq!(Comp::DPlusA.eval(7, 8, 9), 15);
+  /// assert_eq!(Comp::AMinusD.eval(7, 8, 9), 1);
+  /// assert_eq!(Comp::DMinusM.eval(7, 8, 9), 0xFFFE);
+  /// assert_eq!(Comp::MPlus1.eval(7, 8, 0xFFFF), 0);
+  /// assert_eq!(Comp::DAndM.eval(0b0110, 8, 0b0011), 0b0010);
+  /// ```
+  pub fn eval(&self, d: u16, a: u16, m: u16) -> u16 {
+    match self {
+      Comp::Zero => 0,
+      Comp::One => 1,
+      Comp::Neg1 => 0xFFFF,
+      Comp::D => d,
+      Comp::A => a,
+      Comp::NotD => !d,
+      Comp::NotA => !a,
+      Comp::NegD => d.wrapping_neg(),
+      Comp::NegA => a.wrapping_neg(),
+      Comp::DPlus1 => d.wrapping_add(1),
+      Comp::APlus1 => a.wrapping_add(1),
+      Comp::DMinus1 => d.wrapping_sub(1),
+      Comp::AMinus1 => a.wrapping_sub(1),
+      Comp::DPlusA => d.wrapping_add(a),
+      Comp::DMinusA => d.wrapping_sub(a),
+      Comp::AMinusD => a.wrapping_sub(d),
+      Comp::DAndA => d & a,
+      Comp::DOrA => d | a,
+      Comp::M => m,
+      Comp::NotM => !m,
+      Comp::NegM => m.wrapping_neg(),
+      Comp::MPlus1 => m.wrapping_add(1),
+      Comp::MMinus1 => m.wrapping_sub(1),
+      Comp::DPlusM => d.wrapping_add(m),
+      Comp::DMinusM => d.wrapping_sub(m),
+      Comp::MMinusD => m.wrapping_sub(d),
+      Comp::DAndM => d & m,
+      Comp::DOrM => d | m,
+    }
+  }
 }
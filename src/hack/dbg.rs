@@ -0,0 +1,460 @@
+//! Debugger for HACK programs.
+//!
+//! [Debugger] runs a [Prog] in the [emulator](Emu) and stops
+//! execution at breakpoints on ROM addresses or labels and at
+//! watchpoints on RAM cells. [Command] parses the commands of the
+//! interactive debugger (`has debug`).
+
+use crate::hack::emu::RAM_SIZE;
+use crate::hack::emu::ROM_SIZE;
+use crate::hack::Cmd;
+use crate::hack::Emu;
+use crate::hack::EmuErr;
+use crate::hack::Label;
+use crate::hack::Prog;
+use crate::hack::Sym;
+use crate::Loc;
+use derive_more::Display;
+use derive_more::From;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+/// Errors returned by the debugger.
+#[derive(Display, Debug, Clone, PartialEq, Eq, From)]
+pub enum Err {
+  /// Unknown debugger command.
+  #[display(fmt = "Unknown command `{}`, try `help`", _0)]
+  #[from(ignore)]
+  UnknownCommand(String),
+
+  /// A debugger command is missing its argument.
+  #[display(fmt = "Missing argument for `{}`", _0)]
+  #[from(ignore)]
+  MissingArg(&'static str),
+
+  /// Invalid number of steps.
+  #[display(fmt = "Invalid count `{}`", _0)]
+  #[from(ignore)]
+  InvalidCount(String),
+
+  /// Unknown ROM address or label.
+  #[display(fmt = "Unknown ROM address or label `{}`", _0)]
+  #[from(ignore)]
+  UnknownRomAddr(String),
+
+  /// Unknown RAM address or variable.
+  #[display(fmt = "Unknown RAM address or variable `{}`", _0)]
+  #[from(ignore)]
+  UnknownRamAddr(String),
+
+  /// Emulator error.
+  #[display(fmt = "{}", _0)]
+  Emu(EmuErr),
+}
+
+/// A debugger command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'c> {
+  /// Execute a number of clock cycles (`step [N]`).
+  Step(u64),
+
+  /// Run until a breakpoint, watchpoint or halt (`continue`).
+  Continue,
+
+  /// Set a breakpoint on a ROM address or label (`break ADDR`).
+  Break(&'c str),
+
+  /// Remove a breakpoint (`delete ADDR`).
+  Delete(&'c str),
+
+  /// Set a watchpoint on a RAM address or variable (`watch ADDR`).
+  Watch(&'c str),
+
+  /// Remove a watchpoint (`unwatch ADDR`).
+  Unwatch(&'c str),
+
+  /// Print a RAM cell (`print ADDR`).
+  Print(&'c str),
+
+  /// Print the registers and the current instruction (`info`).
+  Info,
+
+  /// List the instructions around the program counter (`list`).
+  List,
+
+  /// Reset the program counter (`reset`).
+  Reset,
+
+  /// Print the list of commands (`help`).
+  Help,
+
+  /// Exit the debugger (`quit`).
+  Quit,
+}
+
+impl<'c> Command<'c> {
+  /// Parse a debugger command.
+  ///
+  /// Returns `None` for empty lines.
+  ///
+  /// # Arguments
+  ///
+  /// * `line` - A line of user input.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::dbg::Command;
+  ///
+  /// assert_eq!(Command::parse("s 10"), Ok(Some(Command::Step(10))));
+  /// assert_eq!(Command::parse("break LOOP"), Ok(Some(Command::Break("LOOP"))));
+  /// assert_eq!(Command::parse("  "), Ok(None));
+  /// assert!(Command::parse("jump").is_err());
+  /// ```
+  pub fn parse(line: &'c str) -> Result<Option<Self>, Err> {
+    let mut words = line.split_whitespace();
+
+    let name = match words.next() {
+      Some(name) => name,
+      None => return Ok(None),
+    };
+
+    let arg = words.next();
+    let arg = |cmd: &'static str| arg.ok_or(Err::MissingArg(cmd));
+
+    let cmd = match name {
+      "s" | "step" => match arg("step").ok() {
+        Some(count) => match count.parse::<u64>() {
+          Ok(count) => Command::Step(count),
+          Err(_) => return Err(Err::InvalidCount(String::from(count))),
+        },
+        None => Command::Step(1),
+      },
+      "c" | "continue" => Command::Continue,
+      "b" | "break" => Command::Break(arg("break")?),
+      "d" | "delete" => Command::Delete(arg("delete")?),
+      "w" | "watch" => Command::Watch(arg("watch")?),
+      "unwatch" => Command::Unwatch(arg("unwatch")?),
+      "p" | "print" => Command::Print(arg("print")?),
+      "i" | "info" => Command::Info,
+      "l" | "list" => Command::List,
+      "reset" => Command::Reset,
+      "h" | "help" => Command::Help,
+      "q" | "quit" => Command::Quit,
+      _ => return Err(Err::UnknownCommand(String::from(name))),
+    };
+
+    Ok(Some(cmd))
+  }
+
+  /// Usage of the debugger commands.
+  pub const HELP: &'static str = "\
+step [N]      (s)  Execute N clock cycles (default 1)
+continue      (c)  Run until a breakpoint, watchpoint or halt
+break ADDR    (b)  Set a breakpoint on a ROM address or label
+delete ADDR   (d)  Remove a breakpoint
+watch ADDR    (w)  Set a watchpoint on a RAM address or variable
+unwatch ADDR       Remove a watchpoint
+print ADDR    (p)  Print a RAM address or variable
+info          (i)  Print the registers and the current instruction
+list          (l)  List the instructions around the program counter
+reset              Reset the program counter to 0
+help          (h)  Print this help
+quit          (q)  Exit the debugger";
+}
+
+/// The reason execution stopped.
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+  /// A breakpoint was reached.
+  #[display(fmt = "Breakpoint at ROM[{}]", _0)]
+  Breakpoint(u16),
+
+  /// A watched RAM cell changed.
+  ///
+  /// Contains the RAM address, the old value and the new value.
+  #[display(fmt = "Watchpoint RAM[{}]: {} -> {}", _0, "*_1 as i16", "*_2 as i16")]
+  Watchpoint(u16, u16, u16),
+
+  /// The program [halted](Emu::is_halted).
+  #[display(fmt = "Halted")]
+  Halted,
+
+  /// The requested number of clock cycles was executed.
+  #[display(fmt = "Stopped")]
+  Limit,
+}
+
+/// A debugger for a HACK program.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::dbg::Debugger;
+/// use has::hack::dbg::Stop;
+/// use has::hack::Prog;
+///
+/// let buf = "@i\nM=1\n(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP".as_bytes();
+/// let prog = Prog::from_source(buf).unwrap();
+/// let mut dbg = Debugger::new(&prog).unwrap();
+///
+/// assert_eq!(dbg.add_breakpoint("LOOP"), Ok(2));
+/// assert_eq!(dbg.cont(100), Ok(Stop::Breakpoint(2)));
+/// assert_eq!(dbg.remove_breakpoint("LOOP"), Ok(true));
+///
+/// assert_eq!(dbg.add_watchpoint("i"), Ok(16));
+/// assert_eq!(dbg.cont(100), Ok(Stop::Watchpoint(16, 1, 2)));
+/// ```
+pub struct Debugger<'p, 'b> {
+  /// The program being debugged.
+  prog: &'p Prog<'b>,
+
+  /// The emulator running the program.
+  emu: Emu,
+
+  /// ROM addresses of breakpoints.
+  breakpoints: BTreeSet<u16>,
+
+  /// RAM addresses of watchpoints and their last seen values.
+  watchpoints: BTreeMap<u16, u16>,
+}
+
+impl<'p, 'b> Debugger<'p, 'b> {
+  /// Create a debugger for `prog`.
+  ///
+  /// # Arguments
+  ///
+  /// * `prog` - The program to debug.
+  pub fn new(prog: &'p Prog<'b>) -> Result<Self, EmuErr> {
+    Ok(Self {
+      prog,
+      emu: Emu::from_prog(prog)?,
+      breakpoints: BTreeSet::new(),
+      watchpoints: BTreeMap::new(),
+    })
+  }
+
+  /// Returns the emulator running the program.
+  pub fn emu(&self) -> &Emu {
+    &self.emu
+  }
+
+  /// Returns a mutable reference to the emulator running the program.
+  pub fn emu_mut(&mut self) -> &mut Emu {
+    &mut self.emu
+  }
+
+  /// Returns the ROM addresses of breakpoints.
+  pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+    self.breakpoints.iter().copied()
+  }
+
+  /// Returns the RAM addresses of watchpoints.
+  pub fn watchpoints(&self) -> impl Iterator<Item = u16> + '_ {
+    self.watchpoints.keys().copied()
+  }
+
+  /// Returns the instruction at ROM address `addr` as written in the
+  /// program, or `None` if the address is outside of the program.
+  pub fn inst(&self, addr: u16) -> Option<Cmd<'b>> {
+    self.prog.insts().get(usize::from(addr)).copied()
+  }
+
+  /// Returns the [location](Loc) in the program's source of the
+  /// instruction at ROM address `addr`.
+  pub fn loc(&self, addr: u16) -> Option<Loc> {
+    self.prog.loc(addr)
+  }
+
+  /// Returns the labels pointing to ROM address `addr`.
+  pub fn labels_at(&self, addr: u16) -> impl Iterator<Item = &Label<'b>> + '_ {
+    self.prog.labels().filter(move |(_, &a)| a == addr).map(|(label, _)| label)
+  }
+
+  /// Returns the names of the user-defined variables pointing to RAM
+  /// address `addr`.
+  pub fn vars_at(&self, addr: u16) -> impl Iterator<Item = String> + '_ {
+    self.prog.vars().filter(move |(_, &a)| a == addr).map(|(label, _)| label.to_string())
+  }
+
+  /// Resolve a ROM address or label.
+  ///
+  /// # Arguments
+  ///
+  /// * `name` - A decimal ROM address or the name of a label.
+  pub fn resolve_rom(&self, name: &str) -> Result<u16, Err> {
+    if let Ok(addr) = name.parse::<u16>() {
+      if usize::from(addr) < ROM_SIZE {
+        return Ok(addr);
+      }
+    }
+
+    self
+      .prog
+      .labels()
+      .find(|(label, _)| label.to_string() == name)
+      .map(|(_, &addr)| addr)
+      .ok_or_else(|| Err::UnknownRomAddr(String::from(name)))
+  }
+
+  /// Resolve a RAM address or variable.
+  ///
+  /// # Arguments
+  ///
+  /// * `name` - A decimal RAM address, the name of a user-defined
+  ///   variable or a predefined symbol (e.g. `R0` or `SCREEN`).
+  pub fn resolve_ram(&self, name: &str) -> Result<u16, Err> {
+    if let Ok(addr) = name.parse::<u16>() {
+      if usize::from(addr) < RAM_SIZE {
+        return Ok(addr);
+      }
+    }
+
+    if let Ok(sym) = Sym::try_from(name.as_bytes()) {
+      return Ok(u16::from(sym));
+    }
+
+    self
+      .prog
+      .vars()
+      .find(|(label, _)| label.to_string() == name)
+      .map(|(_, &addr)| addr)
+      .ok_or_else(|| Err::UnknownRamAddr(String::from(name)))
+  }
+
+  /// Set a breakpoint.
+  ///
+  /// Returns the ROM address of the breakpoint.
+  ///
+  /// # Arguments
+  ///
+  /// * `name` - A ROM address or label.
+  pub fn add_breakpoint(&mut self, name: &str) -> Result<u16, Err> {
+    let addr = self.resolve_rom(name)?;
+    self.breakpoints.insert(addr);
+    Ok(addr)
+  }
+
+  /// Remove a breakpoint.
+  ///
+  /// Returns whether there was a breakpoint at the address.
+  ///
+  /// # Arguments
+  ///
+  /// * `name` - A ROM address or label.
+  pub fn remove_breakpoint(&mut self, name: &str) -> Result<bool, Err> {
+    let addr = self.resolve_rom(name)?;
+    Ok(self.breakpoints.remove(&addr))
+  }
+
+  /// Set a watchpoint.
+  ///
+  /// Returns the RAM address of the watchpoint.
+  ///
+  /// # Arguments
+  ///
+  /// * `name` - A RAM address or variable.
+  pub fn add_watchpoint(&mut self, name: &str) -> Result<u16, Err> {
+    let addr = self.resolve_ram(name)?;
+    self.watchpoints.insert(addr, self.emu.ram()[usize::from(addr)]);
+    Ok(addr)
+  }
+
+  /// Remove a watchpoint.
+  ///
+  /// Returns whether there was a watchpoint at the address.
+  ///
+  /// # Arguments
+  ///
+  /// * `name` - A RAM address or variable.
+  pub fn remove_watchpoint(&mut self, name: &str) -> Result<bool, Err> {
+    let addr = self.resolve_ram(name)?;
+    Ok(self.watchpoints.remove(&addr).is_some())
+  }
+
+  /// Execute at most `limit` clock cycles.
+  ///
+  /// Execution stops early after a clock cycle that changed a watched
+  /// RAM cell, halted the program or reached a breakpoint.
+  ///
+  /// # Arguments
+  ///
+  /// * `limit` - The maximum number of clock cycles to execute.
+  pub fn cont(&mut self, limit: u64) -> Result<Stop, Err> {
+    for _ in 0..limit {
+      self.emu.step()?;
+
+      let ram = self.emu.ram();
+      let changed = self
+        .watchpoints
+        .iter_mut()
+        .map(|(&addr, old)| (addr, old, ram[usize::from(addr)]))
+        .filter(|(_, old, new)| **old != *new)
+        .map(|(addr, old, new)| (addr, std::mem::replace(old, new), new))
+        .last();
+
+      if let Some((addr, old, new)) = changed {
+        return Ok(Stop::Watchpoint(addr, old, new));
+      }
+
+      if self.emu.is_halted() {
+        return Ok(Stop::Halted);
+      }
+
+      if self.breakpoints.contains(&self.emu.pc()) {
+        return Ok(Stop::Breakpoint(self.emu.pc()));
+      }
+    }
+
+    Ok(Stop::Limit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn commands() {
+    assert_eq!(Command::parse("step"), Ok(Some(Command::Step(1))));
+    assert_eq!(Command::parse("c"), Ok(Some(Command::Continue)));
+    assert_eq!(Command::parse("w  R2 "), Ok(Some(Command::Watch("R2"))));
+    assert_eq!(Command::parse("unwatch 16"), Ok(Some(Command::Unwatch("16"))));
+    assert_eq!(Command::parse("b"), Err(Err::MissingArg("break")));
+    assert_eq!(Command::parse("s x"), Err(Err::InvalidCount(String::from("x"))));
+  }
+
+  #[test]
+  fn mult() {
+    let buf = include_bytes!("../../tests/programs/Mult.asm");
+    let prog = Prog::from_source(&buf[..]).unwrap();
+    let mut dbg = Debugger::new(&prog).unwrap();
+    dbg.emu_mut().ram_mut()[0] = 2;
+    dbg.emu_mut().ram_mut()[1] = 3;
+
+    assert_eq!(dbg.resolve_rom("END"), Ok(14));
+    assert_eq!(dbg.resolve_ram("R2"), Ok(2));
+    assert_eq!(dbg.resolve_rom("FOO"), Err(Err::UnknownRomAddr(String::from("FOO"))));
+    assert_eq!(dbg.resolve_ram("LOOP"), Err(Err::UnknownRamAddr(String::from("LOOP"))));
+
+    assert_eq!(dbg.add_watchpoint("R2"), Ok(2));
+    assert_eq!(dbg.cont(1000), Ok(Stop::Watchpoint(2, 0, 3)));
+    assert_eq!(dbg.cont(1000), Ok(Stop::Watchpoint(2, 3, 6)));
+    assert_eq!(dbg.remove_watchpoint("R2"), Ok(true));
+
+    assert_eq!(dbg.add_breakpoint("LOOP"), Ok(2));
+    assert_eq!(dbg.cont(1000), Ok(Stop::Breakpoint(2)));
+    assert_eq!(dbg.labels_at(2).map(|l| l.name()).collect::<Vec<_>>(), ["LOOP"]);
+    assert_eq!(
+      dbg.inst(dbg.emu().pc()).map(|i| i.to_string()),
+      Some(String::from("@R0"))
+    );
+
+    assert_eq!(dbg.remove_breakpoint("2"), Ok(true));
+    assert_eq!(dbg.cont(1000), Ok(Stop::Halted));
+    assert_eq!(dbg.emu().ram()[2], 6);
+    assert_eq!(dbg.cont(3), Ok(Stop::Halted));
+    assert_eq!(dbg.cont(0), Ok(Stop::Limit));
+  }
+}
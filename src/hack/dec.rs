@@ -1,11 +1,19 @@
 //! Parser for disassembling HACK programs from binary and bintext
-//! files.
+//! files, as well as from the Intel HEX, Logisim and Verilog memory
+//! image formats.
 
+use crate::hack::Cmd;
+use crate::hack::CmdErr;
 use crate::parser;
 use crate::Buf;
 use crate::Index;
 use crate::Loc;
 use derive_more::Display;
+use derive_more::From;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io;
+use std::io::Read;
 use std::marker::PhantomData;
 
 /// Shorthand for items returned by the (parser)(Parser)
@@ -34,7 +42,30 @@ impl Impl for BinParser {
     let &lsb = if let Some(lsb) = parser.buf.get(1) {
       lsb
     } else {
-      return Some(Err(Err::expected(parser.buf, parser.index + 1)));
+      return Some(Err(Err::expected(parser.orig, parser.index + 1)));
+    };
+
+    let token = Token::new(parser.index, (u16::from(msb) << 8) | u16::from(lsb));
+
+    parser.buf = &parser.buf[2..];
+    parser.index += 2;
+
+    Some(Ok(token))
+  }
+}
+
+/// A parser helper for little-endian binary HACK programs.
+pub struct BinLeParser;
+
+impl Impl for BinLeParser {
+  type Item = Res;
+
+  fn next<T: Impl>(parser: &mut Parser<T>) -> Option<Self::Item> {
+    let &lsb = parser.buf.first()?;
+    let &msb = if let Some(msb) = parser.buf.get(1) {
+      msb
+    } else {
+      return Some(Err(Err::expected(parser.orig, parser.index + 1)));
     };
 
     let token = Token::new(parser.index, (u16::from(msb) << 8) | u16::from(lsb));
@@ -76,7 +107,7 @@ impl Impl for BinTextParser {
           };
           ($index:expr) => {
             consume_bit!($index, {
-              return Some(Err(Err::expected(parser.buf, parser.index + (15 - $index))))
+              return Some(Err(Err::expected(parser.orig, parser.index + (15 - $index))))
             });
           }
         }
@@ -106,6 +137,440 @@ impl Impl for BinTextParser {
   }
 }
 
+/// A parser helper for HACK programs in the Intel HEX format.
+///
+/// Data records hold the instructions as big-endian byte pairs and
+/// must be contiguous, starting at address 0. Parsing stops at the
+/// end-of-file record.
+pub struct IhexParser;
+
+impl Impl for IhexParser {
+  type Item = Res;
+
+  fn next<T: Impl>(parser: &mut Parser<T>) -> Option<Self::Item> {
+    while parser.pending == 0 {
+      skip_blank(parser, b"");
+      let (record, _) = parser::read_until_ws(parser.buf);
+
+      if record.is_empty() {
+        return None;
+      }
+
+      let bytes = match read_ihex_record(record) {
+        Ok(bytes) => bytes,
+        Err(offset) => {
+          return Some(Err(Err::new(parser.orig, parser.index + offset, ErrKind::Record)))
+        }
+      };
+
+      if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+        let index = parser.index + record.len() - 2;
+        return Some(Err(Err::new(parser.orig, index, ErrKind::Checksum)));
+      }
+
+      let (len, addr, data) = (
+        bytes[0],
+        u16::from(bytes[1]) << 8 | u16::from(bytes[2]),
+        &bytes[4..bytes.len() - 1],
+      );
+
+      match bytes[3] {
+        0x00 if len % 2 != 0 => {
+          return Some(Err(Err::new(parser.orig, parser.index + 1, ErrKind::Record)))
+        }
+        0x00 if usize::from(addr) != parser.words * 2 => {
+          return Some(Err(Err::new(parser.orig, parser.index + 3, ErrKind::Address)))
+        }
+        0x00 if len > 0 => {
+          parser.pending = usize::from(len / 2);
+          parser.buf = &parser.buf[9..];
+          parser.index += 9;
+          continue;
+        }
+        0x01 => {
+          parser.buf = &parser.buf[parser.buf.len()..];
+          parser.end = true;
+          return None;
+        }
+        0x02 | 0x04 if data.iter().any(|&b| b != 0) => {
+          return Some(Err(Err::new(parser.orig, parser.index + 9, ErrKind::Address)))
+        }
+        0x00 | 0x02..=0x05 => {}
+        _ => return Some(Err(Err::new(parser.orig, parser.index + 7, ErrKind::Record))),
+      }
+
+      parser.buf = &parser.buf[record.len()..];
+      parser.index += record.len();
+    }
+
+    // The record has already been checked to only contain hex digits.
+    let value =
+      parser.buf[..4].iter().fold(0, |value, &b| value << 4 | hex_digit(b).unwrap());
+    let token = Token::new(parser.index, value);
+
+    parser.buf = &parser.buf[4..];
+    parser.index += 4;
+    parser.pending -= 1;
+
+    if parser.pending == 0 {
+      // Skip the checksum.
+      parser.buf = &parser.buf[2..];
+      parser.index += 2;
+    }
+
+    Some(Ok(token))
+  }
+}
+
+/// A parser helper for HACK programs in the Logisim `v2.0 raw` memory
+/// image format.
+///
+/// After the header, the instructions are given as hexadecimal
+/// numbers, optionally repeated with a `COUNT*VALUE` prefix. Comments
+/// start with `#`.
+pub struct LogisimParser;
+
+impl Impl for LogisimParser {
+  type Item = Res;
+
+  fn next<T: Impl>(parser: &mut Parser<T>) -> Option<Self::Item> {
+    const HEADER: &[u8] = b"v2.0 raw";
+
+    if !parser.header {
+      if !parser.buf.starts_with(HEADER) {
+        return Some(Err(Err::new(parser.orig, parser.index, ErrKind::Header)));
+      }
+
+      parser.buf = &parser.buf[HEADER.len()..];
+      parser.index += HEADER.len();
+      parser.header = true;
+    }
+
+    while parser.pending == 0 {
+      skip_blank(parser, b"#");
+      let (word, _) = parser::read_until_ws(parser.buf);
+
+      if word.is_empty() {
+        return None;
+      }
+
+      let star = match word.iter().position(|&b| b == b'*') {
+        Some(star) => star,
+        None => {
+          parser.pending = 1;
+          break;
+        }
+      };
+
+      let count = match std::str::from_utf8(&word[..star]).map(str::parse::<usize>) {
+        Ok(Ok(count)) => count,
+        _ => return Some(Err(Err::new(parser.orig, parser.index, ErrKind::Digit))),
+      };
+
+      parser.buf = &parser.buf[star + 1..];
+      parser.index += star + 1;
+
+      if count == 0 {
+        if let Err(e) = read_word(parser, 16) {
+          return Some(Err(e));
+        }
+      }
+
+      parser.pending = count;
+    }
+
+    let (buf, index) = (parser.buf, parser.index);
+    let token = read_word(parser, 16);
+    parser.pending -= 1;
+
+    // Stay on a repeated value until all repetitions are returned.
+    if token.is_ok() && parser.pending > 0 {
+      parser.buf = buf;
+      parser.index = index;
+    }
+
+    Some(token)
+  }
+}
+
+/// A parser helper for HACK programs in the format read by Verilog's
+/// `$readmemh`, with one hexadecimal number per instruction.
+///
+/// Comments start with `//`. Address specifications (`@ADDRESS`) are
+/// not supported.
+pub struct ReadmemhParser;
+
+impl Impl for ReadmemhParser {
+  type Item = Res;
+
+  fn next<T: Impl>(parser: &mut Parser<T>) -> Option<Self::Item> {
+    skip_blank(parser, b"//");
+
+    if parser.buf.is_empty() {
+      return None;
+    }
+
+    Some(read_word(parser, 16))
+  }
+}
+
+/// A parser helper for HACK programs in the format read by Verilog's
+/// `$readmemb`, with one binary number per instruction.
+///
+/// Unlike [BinTextParser], this accepts comments starting with `//`,
+/// numbers with fewer than 16 digits and `_` digit separators.
+/// Address specifications (`@ADDRESS`) are not supported.
+pub struct ReadmembParser;
+
+impl Impl for ReadmembParser {
+  type Item = Res;
+
+  fn next<T: Impl>(parser: &mut Parser<T>) -> Option<Self::Item> {
+    skip_blank(parser, b"//");
+
+    if parser.buf.is_empty() {
+      return None;
+    }
+
+    Some(read_word(parser, 2))
+  }
+}
+
+/// Consume whitespace and comments starting with `comment` from the
+/// input of `parser`.
+///
+/// An empty `comment` only consumes whitespace.
+fn skip_blank<T: Impl>(parser: &mut Parser<T>, comment: &[u8]) {
+  loop {
+    let (len, rem) = parser::read_ws(parser.buf);
+    parser.index += len;
+    parser.buf = rem;
+
+    if comment.is_empty() || !parser.buf.starts_with(comment) {
+      return;
+    }
+
+    let (line, rem) = parser::read_until_nl(parser.buf);
+    parser.index += line.len();
+    parser.buf = rem;
+  }
+}
+
+/// Consume a 16-bit number in `radix` up to the next whitespace from
+/// the input of `parser`.
+///
+/// Digits may be separated by `_`.
+fn read_word<T: Impl>(parser: &mut Parser<T>, radix: u32) -> Res {
+  let (digits, rem) = parser::read_until_ws(parser.buf);
+  let mut value = 0;
+
+  for (i, &b) in digits.iter().enumerate().filter(|&(_, &b)| b != b'_') {
+    let index = parser.index + i;
+    let digit = char::from(b)
+      .to_digit(radix)
+      .ok_or_else(|| Err::new(parser.orig, index, ErrKind::Digit))?;
+    value = value * radix + digit;
+
+    if value > u32::from(u16::MAX) {
+      return Err(Err::new(parser.orig, parser.index, ErrKind::Range));
+    }
+  }
+
+  let token = Token::new(parser.index, value as u16);
+  parser.buf = rem;
+  parser.index += digits.len();
+  Ok(token)
+}
+
+/// Decode the value of a hexadecimal digit.
+fn hex_digit(b: u8) -> Option<u16> {
+  char::from(b).to_digit(16).map(|digit| digit as u16)
+}
+
+/// Decode the bytes of an Intel HEX record (`:LLAAAATT...CC`).
+///
+/// Returns the offset into the record of the first invalid byte on
+/// error.
+fn read_ihex_record(record: Buf) -> Result<Vec<u8>, usize> {
+  if record.first() != Some(&b':') {
+    return Err(0);
+  }
+
+  let bytes = record[1..]
+    .chunks(2)
+    .enumerate()
+    .map(|(i, pair)| match pair {
+      &[hi, lo] => {
+        Ok((hex_digit(hi).ok_or(1 + i * 2)? << 4 | hex_digit(lo).ok_or(2 + i * 2)?) as u8)
+      }
+      _ => Err(1 + i * 2),
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+  if bytes.len() < 5 || bytes.len() != usize::from(bytes[0]) + 5 {
+    return Err(1);
+  }
+
+  Ok(bytes)
+}
+
+/// Encodings of compiled HACK programs.
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  /// Binary with two big-endian bytes per instruction.
+  #[display(fmt = "bin")]
+  Bin,
+
+  /// Binary with two little-endian bytes per instruction.
+  #[display(fmt = "bin-le")]
+  BinLe,
+
+  /// Bintext with one instruction in binary per line.
+  #[display(fmt = "bintext")]
+  Bintext,
+
+  /// Intel HEX.
+  #[display(fmt = "ihex")]
+  Ihex,
+
+  /// Logisim `v2.0 raw` memory image.
+  #[display(fmt = "logisim")]
+  Logisim,
+
+  /// Verilog memory file in hexadecimal (as read by `$readmemh`).
+  #[display(fmt = "readmemh")]
+  Readmemh,
+
+  /// Verilog memory file in binary (as read by `$readmemb`).
+  #[display(fmt = "readmemb")]
+  Readmemb,
+}
+
+/// Detect the encoding of a compiled HACK program from its contents.
+///
+/// Text is recognized as a Logisim memory image by its header, as
+/// Intel HEX by its leading `:`, as bintext if it starts with a
+/// 16-digit binary number and as a `$readmemh` file if it starts with
+/// a hexadecimal number of up to 4 digits. The whole input must then
+/// decode in that format. Any other input is binary, which is
+/// big-endian if all its instructions only decode in that byte order
+/// and little-endian if they only decode in the other.
+///
+/// Text of an even length also decodes as binary (every pair of ASCII
+/// characters is an A-instruction), so text that does not decode in
+/// any text format is read as binary, in both byte orders.
+///
+/// # Arguments
+///
+/// * `buf` - The input buffer.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::dec;
+/// use has::hack::dec::Encoding;
+/// use has::hack::dec::ErrKind;
+///
+/// let detect = |buf: &[u8]| dec::detect(buf).map_err(|e| e.kind());
+///
+/// assert_eq!(detect(b"0000000000010000\n1110101010001000"), Ok(Encoding::Bintext));
+/// assert_eq!(detect(b"0010\nea88\n\n"), Ok(Encoding::Readmemh));
+/// assert_eq!(detect(b":040000000010EA887A\n:00000001FF"), Ok(Encoding::Ihex));
+/// assert_eq!(detect(b"v2.0 raw\n10 ea88\n"), Ok(Encoding::Logisim));
+/// assert_eq!(detect(&[0x00, 0x10, 0xEA, 0x88]), Ok(Encoding::Bin));
+/// assert_eq!(detect(&[0x10, 0x00, 0x88, 0xEA]), Ok(Encoding::BinLe));
+///
+/// // Only A-instructions decode in both byte orders.
+/// let ambiguous = ErrKind::Ambiguous(Encoding::Bin, Encoding::BinLe);
+/// assert_eq!(detect(&[0x00, 0x10, 0x00, 0x11]), Err(ambiguous));
+///
+/// // Text is only read as binary when it does not decode as text.
+/// assert_eq!(detect(b"0010\nea88\n"), Ok(Encoding::Readmemh));
+/// assert_eq!(detect(b"0010\nea88\nzz"), Err(ambiguous));
+///
+/// assert_eq!(detect(b"@16\nM=0"), Err(ErrKind::Unrecognized));
+/// assert_eq!(detect(b"0010\nea88\nzz\n"), Err(ErrKind::Unrecognized));
+/// ```
+pub fn detect(buf: Buf) -> Result<Encoding, Err> {
+  let decodes = |msb: usize| {
+    buf.len() % 2 == 0
+      && buf.chunks_exact(2).all(|word| {
+        let word = (u16::from(word[msb]) << 8) | u16::from(word[1 - msb]);
+        Cmd::try_from(word).is_ok()
+      })
+  };
+
+  let text = if buf.iter().all(|&b| b.is_ascii_graphic() || b.is_ascii_whitespace()) {
+    match detect_text(buf) {
+      Ok(encoding) => return Ok(encoding),
+      Err(e) => Some(e),
+    }
+  } else {
+    None
+  };
+
+  match (decodes(0), decodes(1), text) {
+    (true, true, _) => {
+      Err(Err::new(buf, 0, ErrKind::Ambiguous(Encoding::Bin, Encoding::BinLe)))
+    }
+    (false, true, _) => Ok(Encoding::BinLe),
+    (true, false, _) | (false, false, None) => Ok(Encoding::Bin),
+    (false, false, Some(e)) => Err(e),
+  }
+}
+
+/// Detect the encoding of a compiled HACK program in text form, and
+/// check that all of it decodes in that encoding.
+///
+/// # Arguments
+///
+/// * `buf` - The input buffer.
+fn detect_text(buf: Buf) -> Result<Encoding, Err> {
+  let mut parser: Parser<ReadmemhParser> = Parser::from(buf);
+  skip_blank(&mut parser, b"//");
+  let (word, _) = parser::read_until_ws(parser.buf);
+
+  let (encoding, decoded) = if parser.buf.starts_with(b"v2.0 raw") {
+    (Encoding::Logisim, decodes_all::<LogisimParser>(buf))
+  } else if parser.buf.starts_with(b":") {
+    (Encoding::Ihex, decodes_all::<IhexParser>(buf))
+  } else if word.is_empty()
+    || word.len() == 16 && word.iter().all(|&b| b == b'0' || b == b'1')
+  {
+    (Encoding::Bintext, decodes_all::<BinTextParser>(buf))
+  } else if word.len() <= 4 && word.iter().all(u8::is_ascii_hexdigit) {
+    (Encoding::Readmemh, decodes_all::<ReadmemhParser>(buf))
+  } else {
+    return Err(Err::new(buf, parser.index, ErrKind::Unrecognized));
+  };
+
+  decoded.map(|()| encoding)
+}
+
+/// Check that all of `buf` decodes to instructions with a parser of
+/// type `T`.
+///
+/// Fails with an `ErrKind::Unrecognized` error at the first token
+/// that does not decode.
+///
+/// # Arguments
+///
+/// * `buf` - The input buffer.
+fn decodes_all<'b, T>(buf: Buf<'b>) -> Result<(), Err>
+where
+  T: Impl<Item = Result<Token, Err>>,
+{
+  for token in Parser::<'b, T>::from(buf) {
+    match token {
+      Ok(token) if Cmd::try_from(token.value()).is_ok() => {}
+      Ok(token) => return Err(Err::new(buf, token.index(), ErrKind::Unrecognized)),
+      Err(e) => return Err(Err { loc: e.loc(), kind: ErrKind::Unrecognized }),
+    }
+  }
+
+  Ok(())
+}
+
 /// Parser state for parsing compiled HACK programs.
 ///
 /// # impl `From<Buf>`
@@ -121,8 +586,11 @@ impl Impl for BinTextParser {
 ///
 /// There are two ways to parse HACK programs: from binary files and
 /// from the textual representation of such binary files. Use
-/// [`Parser<BinaryImpl>`] to create a parser for binary HACK files and
-/// [`Parser<TextImpl>`] to create a parser for text HACK files.
+/// [`Parser<BinParser>`] to create a parser for binary HACK files and
+/// [`Parser<BinTextParser>`] to create a parser for text HACK files.
+/// Other formats are parsed with [`Parser<BinLeParser>`],
+/// [`Parser<IhexParser>`], [`Parser<LogisimParser>`],
+/// [`Parser<ReadmemhParser>`] and [`Parser<ReadmembParser>`].
 ///
 /// ## Examples
 ///
@@ -144,6 +612,13 @@ impl Impl for BinTextParser {
 ///
 /// assert_eq!(parser.next(), Some(Ok(Token::new(0, 0))));
 /// assert_eq!(parser.next(), Some(Ok(Token::new(17, 59346))));
+///
+/// // Parse a Logisim memory image of the same program
+/// let prog = "v2.0 raw\n0 e7d2".as_bytes();
+/// let mut parser: Parser<dec::LogisimParser> = Parser::from(prog);
+///
+/// assert_eq!(parser.next(), Some(Ok(Token::new(9, 0))));
+/// assert_eq!(parser.next(), Some(Ok(Token::new(11, 59346))));
 /// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Parser<'b, T: Impl> {
@@ -156,6 +631,21 @@ pub struct Parser<'b, T: Impl> {
   /// The current byte into the input buffer.
   index: usize,
 
+  /// Number of instructions left in the current record or run, for
+  /// formats that group instructions.
+  pending: usize,
+
+  /// Number of instructions parsed so far.
+  words: usize,
+
+  /// Whether the header of the input has been read, for formats with
+  /// a header.
+  header: bool,
+
+  /// Whether the end of the input has been reached, for formats with
+  /// an end marker.
+  end: bool,
+
   /// Phantom for keeping `Parser` generic over different decoder
   /// implementations.
   phantom: PhantomData<T>,
@@ -170,7 +660,16 @@ impl<'b, T: Impl> Parser<'b, T> {
 
 impl<'b, T: Impl> From<Buf<'b>> for Parser<'b, T> {
   fn from(buf: Buf<'b>) -> Self {
-    Self { buf, orig: buf, index: 0, phantom: PhantomData }
+    Self {
+      buf,
+      orig: buf,
+      index: 0,
+      pending: 0,
+      words: 0,
+      header: false,
+      end: false,
+      phantom: PhantomData,
+    }
   }
 }
 
@@ -213,6 +712,38 @@ pub enum ErrKind {
   /// Expected another byte to form an instruction.
   #[display(fmt = "Expected another byte")]
   Expected,
+
+  /// Invalid digit in a number.
+  #[display(fmt = "Invalid digit")]
+  Digit,
+
+  /// Number does not fit in 16 bits.
+  #[display(fmt = "Number does not fit in 16 bits")]
+  Range,
+
+  /// Missing `v2.0 raw` header of a Logisim memory image.
+  #[display(fmt = "Expected a `v2.0 raw` header")]
+  Header,
+
+  /// Malformed Intel HEX record.
+  #[display(fmt = "Invalid record")]
+  Record,
+
+  /// Intel HEX record checksum mismatch.
+  #[display(fmt = "Checksum mismatch")]
+  Checksum,
+
+  /// Intel HEX record at an unexpected address.
+  #[display(fmt = "Unexpected address")]
+  Address,
+
+  /// Input that is not in any known encoding.
+  #[display(fmt = "Unrecognized encoding")]
+  Unrecognized,
+
+  /// Input that could be in either of two encodings.
+  #[display(fmt = "Ambiguous encoding, could be {} or {}", _0, _1)]
+  Ambiguous(Encoding, Encoding),
 }
 
 /// Error during parsing.
@@ -261,7 +792,207 @@ impl<'b, T: Impl<Item = Result<Token, Err>>> Iterator for Parser<'b, T> {
   type Item = Result<Token, Err>;
 
   fn next(&mut self) -> Option<Self::Item> {
-    T::next(self)
+    let res = T::next(self);
+
+    if let Some(Ok(_)) = res {
+      self.words += 1;
+    }
+
+    res
+  }
+}
+
+/// Streaming decoder of compiled HACK programs.
+///
+/// A reader decodes the instructions of a program as they are read
+/// from its input (e.g. the standard input), a line at a time for text
+/// encodings and an instruction at a time for binary ones, instead of
+/// reading all of the input first. The locations of errors refer to
+/// the whole input.
+///
+/// A reader stops after the first error.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::dec::Encoding;
+/// use has::hack::dec::Reader;
+/// use has::Loc;
+///
+/// let input = "0000000000010000\n1110101010001000\n".as_bytes();
+/// let cmds = Reader::new(input, Encoding::Bintext).map(|cmd| cmd.unwrap().to_string());
+/// assert_eq!(cmds.collect::<Vec<_>>(), ["@16", "M=0"]);
+///
+/// let input = [0x10, 0x00, 0x88, 0xEA];
+/// let cmds = Reader::new(&input[..], Encoding::BinLe).map(|cmd| cmd.unwrap().to_string());
+/// assert_eq!(cmds.collect::<Vec<_>>(), ["@16", "M=0"]);
+///
+/// let mut reader = Reader::new("v2.0 raw\n10\n1 zz\n".as_bytes(), Encoding::Logisim);
+/// assert_eq!(reader.next().unwrap().unwrap().to_string(), "@16");
+/// assert_eq!(reader.next().unwrap().unwrap().to_string(), "@1");
+/// assert_eq!(reader.next().unwrap().unwrap_err().loc(), Some(Loc::new(3, 3)));
+/// assert!(reader.next().is_none());
+/// ```
+pub struct Reader<R: io::BufRead> {
+  /// The input.
+  reader: R,
+
+  /// The encoding of the input.
+  encoding: Encoding,
+
+  /// The location of the start of the next chunk of input.
+  loc: Loc,
+
+  /// Number of instructions parsed so far.
+  words: usize,
+
+  /// Whether the header of the input has been read.
+  header: bool,
+
+  /// Whether the end of the input has been reached, or decoding
+  /// failed.
+  end: bool,
+
+  /// Instructions decoded from the current chunk of input that have
+  /// not been returned yet.
+  cmds: VecDeque<Result<Cmd<'static>, ReadErr>>,
+}
+
+impl<R: io::BufRead> Reader<R> {
+  /// Create a reader that decodes a program from `reader`.
+  ///
+  /// # Arguments
+  ///
+  /// * `reader` - The input.
+  ///
+  /// * `encoding` - The encoding of the input.
+  pub fn new(reader: R, encoding: Encoding) -> Self {
+    Self {
+      reader,
+      encoding,
+      loc: Loc::default(),
+      words: 0,
+      header: false,
+      end: false,
+      cmds: VecDeque::new(),
+    }
+  }
+
+  /// Decode a chunk of input.
+  fn decode(&mut self, chunk: Buf) {
+    match self.encoding {
+      Encoding::Bin => self.decode_with::<BinParser>(chunk),
+      Encoding::BinLe => self.decode_with::<BinLeParser>(chunk),
+      Encoding::Bintext => self.decode_with::<BinTextParser>(chunk),
+      Encoding::Ihex => self.decode_with::<IhexParser>(chunk),
+      Encoding::Logisim => self.decode_with::<LogisimParser>(chunk),
+      Encoding::Readmemh => self.decode_with::<ReadmemhParser>(chunk),
+      Encoding::Readmemb => self.decode_with::<ReadmembParser>(chunk),
+    }
+
+    for &b in chunk {
+      self.loc.inc(b);
+    }
+  }
+
+  /// Decode a chunk of input with a parser of type `T`, continuing
+  /// from the state of the parser of the previous chunk.
+  fn decode_with<T: Impl<Item = Res>>(&mut self, chunk: Buf) {
+    let mut parser: Parser<T> = Parser::from(chunk);
+    parser.words = self.words;
+    parser.header = self.header;
+
+    for token in &mut parser {
+      let cmd = match token {
+        Ok(token) => Cmd::try_from(token.value()).map_err(|e| {
+          let loc = self.locate(Loc::from_index(chunk, token.index()));
+          ReadErr::Decode(CmdErr::InvalidInst(loc, e))
+        }),
+        Err(e) => Err(ReadErr::Dis(Err { loc: self.locate(e.loc()), kind: e.kind() })),
+      };
+
+      let failed = cmd.is_err();
+      self.cmds.push_back(cmd);
+
+      if failed {
+        self.end = true;
+        break;
+      }
+    }
+
+    self.words = parser.words;
+    self.header = parser.header;
+    self.end |= parser.end;
+  }
+
+  /// Returns the location in the whole input of a location `loc` in
+  /// the current chunk.
+  fn locate(&self, loc: Loc) -> Loc {
+    if loc.line() == 1 {
+      Loc::new(self.loc.line(), self.loc.col() + loc.col() - 1)
+    } else {
+      Loc::new(self.loc.line() + loc.line() - 1, loc.col())
+    }
+  }
+}
+
+impl<R: io::BufRead> Iterator for Reader<R> {
+  type Item = Result<Cmd<'static>, ReadErr>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.cmds.is_empty() {
+      if self.end {
+        return None;
+      }
+
+      let mut chunk = Vec::new();
+      let read = match self.encoding {
+        Encoding::Bin | Encoding::BinLe => {
+          (&mut self.reader).take(2).read_to_end(&mut chunk)
+        }
+        _ => self.reader.read_until(b'\n', &mut chunk),
+      };
+
+      match read {
+        Ok(0) => return None,
+        Ok(_) => self.decode(&chunk),
+        Err(e) => {
+          self.end = true;
+          return Some(Err(ReadErr::Io(e)));
+        }
+      }
+    }
+
+    self.cmds.pop_front()
+  }
+}
+
+/// Error while [reading](Reader) a compiled HACK program.
+#[derive(Display, Debug, From)]
+pub enum ReadErr {
+  /// Error reading the input.
+  #[display(fmt = "IO error: {}", _0)]
+  Io(io::Error),
+
+  /// Error parsing the input.
+  #[display(fmt = "{}", _0)]
+  Dis(Err),
+
+  /// Error decoding an instruction.
+  #[display(fmt = "{}", _0)]
+  Decode(CmdErr),
+}
+
+impl ReadErr {
+  /// Returns the [location](Loc) in the input at which the error
+  /// occurred, if it is not an IO error.
+  pub fn loc(&self) -> Option<Loc> {
+    match self {
+      ReadErr::Io(_) => None,
+      ReadErr::Dis(e) => Some(e.loc()),
+      ReadErr::Decode(CmdErr::InvalidInst(loc, _))
+      | ReadErr::Decode(CmdErr::InvalidAddr(loc, _)) => Some(*loc),
+    }
   }
 }
 
@@ -378,6 +1109,13 @@ mod tests_bintext {
 
     assert_eq!(p.next(), None);
   }
+
+  #[test]
+  fn incomplete() {
+    let mut p: Parser<super::BinTextParser> = Parser::from(&b"0000000000000000\n111"[..]);
+    assert!(p.next().unwrap().is_ok());
+    assert_eq!(p.next().unwrap().unwrap_err().loc(), Loc::new(2, 4));
+  }
 }
 
 #[cfg(test)]
@@ -493,4 +1231,141 @@ mod tests_bin {
 
     assert_eq!(p.next(), None);
   }
+
+  #[test]
+  fn incomplete() {
+    let mut p: Parser<super::BinParser> = Parser::from(&[0, 0, 0][..]);
+    assert!(p.next().unwrap().is_ok());
+    assert_eq!(p.next().unwrap().unwrap_err().loc(), Loc::new(1, 4));
+  }
+}
+
+#[cfg(test)]
+mod tests_formats {
+  use super::ErrKind;
+  use super::Impl;
+  use super::Parser;
+  use crate::Loc;
+
+  fn values<T: Impl<Item = super::Res>>(buf: &[u8]) -> Result<Vec<u16>, (Loc, ErrKind)> {
+    let parser: Parser<T> = Parser::from(buf);
+    parser.map(|res| res.map(|t| t.value()).map_err(|e| (e.loc(), e.kind()))).collect()
+  }
+
+  #[test]
+  fn bin_le() {
+    assert_eq!(
+      values::<super::BinLeParser>(&[0x10, 0, 0x88, 0xEA]),
+      Ok(vec![16, 0xEA88])
+    );
+    assert_eq!(
+      values::<super::BinLeParser>(&[0x10, 0, 0x88]),
+      Err((Loc::new(1, 4), ErrKind::Expected))
+    );
+  }
+
+  #[test]
+  fn ihex() {
+    let buf =
+      b":020000040000FA\n:040000000010EA887A\r\n:020004000002F8\n:00000001FF\njunk";
+    assert_eq!(values::<super::IhexParser>(buf), Ok(vec![16, 0xEA88, 2]));
+    assert_eq!(values::<super::IhexParser>(b""), Ok(vec![]));
+
+    let err = |buf: &[u8]| values::<super::IhexParser>(buf).unwrap_err();
+    assert_eq!(err(b"\n040000000010EA887A"), (Loc::new(2, 1), ErrKind::Record));
+    assert_eq!(err(b":0400000000X0EA887A"), (Loc::new(1, 12), ErrKind::Record));
+    assert_eq!(err(b":040000000010EA88"), (Loc::new(1, 2), ErrKind::Record));
+    assert_eq!(err(b":040000000010EA887B"), (Loc::new(1, 18), ErrKind::Checksum));
+    assert_eq!(err(b":0100000000FF"), (Loc::new(1, 2), ErrKind::Record));
+    assert_eq!(err(b":020002000002FA"), (Loc::new(1, 4), ErrKind::Address));
+    assert_eq!(err(b":020000040001F9"), (Loc::new(1, 10), ErrKind::Address));
+    assert_eq!(err(b":00000006FA"), (Loc::new(1, 8), ErrKind::Record));
+  }
+
+  #[test]
+  fn logisim() {
+    let buf = b"v2.0 raw\n# Comment\n3*0 ea88 # End\n0*5 10\n";
+    assert_eq!(values::<super::LogisimParser>(buf), Ok(vec![0, 0, 0, 0xEA88, 16]));
+    assert_eq!(values::<super::LogisimParser>(b"v2.0 raw"), Ok(vec![]));
+
+    let err = |buf: &[u8]| values::<super::LogisimParser>(buf).unwrap_err();
+    assert_eq!(err(b"0 ea88"), (Loc::new(1, 1), ErrKind::Header));
+    assert_eq!(err(b"v2.0 raw\n0 eg88"), (Loc::new(2, 4), ErrKind::Digit));
+    assert_eq!(err(b"v2.0 raw\nx*0"), (Loc::new(2, 1), ErrKind::Digit));
+    assert_eq!(err(b"v2.0 raw\n2*10000"), (Loc::new(2, 3), ErrKind::Range));
+  }
+
+  #[test]
+  fn readmemh() {
+    let buf = b"// Program\n0010 // @16\nEA88\n\n2";
+    assert_eq!(values::<super::ReadmemhParser>(buf), Ok(vec![16, 0xEA88, 2]));
+
+    let err = |buf: &[u8]| values::<super::ReadmemhParser>(buf).unwrap_err();
+    assert_eq!(err(b"0010\n@10"), (Loc::new(2, 1), ErrKind::Digit));
+    assert_eq!(err(b"0010\n10000"), (Loc::new(2, 1), ErrKind::Range));
+  }
+
+  #[test]
+  fn readmemb() {
+    let buf = b"1_0000 // @16\n1110_1010_1000_1000\n";
+    assert_eq!(values::<super::ReadmembParser>(buf), Ok(vec![16, 0xEA88]));
+
+    let err = |buf: &[u8]| values::<super::ReadmembParser>(buf).unwrap_err();
+    assert_eq!(err(b"0012"), (Loc::new(1, 4), ErrKind::Digit));
+    assert_eq!(err(b"11110000111100001"), (Loc::new(1, 1), ErrKind::Range));
+  }
+
+  #[test]
+  fn reader() {
+    use super::Encoding;
+    use super::ReadErr;
+    use super::Reader;
+
+    let read = |buf: &[u8], encoding| {
+      let cmds = Reader::new(buf, encoding).map(|cmd| cmd.map(|cmd| cmd.to_string()));
+      cmds.collect::<Result<Vec<_>, _>>()
+    };
+    let err = |buf: &[u8], encoding| match read(buf, encoding).unwrap_err() {
+      ReadErr::Dis(e) => (e.loc(), e.kind()),
+      e => panic!("{}", e),
+    };
+
+    let cmds = ["@16", "M=0", "@2"];
+    assert_eq!(read(b"\x00\x10\xEA\x88\x00\x02", Encoding::Bin).unwrap(), cmds);
+    assert_eq!(read(b"\x10\x00\x88\xEA\x02\x00", Encoding::BinLe).unwrap(), cmds);
+    let buf = b"0000000000010000\n1110101010001000\n0000000000000010";
+    assert_eq!(read(buf, Encoding::Bintext).unwrap(), cmds);
+    let buf =
+      b":020000040000FA\n:040000000010EA887A\r\n:020004000002F8\n:00000001FF\njunk";
+    assert_eq!(read(buf, Encoding::Ihex).unwrap(), cmds);
+    let buf = b"v2.0 raw\n# Comment\n10 ea88 # End\n0*5 2\n";
+    assert_eq!(read(buf, Encoding::Logisim).unwrap(), cmds);
+    assert_eq!(
+      read(b"// Program\n0010 // @16\nEA88\n\n2", Encoding::Readmemh).unwrap(),
+      cmds
+    );
+    let buf = b"1_0000 // @16\n1110_1010_1000_1000\n10";
+    assert_eq!(read(buf, Encoding::Readmemb).unwrap(), cmds);
+
+    assert_eq!(err(b"\x00\x10\xEA", Encoding::Bin), (Loc::new(1, 4), ErrKind::Expected));
+    let buf = b"0000000000010000\n000000000001000x";
+    assert_eq!(err(buf, Encoding::Bintext), (Loc::new(2, 16), ErrKind::Expected));
+    let buf = b":040000000010EA887A\n:020004000002F8\n:040000000010EA887B";
+    assert_eq!(err(buf, Encoding::Ihex), (Loc::new(3, 18), ErrKind::Checksum));
+    let buf = b":040000000010EA887A\n:020006000002F6";
+    assert_eq!(err(buf, Encoding::Ihex), (Loc::new(2, 4), ErrKind::Address));
+    assert_eq!(
+      err(b"v2.0 raw\n10\n0 eg88", Encoding::Logisim),
+      (Loc::new(3, 4), ErrKind::Digit)
+    );
+    assert_eq!(err(b"10\n0 ea88", Encoding::Logisim), (Loc::new(1, 1), ErrKind::Header));
+    assert_eq!(err(b"0010\n\n@10", Encoding::Readmemh), (Loc::new(3, 1), ErrKind::Digit));
+
+    let err = read(b"0010\nFFC0\n0010", Encoding::Readmemh).unwrap_err();
+    assert_eq!(err.loc(), Some(Loc::new(2, 1)));
+
+    let mut reader = Reader::new(&b"0010\nzz\n0010"[..], Encoding::Readmemh);
+    assert_eq!(reader.by_ref().count(), 2);
+    assert!(reader.next().is_none());
+  }
 }
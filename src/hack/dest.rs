@@ -132,7 +132,8 @@ impl TryFrom<u16> for Dest {
 /// Parse a [Dest] object from a (byte buffer)(Buf).
 ///
 /// An unrecognized input produces an `Err(())`. Note that
-/// [Dest::Null] is inconstructible this way.
+/// [Dest::Null] is inconstructible this way. Registers may be given
+/// in any order (e.g. `DM` for [Dest::MD]).
 ///
 /// # Examples
 ///
@@ -147,6 +148,10 @@ impl TryFrom<u16> for Dest {
 /// assert_eq!(Dest::try_from("AM".as_bytes()), Ok(Dest::AM));
 /// assert_eq!(Dest::try_from("AD".as_bytes()), Ok(Dest::AD));
 /// assert_eq!(Dest::try_from("AMD".as_bytes()), Ok(Dest::AMD));
+///
+/// assert_eq!(Dest::try_from("DM".as_bytes()), Ok(Dest::MD));
+/// assert_eq!(Dest::try_from("ADM".as_bytes()), Ok(Dest::AMD));
+/// assert_eq!(Dest::try_from("MM".as_bytes()), Err(()));
 /// ```
 impl TryFrom<Buf<'_>> for Dest {
   type Error = ();
@@ -160,6 +165,10 @@ impl TryFrom<Buf<'_>> for Dest {
       b"AM" => Ok(Dest::AM),
       b"AD" => Ok(Dest::AD),
       b"AMD" => Ok(Dest::AMD),
+      b"DM" => Ok(Dest::MD),
+      b"MA" => Ok(Dest::AM),
+      b"DA" => Ok(Dest::AD),
+      b"ADM" | b"MAD" | b"MDA" | b"DAM" | b"DMA" => Ok(Dest::AMD),
       _ => Err(()),
     }
   }
@@ -288,4 +297,49 @@ impl Dest {
   pub fn is_null(&self) -> bool {
     matches!(self, Dest::Null)
   }
+
+  /// Whether the [destination](Dest) object includes the Address
+  /// register.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Dest;
+  ///
+  /// assert!(Dest::AMD.has_a());
+  /// assert!(!Dest::MD.has_a());
+  /// ```
+  pub fn has_a(&self) -> bool {
+    u16::from(*self) & 0b100 != 0
+  }
+
+  /// Whether the [destination](Dest) object includes the Data
+  /// register.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Dest;
+  ///
+  /// assert!(Dest::AD.has_d());
+  /// assert!(!Dest::AM.has_d());
+  /// ```
+  pub fn has_d(&self) -> bool {
+    u16::from(*self) & 0b010 != 0
+  }
+
+  /// Whether the [destination](Dest) object includes the Memory
+  /// register.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Dest;
+  ///
+  /// assert!(Dest::MD.has_m());
+  /// assert!(!Dest::AD.has_m());
+  /// ```
+  pub fn has_m(&self) -> bool {
+    u16::from(*self) & 0b001 != 0
+  }
 }
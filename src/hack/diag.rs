@@ -0,0 +1,1064 @@
+//! Human-readable diagnostics for errors in HACK programs.
+//!
+//! [Diagnostic] describes an [error](ProgErr) with an error code, a
+//! message, the offending line of the input with the problematic span
+//! underlined and, where possible, a hint on how to fix the problem:
+//!
+//! ```text
+//! error[E0005]: unknown jump `JPM`
+//!  --> Max.asm:6:3
+//!   |
+//! 6 | D;JPM
+//!   |   ^^^
+//!   |
+//!   = help: did you mean `JMP`?
+//! ```
+
+use crate::hack::dec::ErrKind as DecErrKind;
+use crate::hack::macros::PSEUDOS;
+use crate::hack::parser::ErrKind;
+use crate::hack::AddrErr;
+use crate::hack::Anon;
+use crate::hack::CmdErr;
+use crate::hack::Comp;
+use crate::hack::Dest;
+use crate::hack::DirectiveErr;
+use crate::hack::ExprErr;
+use crate::hack::InstDecodeErr;
+use crate::hack::InstErr;
+use crate::hack::Jump;
+use crate::hack::Label;
+use crate::hack::LabelErr;
+use crate::hack::MacroErr;
+use crate::hack::ParserErr;
+use crate::hack::ProgErr;
+use crate::Buf;
+use crate::Loc;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// Expected a second `/` to form a comment.
+pub const E_COMMENT: &str = "E0001";
+
+/// Invalid label declaration.
+pub const E_LABEL: &str = "E0002";
+
+/// Missing closing parenthesis of a label declaration.
+pub const E_PAREN: &str = "E0003";
+
+/// Invalid A-instruction.
+pub const E_ADDR: &str = "E0004";
+
+/// Invalid C-instruction.
+pub const E_INST: &str = "E0005";
+
+/// Duplicate label declaration.
+pub const E_DUPLICATE: &str = "E0006";
+
+/// Undefined label.
+pub const E_UNDEFINED: &str = "E0007";
+
+/// Invalid symbol table entry.
+pub const E_SYMBOL: &str = "E0008";
+
+/// Incomplete instruction in a binary or bintext file.
+pub const E_INCOMPLETE: &str = "E0009";
+
+/// Instruction that cannot be decoded from a binary, bintext or memory
+/// image file.
+pub const E_DECODE: &str = "E0010";
+
+/// Invalid macro definition or call.
+pub const E_MACRO: &str = "E0011";
+
+/// Invalid directive.
+pub const E_DIRECTIVE: &str = "E0012";
+
+/// Included file that is not part of the program.
+pub const E_INCLUDE: &str = "E0013";
+
+/// Constant expression that cannot be evaluated.
+pub const E_EXPR: &str = "E0014";
+
+/// Variable or data that does not fit in the data memory.
+pub const E_MEMORY: &str = "E0015";
+
+/// Compiled program whose encoding cannot be detected.
+pub const E_ENCODING: &str = "E0016";
+
+/// Predefined symbols, as listed in hints.
+const SYMS: &str =
+  "`SP`, `LCL`, `ARG`, `THIS`, `THAT`, `R0` to `R15`, `SCREEN` and `KBD`";
+
+/// A diagnostic for an error in a HACK program.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::Diagnostic;
+/// use has::hack::Prog;
+///
+/// let buf = "@2\nD;JPM // Jump\n".as_bytes();
+/// let err = Prog::from_source(buf).err().unwrap();
+/// let diag = Diagnostic::new(buf, &err);
+///
+/// assert_eq!(diag.code(), "E0005");
+/// assert_eq!(diag.message(), "unknown jump `JPM`");
+/// assert_eq!(diag.hint(), Some("did you mean `JMP`?"));
+/// assert_eq!(
+///   diag.render("Max.asm"),
+///   "error[E0005]: unknown jump `JPM`\n \
+///     --> Max.asm:2:3\n  \
+///      |\n\
+///    2 | D;JPM // Jump\n  \
+///      |   ^^^\n  \
+///      |\n  \
+///      = help: did you mean `JMP`?\n"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  /// The error code.
+  code: &'static str,
+
+  /// The error message.
+  message: String,
+
+  /// The start of the problematic span, if known.
+  loc: Option<Loc>,
+
+  /// The length of the problematic span in bytes.
+  len: usize,
+
+  /// The line of the input containing the problematic span.
+  line: Option<String>,
+
+  /// A hint on how to fix the problem.
+  hint: Option<String>,
+
+  /// Whether the problem is a warning rather than an error.
+  warning: bool,
+}
+
+impl Diagnostic {
+  /// Create a diagnostic for an error in a HACK program.
+  ///
+  /// # Arguments
+  ///
+  /// * `buf` - The input buffer the program was parsed from.
+  ///
+  /// * `err` - The error.
+  pub fn new(buf: Buf, err: &ProgErr) -> Self {
+    let (code, message, loc, len, hint) = match err {
+      ProgErr::Asm(err) => return Self::from_parser_err(buf, err),
+      ProgErr::Dis(err) => {
+        let (code, message, hint) = match err.kind() {
+          DecErrKind::Expected => (
+            E_INCOMPLETE,
+            String::from("incomplete instruction"),
+            Some("binary files contain 2 bytes per instruction and bintext files 16 bits per line"),
+          ),
+          DecErrKind::Digit => (E_DECODE, String::from("invalid digit"), None),
+          DecErrKind::Range => (E_DECODE, String::from("number does not fit in 16 bits"), None),
+          DecErrKind::Header => (
+            E_DECODE,
+            String::from("missing memory image header"),
+            Some("Logisim memory images start with `v2.0 raw`"),
+          ),
+          DecErrKind::Record => (
+            E_DECODE,
+            String::from("invalid record"),
+            Some("Intel HEX records have the form `:LLAAAATT[DD...]CC`"),
+          ),
+          DecErrKind::Checksum => (E_DECODE, String::from("record checksum mismatch"), None),
+          DecErrKind::Address => (
+            E_DECODE,
+            String::from("unexpected record address"),
+            Some("data records must be contiguous, starting at address 0"),
+          ),
+          DecErrKind::Unrecognized => (
+            E_ENCODING,
+            String::from("unrecognized encoding"),
+            Some("specify the format of the file explicitly"),
+          ),
+          DecErrKind::Ambiguous(a, b) => (
+            E_ENCODING,
+            format!("ambiguous encoding, could be `{}` or `{}`", a, b),
+            Some("specify the format of the file explicitly"),
+          ),
+        };
+
+        (code, message, Some(err.loc()), 1, hint.map(String::from))
+      }
+      ProgErr::DuplicateLabel(name, loc) => (
+        E_DUPLICATE,
+        format!("label `{}` is declared more than once", name),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from("rename or remove this declaration")),
+      ),
+      ProgErr::InvalidSymbol(loc) => (
+        E_SYMBOL,
+        String::from("invalid symbol table entry"),
+        Some(*loc),
+        line_at(buf, *loc).map_or(1, |line| line.trim_end().len()),
+        Some(String::from("entries are `label NAME ADDRESS` or `var NAME ADDRESS`")),
+      ),
+      ProgErr::UndefinedLabel(name, loc) => {
+        let hint = match Label::anonymous(name.as_bytes()).and_then(|l| l.anon()) {
+          Some(Anon::Forward(n)) => format!("declare `({})` after this instruction", n),
+          Some(Anon::Backward(n)) => format!("declare `({})` before this instruction", n),
+          _ => format!("declare the label with `({})` or fix its spelling", name),
+        };
+
+        (
+          E_UNDEFINED,
+          format!("label `{}` is used as a jump target but never declared", name),
+          Some(*loc),
+          token_len(buf, *loc),
+          Some(hint),
+        )
+      }
+      ProgErr::LabelNotFound(name) => {
+        (E_UNDEFINED, format!("label or variable `{}` not found", name), None, 0, None)
+      }
+      ProgErr::Decode(CmdErr::InvalidInst(loc, err)) => {
+        let message = match err {
+          InstDecodeErr::InvalidComp(v) => {
+            format!("`{:#09b}` is not a valid computation", v)
+          }
+          InstDecodeErr::InvalidDest(v) => {
+            format!("`{:#05b}` is not a valid destination", v)
+          }
+          InstDecodeErr::InvalidJump(v) => format!("`{:#05b}` is not a valid jump", v),
+        };
+
+        (E_DECODE, message, Some(*loc), 1, None)
+      }
+      ProgErr::Decode(CmdErr::InvalidAddr(loc, _)) => {
+        (E_DECODE, String::from("invalid address instruction"), Some(*loc), 1, None)
+      }
+      ProgErr::Macro(err, loc) => {
+        let hint = match err {
+          MacroErr::Unknown(name) => {
+            suggest(name, PSEUDOS.iter().map(|p| p.to_string()), true).or_else(|| {
+              Some(String::from("define the macro with `.macro` before calling it"))
+            })
+          }
+          MacroErr::Recursive(_) => Some(String::from("macros cannot call themselves")),
+          _ => None,
+        };
+
+        (E_MACRO, err.to_string(), Some(*loc), token_len(buf, *loc), hint)
+      }
+      ProgErr::DuplicateMacro(name, loc) => (
+        E_MACRO,
+        if PSEUDOS.contains(&name.as_str()) {
+          format!("macro `{}` redefines a pseudo-instruction", name)
+        } else {
+          format!("macro `{}` is defined more than once", name)
+        },
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from("rename or remove this definition")),
+      ),
+      ProgErr::UnclosedMacro(name, loc) => (
+        E_MACRO,
+        format!("macro `{}` is missing its `.endm`", name),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from("add `.endm` after the body of the macro")),
+      ),
+      ProgErr::NestedMacro(loc) => (
+        E_MACRO,
+        String::from("macro definitions cannot be nested"),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from("add `.endm` to close the previous definition")),
+      ),
+      ProgErr::UnexpectedEndMacro(loc) => (
+        E_MACRO,
+        String::from("`.endm` outside of a macro definition"),
+        Some(*loc),
+        token_len(buf, *loc),
+        None,
+      ),
+      ProgErr::DirectiveInMacro(name, loc) => (
+        E_MACRO,
+        format!("`.{}` cannot be used in a macro definition", name),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(format!("move the `.{}` out of the macro", name)),
+      ),
+      ProgErr::DuplicateConst(name, loc) => (
+        E_DUPLICATE,
+        format!("constant `{}` is defined more than once", name),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from("rename or remove this definition")),
+      ),
+      ProgErr::Eval(err, loc) => {
+        let hint = match err {
+          AddrErr::Range(_) => {
+            Some(String::from("addresses are numbers from 0 to 32767"))
+          }
+          AddrErr::Expr(ExprErr::Undefined(_)) => Some(String::from(
+            "define the constant with `.equ` before using it in a constant, \
+             or declare the label or variable",
+          )),
+          _ => None,
+        };
+
+        let message = match err {
+          AddrErr::Range(value) => format!("value `{}` is out of range", value),
+          AddrErr::Expr(err) => err.to_string(),
+          _ => err.to_string(),
+        };
+
+        (E_EXPR, message, Some(*loc), operand_len(buf, *loc), hint)
+      }
+      ProgErr::MissingInclude(path, loc) => (
+        E_INCLUDE,
+        format!("included file `{}` is not part of the program", path),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from("pass the file to the assembler or fix its path")),
+      ),
+      ProgErr::LabelInMacro(name, loc) => (
+        E_MACRO,
+        format!("label `{}` is declared in a macro", name),
+        Some(*loc),
+        name.len() + 2,
+        Some(String::from("labels cannot be declared in macros, move it out")),
+      ),
+      ProgErr::DuplicateVar(name, loc) => (
+        E_DUPLICATE,
+        format!("variable `{}` is declared more than once", name),
+        Some(*loc),
+        operand_len(buf, *loc),
+        Some(String::from("rename or remove this declaration")),
+      ),
+      ProgErr::RamOverflow(name, loc) => (
+        E_MEMORY,
+        format!("variable `{}` overlaps the screen memory map", name),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from(
+          "variables are allocated from RAM address 16 up to 16383, \
+           use fewer or smaller variables or place them with `.var`",
+        )),
+      ),
+      ProgErr::DataWithoutVar(loc) => (
+        E_MEMORY,
+        String::from("`.data` does not follow a variable"),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from(
+          "declare the variable with `.var` or `.reserve` before its data",
+        )),
+      ),
+      ProgErr::EmptyArray(name, loc) => (
+        E_MEMORY,
+        format!("array `{}` has a size of 0", name),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from("arrays reserve at least one word")),
+      ),
+      ProgErr::RamOverlap(name, other, loc) => (
+        E_MEMORY,
+        format!("variable `{}` overlaps `{}`", name, other),
+        Some(*loc),
+        token_len(buf, *loc),
+        Some(String::from("place each variable and its data at different addresses")),
+      ),
+      ProgErr::DataOverlap(name, other, loc) => (
+        E_MEMORY,
+        format!("data of `{}` overlaps `{}`", name, other),
+        Some(*loc),
+        operand_len(buf, *loc),
+        Some(String::from("place each variable and its data at different addresses")),
+      ),
+      ProgErr::DataOverflow(name, loc) => (
+        E_MEMORY,
+        format!("data of `{}` exceeds its reserved space", name),
+        Some(*loc),
+        operand_len(buf, *loc),
+        Some(String::from(
+          "arrays hold as many values as their `.reserve` size, \
+           and data cannot extend past the screen memory map",
+        )),
+      ),
+    };
+
+    Self::with_line(buf, code, message, loc, len, hint)
+  }
+
+  /// Create a diagnostic for a parsing error.
+  fn from_parser_err(buf: Buf, err: &ParserErr) -> Self {
+    let loc = err.loc();
+    let text = line_at(buf, loc).map(|line| line[loc.col() - 1..].to_owned());
+    let text = text.unwrap_or_default();
+    let token = token(&text);
+
+    let (code, message, offset, len, hint) = match err.kind() {
+      ErrKind::ExpectedComment => (
+        E_COMMENT,
+        String::from("expected a second `/` to form a comment"),
+        0,
+        1,
+        Some(String::from("comments start with `//`")),
+      ),
+      ErrKind::InvalidLabel(LabelErr::InvalidByte(b)) if b.is_ascii_whitespace() => (
+        E_PAREN,
+        String::from("expected a closing `)` for label"),
+        0,
+        token.len(),
+        Some(String::from("add `)` after the label name")),
+      ),
+      ErrKind::InvalidLabel(err) => {
+        let len = text.find(')').map_or(token.len(), |i| i + 1);
+        let (message, hint) = label_err(err, &text[..len]);
+        (E_LABEL, message, 0, len, Some(hint))
+      }
+      ErrKind::MissingLParen => (
+        E_PAREN,
+        String::from("expected a closing `)` for label"),
+        0,
+        1,
+        Some(String::from("add `)` after the label name")),
+      ),
+      ErrKind::InvalidAddr(err) => {
+        let operand = token.get(1..).unwrap_or_default();
+        let range = "addresses are numbers from 0 to 32767, labels or predefined symbols";
+
+        let out_of_range = matches!(err, AddrErr::Range(_) | AddrErr::InvalidNum(_))
+          || operand.starts_with('-');
+
+        let (message, hint) = if let AddrErr::Expr(err) = err {
+          let hint = "expressions combine numbers, characters, symbols and names with \
+                      `+`, `-`, `*`, `/`, `%` and parentheses";
+          (err.to_string(), String::from(hint))
+        } else if out_of_range {
+          (format!("address `{}` is out of range", operand), String::from(range))
+        } else if operand.is_empty() {
+          (String::from("missing address after `@`"), String::from(range))
+        } else {
+          (
+            format!("invalid address `{}`", operand),
+            String::from("names start with a letter or one of `_`, `.`, `$` and `:`"),
+          )
+        };
+
+        (E_ADDR, message, 1, operand.len(), Some(hint))
+      }
+      ErrKind::InvalidInst(err) => {
+        let (message, offset, len, hint) = inst_err(err, token);
+        (E_INST, message, offset, len, hint)
+      }
+      ErrKind::InvalidDirective(err) => {
+        let hint = match err {
+          DirectiveErr::Unknown(_) => Some(String::from(
+            "the supported directives are `.macro`, `.endm`, `.include`, `.equ`, `.var`, \
+             `.reserve` and `.data`",
+          )),
+          _ => None,
+        };
+
+        (E_DIRECTIVE, err.to_string(), 0, token.len(), hint)
+      }
+      ErrKind::InvalidCall(err) => (E_MACRO, err.to_string(), 0, token.len(), None),
+    };
+
+    let loc = Loc::new(loc.line(), loc.col() + offset);
+    Self::with_line(buf, code, message, Some(loc), len, hint)
+  }
+
+  /// Create a diagnostic, looking up the line at `loc` in `buf`.
+  fn with_line(
+    buf: Buf,
+    code: &'static str,
+    message: String,
+    loc: Option<Loc>,
+    len: usize,
+    hint: Option<String>,
+  ) -> Self {
+    let line = loc.and_then(|loc| line_at(buf, loc));
+    Self { code, message, loc, len, line, hint, warning: false }
+  }
+
+  /// Turn the diagnostic into a warning, for problems that do not
+  /// prevent the program from being assembled (see
+  /// [Prog::warnings](crate::hack::Prog::warnings)).
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Diagnostic;
+  /// use has::hack::Prog;
+  ///
+  /// let buf = "@LOPO\n0;JMP".as_bytes();
+  /// let warning = &Prog::from_source(buf).unwrap().warnings()[0];
+  /// let diag = Diagnostic::new(buf, warning).into_warning();
+  /// assert_eq!(diag.severity(), "warning");
+  /// assert!(diag.render("Loop.asm").starts_with("warning[E0007]: label `LOPO`"));
+  /// ```
+  pub fn into_warning(self) -> Self {
+    Self { warning: true, ..self }
+  }
+
+  /// Returns the severity of the diagnostic, `error` or `warning`.
+  pub fn severity(&self) -> &'static str {
+    if self.warning {
+      "warning"
+    } else {
+      "error"
+    }
+  }
+
+  /// Returns the error code (e.g. `E0005`).
+  pub fn code(&self) -> &'static str {
+    self.code
+  }
+
+  /// Returns the kind of error as a stable `snake_case` name (e.g.
+  /// `invalid_instruction`) for machine-readable output.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Diagnostic;
+  /// use has::hack::Prog;
+  ///
+  /// let buf = "(A)\n(A)".as_bytes();
+  /// let err = Prog::from_source(buf).err().unwrap();
+  /// assert_eq!(Diagnostic::new(buf, &err).kind(), "duplicate_label");
+  /// ```
+  pub fn kind(&self) -> &'static str {
+    match self.code {
+      E_COMMENT => "expected_comment",
+      E_LABEL => "invalid_label",
+      E_PAREN => "missing_paren",
+      E_ADDR => "invalid_address",
+      E_INST => "invalid_instruction",
+      E_DUPLICATE => "duplicate_label",
+      E_UNDEFINED => "undefined_label",
+      E_SYMBOL => "invalid_symbol",
+      E_INCOMPLETE => "incomplete_instruction",
+      E_DECODE => "invalid_encoding",
+      E_MACRO => "macro_error",
+      E_DIRECTIVE => "invalid_directive",
+      E_INCLUDE => "missing_include",
+      E_EXPR => "invalid_expression",
+      E_MEMORY => "memory_layout",
+      E_ENCODING => "unknown_encoding",
+      _ => "error",
+    }
+  }
+
+  /// Returns the error message.
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+
+  /// Returns the start of the problematic span, if known.
+  pub fn loc(&self) -> Option<Loc> {
+    self.loc
+  }
+
+  /// Returns the length of the problematic span in bytes.
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Returns whether the problematic span is empty.
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Returns the hint on how to fix the problem, if any.
+  pub fn hint(&self) -> Option<&str> {
+    self.hint.as_deref()
+  }
+
+  /// Render the diagnostic in the style of the Rust compiler.
+  ///
+  /// # Arguments
+  ///
+  /// * `name` - The name of the input file.
+  pub fn render(&self, name: &str) -> String {
+    let mut out = format!("{}[{}]: {}\n", self.severity(), self.code, self.message);
+
+    let loc = match self.loc {
+      Some(loc) => loc,
+      None => {
+        if let Some(hint) = &self.hint {
+          let _ = writeln!(out, "  = help: {}", hint);
+        }
+
+        return out;
+      }
+    };
+
+    let line_no = loc.line().to_string();
+    let pad = " ".repeat(line_no.len());
+    let _ = writeln!(out, "{}--> {}:{}:{}", pad, name, loc.line(), loc.col());
+
+    if let Some(line) = &self.line {
+      // Columns and lengths are in bytes, the caret line is in
+      // characters.
+      let (prefix, rest) = line.split_at(loc.col() - 1);
+      let indent =
+        prefix.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect::<String>();
+      let len = rest.char_indices().take_while(|&(i, _)| i < self.len).count()
+        + self.len.saturating_sub(rest.len());
+      let carets = "^".repeat(len.max(1));
+
+      let _ = writeln!(out, "{} |", pad);
+      let _ = writeln!(out, "{} | {}", line_no, line);
+      let _ = writeln!(out, "{} | {}{}", pad, indent, carets);
+    }
+
+    if let Some(hint) = &self.hint {
+      let _ = writeln!(out, "{} |", pad);
+      let _ = writeln!(out, "{} = help: {}", pad, hint);
+    }
+
+    out
+  }
+}
+
+/// Returns the line at `loc` in `buf`, if it is printable text.
+fn line_at(buf: Buf, loc: Loc) -> Option<String> {
+  let line = buf.split(|&b| b == b'\n').nth(loc.line() - 1)?;
+  let line = line.strip_suffix(b"\r").unwrap_or(line);
+  let line = std::str::from_utf8(line).ok()?;
+
+  if !line.is_char_boundary(loc.col() - 1)
+    || line.chars().any(|c| c.is_control() && c != '\t')
+  {
+    return None;
+  }
+
+  Some(line.to_owned())
+}
+
+/// Returns the token at the start of `text`, up to whitespace or a
+/// comment.
+fn token(text: &str) -> &str {
+  let end = text.find(|c: char| c.is_whitespace()).unwrap_or(text.len());
+  let token = &text[..end];
+  token.find("//").map_or(token, |i| &token[..i])
+}
+
+/// Returns the length of the token at `loc` in `buf`.
+fn token_len(buf: Buf, loc: Loc) -> usize {
+  line_at(buf, loc).map_or(1, |line| token(&line[loc.col() - 1..]).len())
+}
+
+/// Returns the length of the operand of the instruction or directive
+/// at `loc` in `buf` (i.e. the rest of the line without comments).
+fn operand_len(buf: Buf, loc: Loc) -> usize {
+  line_at(buf, loc).map_or(1, |line| {
+    let line = &line[loc.col() - 1..];
+    line.split("//").next().unwrap_or_default().trim_end().len()
+  })
+}
+
+/// Returns the character in `text` that starts with the byte `b`, or
+/// the escaped byte if there is none.
+fn char_at(text: &str, b: u8) -> String {
+  text
+    .chars()
+    .find(|c| c.to_string().as_bytes()[0] == b)
+    .map_or_else(|| std::ascii::escape_default(b).to_string(), String::from)
+}
+
+/// Returns the message and hint for an invalid label declaration.
+fn label_err(err: &LabelErr, decl: &str) -> (String, String) {
+  let hint = "labels contain letters, digits and `_`, `.`, `$` and `:`, \
+              and do not start with a digit unless they are anonymous (e.g. `(1)`)";
+
+  match err {
+    LabelErr::Empty => (
+      String::from("empty label declaration"),
+      String::from("labels are declared with a name in parentheses, e.g. `(LOOP)`"),
+    ),
+    LabelErr::InvalidStart(b) => (
+      format!("label `{}` starts with invalid character `{}`", decl, char_at(decl, *b)),
+      String::from(hint),
+    ),
+    LabelErr::InvalidByte(b) => (
+      format!("label `{}` contains invalid character `{}`", decl, char_at(decl, *b)),
+      String::from(hint),
+    ),
+    LabelErr::Sym(sym) => (
+      format!("cannot use predefined symbol `{}` as a label", sym),
+      format!("choose another name, the predefined symbols are {}", SYMS),
+    ),
+  }
+}
+
+/// Returns the message, offset and length of the span, and hint for
+/// an invalid C-instruction `token`.
+///
+/// The destination, computation and jump parts of the instruction
+/// are checked in order, and the first invalid part is reported.
+fn inst_err(err: &InstErr, token: &str) -> (String, usize, usize, Option<String>) {
+  let (dest, rest, rest_offset) = match token.find('=') {
+    Some(i) => (Some(&token[..i]), &token[i + 1..], i + 1),
+    None => (None, token, 0),
+  };
+
+  let (comp, jump) = match rest.find(';') {
+    Some(i) => (&rest[..i], Some((&rest[i + 1..], rest_offset + i + 1))),
+    None => (rest, None),
+  };
+
+  if let Some(dest) = dest {
+    if dest.is_empty() {
+      let message = String::from("missing destination before `=`");
+      return (
+        message,
+        0,
+        1,
+        Some(String::from("destinations are `A`, `D`, `M` or a combination")),
+      );
+    }
+
+    if Dest::try_from(dest.as_bytes()).is_err() {
+      let candidates =
+        (1..8u16).filter_map(|v| Dest::try_from(v).ok()).map(|d| d.to_string());
+      let hint = suggest(dest, candidates, true);
+      return (format!("unknown destination `{}`", dest), 0, dest.len(), hint);
+    }
+  }
+
+  if comp.is_empty() {
+    let message = String::from("missing computation");
+    return (message, rest_offset, 1, Some(String::from("e.g. `D=D+1` or `0;JMP`")));
+  }
+
+  if Comp::try_from(comp.as_bytes()).is_err() {
+    let candidates =
+      (0..128u16).filter_map(|v| Comp::try_from(v).ok()).map(|c| c.to_string());
+    let hint = suggest(comp, candidates, false);
+    return (format!("unknown computation `{}`", comp), rest_offset, comp.len(), hint);
+  }
+
+  if let Some((jump, offset)) = jump {
+    if jump.is_empty() {
+      let message = String::from("missing jump after `;`");
+      return (message, offset - 1, 1, Some(String::from("e.g. `0;JMP` or `D;JGT`")));
+    }
+
+    if Jump::try_from(jump.as_bytes()).is_err() {
+      let candidates =
+        (1..8u16).filter_map(|v| Jump::try_from(v).ok()).map(|j| j.to_string());
+      let hint = suggest(jump, candidates, true);
+      return (format!("unknown jump `{}`", jump), offset, jump.len(), hint);
+    }
+  }
+
+  match err {
+    InstErr::MissingDestJump => (
+      String::from("instruction without destination or jump"),
+      0,
+      token.len(),
+      Some(String::from("add a destination (e.g. `D=...`) or a jump (e.g. `...;JMP`)")),
+    ),
+    _ => (String::from("invalid instruction"), 0, token.len().max(1), None),
+  }
+}
+
+/// Suggest the candidate closest to `text`.
+///
+/// Candidates that only differ in case, or (for commutative
+/// operations) in order, are preferred over ones within an edit
+/// distance of 2, which are only considered if `fuzzy` is set.
+fn suggest(
+  text: &str,
+  candidates: impl Iterator<Item = String>,
+  fuzzy: bool,
+) -> Option<String> {
+  let upper = text.to_ascii_uppercase();
+  let mut sorted = upper.chars().collect::<Vec<_>>();
+  sorted.sort_unstable();
+
+  let mut best: Option<(usize, String)> = None;
+
+  for candidate in candidates {
+    let mut candidate_sorted = candidate.chars().collect::<Vec<_>>();
+    candidate_sorted.sort_unstable();
+
+    let score = if candidate == upper {
+      0
+    } else if candidate_sorted == sorted && !upper.contains('-') {
+      1
+    } else if fuzzy {
+      match distance(&upper, &candidate) {
+        d @ 1..=2 if d < upper.len() => d + 1,
+        _ => continue,
+      }
+    } else {
+      continue;
+    };
+
+    if best.as_ref().map_or(true, |(s, _)| score < *s) {
+      best = Some((score, candidate));
+    }
+  }
+
+  best.map(|(_, candidate)| format!("did you mean `{}`?", candidate))
+}
+
+/// Returns the Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+  let b = b.chars().collect::<Vec<_>>();
+  let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+  for (i, ca) in a.chars().enumerate() {
+    let mut prev = row[0];
+    row[0] = i + 1;
+
+    for (j, &cb) in b.iter().enumerate() {
+      let cur = row[j + 1];
+      row[j + 1] = (prev + usize::from(ca != cb)).min(row[j] + 1).min(cur + 1);
+      prev = cur;
+    }
+  }
+
+  row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hack::Prog;
+
+  fn diag(src: &str) -> Diagnostic {
+    let err = Prog::from_source(src.as_bytes()).err().unwrap();
+    Diagnostic::new(src.as_bytes(), &err)
+  }
+
+  #[test]
+  fn instructions() {
+    let d = diag("  d=D+1");
+    assert_eq!((d.code(), d.message()), (E_INST, "unknown destination `d`"));
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(1, 3)), 1));
+    assert_eq!(d.hint(), Some("did you mean `D`?"));
+
+    let d = diag("MDD=M");
+    assert_eq!(d.hint(), Some("did you mean `MD`?"));
+
+    let d = diag("D=1D+");
+    assert_eq!(
+      (d.message(), d.loc()),
+      ("unknown computation `1D+`", Some(Loc::new(1, 3)))
+    );
+    assert_eq!(d.hint(), Some("did you mean `D+1`?"));
+
+    let d = diag("D=1-D");
+    assert_eq!(d.hint(), None);
+
+    let d = diag("0;jmp");
+    assert_eq!(d.hint(), Some("did you mean `JMP`?"));
+
+    let d = diag("0;");
+    assert_eq!((d.message(), d.loc()), ("missing jump after `;`", Some(Loc::new(1, 2))));
+
+    let d = diag("D+1");
+    assert_eq!(d.message(), "instruction without destination or jump");
+    assert_eq!(d.len(), 3);
+  }
+
+  #[test]
+  fn labels_and_addresses() {
+    let d = diag("(1A)");
+    assert_eq!((d.code(), d.len()), (E_LABEL, 4));
+
+    let d = diag("(R0)");
+    assert_eq!(d.message(), "cannot use predefined symbol `R0` as a label");
+
+    let d = diag("(aé)");
+    assert_eq!(d.message(), "label `(aé)` contains invalid character `é`");
+
+    assert_eq!(char_at("", 0xFF), "\\xff");
+
+    let d = diag("(LOOP\n@LOOP");
+    assert_eq!(d.code(), E_PAREN);
+
+    let d = diag("@32768");
+    assert_eq!((d.code(), d.loc(), d.len()), (E_ADDR, Some(Loc::new(1, 2)), 5));
+
+    let d = diag("(A)\n(A)");
+    assert_eq!((d.code(), d.loc(), d.len()), (E_DUPLICATE, Some(Loc::new(2, 1)), 3));
+
+    let buf = "@LOOP\n0;JMP".as_bytes();
+    let d = Diagnostic::new(buf, &Prog::from_source(buf).unwrap().warnings()[0]);
+    assert_eq!((d.code(), d.len(), d.severity()), (E_UNDEFINED, 5, "error"));
+    assert_eq!(d.into_warning().severity(), "warning");
+  }
+
+  #[test]
+  fn render() {
+    let d = diag("\t@-1");
+    assert_eq!(
+      d.render("a.asm"),
+      "error[E0004]: address `-1` is out of range\n \
+        --> a.asm:1:3\n  \
+         |\n\
+       1 | \t@-1\n  \
+         | \t ^^\n  \
+         |\n  \
+         = help: addresses are numbers from 0 to 32767, labels or predefined symbols\n"
+    );
+
+    let buf = "@'é'+X  // ü";
+    let d = Diagnostic::with_line(
+      buf.as_bytes(),
+      E_EXPR,
+      String::new(),
+      Some(Loc::new(1, 7)),
+      1,
+      None,
+    );
+    assert!(d.render("a.asm").ends_with("1 | @'é'+X  // ü\n  |      ^\n"));
+
+    let d = Diagnostic::with_line(
+      buf.as_bytes(),
+      E_EXPR,
+      String::new(),
+      Some(Loc::new(1, 10)),
+      5,
+      None,
+    );
+    assert!(d.render("a.asm").ends_with("  |         ^^^^\n"));
+
+    let err = ProgErr::LabelNotFound(String::from("FOO"));
+    let d = Diagnostic::new(b"", &err);
+    assert_eq!(d.render("a.asm"), "error[E0007]: label or variable `FOO` not found\n");
+  }
+
+  #[test]
+  fn macros() {
+    let d = diag("  GOTP LOOP");
+    assert_eq!((d.code(), d.message()), (E_MACRO, "unknown macro or instruction `GOTP`"));
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(1, 3)), 4));
+    assert_eq!(d.hint(), Some("did you mean `GOTO`?"));
+    assert_eq!(d.kind(), "macro_error");
+
+    let d = diag("SET R0, ,");
+    assert_eq!((d.code(), d.message()), (E_MACRO, "missing argument"));
+
+    let d = diag(".macro M\n(L)\n.endm");
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(2, 1)), 3));
+
+    let d = diag(".mcro M");
+    assert_eq!((d.code(), d.message()), (E_DIRECTIVE, "unknown directive `.mcro`"));
+    assert_eq!(d.len(), 5);
+  }
+
+  #[test]
+  fn includes() {
+    let d = diag("@R0\n.include \"Mult.asm\" // Multiply");
+    assert_eq!((d.code(), d.kind()), (E_INCLUDE, "missing_include"));
+    assert_eq!(d.message(), "included file `Mult.asm` is not part of the program");
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(2, 1)), 8));
+
+    let d = diag(".macro M\n.include \"Mult.asm\"\n.endm");
+    assert_eq!((d.code(), d.loc()), (E_MACRO, Some(Loc::new(2, 1))));
+
+    let d = diag(".include Mult.asm");
+    assert_eq!(d.message(), "directive `.include` requires a quoted path");
+  }
+
+  #[test]
+  fn exprs() {
+    let d = diag("@SCREEN+ // Row");
+    assert_eq!((d.code(), d.message()), (E_ADDR, "invalid expression `SCREEN+`"));
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(1, 2)), 7));
+
+    let d = diag("@0x4000*2");
+    assert_eq!(d.message(), "address `0x4000*2` is out of range");
+
+    let d = diag("@ROW*32 // Row\n.equ ROW 1024");
+    assert_eq!((d.code(), d.kind()), (E_EXPR, "invalid_expression"));
+    assert_eq!(d.message(), "value `32768` is out of range");
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(1, 1)), 7));
+
+    let d = diag(".equ A B+1");
+    assert_eq!(d.message(), "undefined symbol `B`");
+    assert_eq!(d.len(), 10);
+
+    let d = diag(".equ R 1\n.equ R 2");
+    assert_eq!(
+      (d.code(), d.message()),
+      (E_DUPLICATE, "constant `R` is defined more than once")
+    );
+  }
+
+  #[test]
+  fn local_labels() {
+    let d = diag("(MULT)\n(.loop) // Loop\n(.loop)");
+    assert_eq!(d.message(), "label `MULT.loop` is declared more than once");
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(3, 1)), 7));
+
+    let d = diag("@2f\n0;JMP");
+    assert_eq!(d.message(), "label `2f` is used as a jump target but never declared");
+    assert_eq!((d.len(), d.hint()), (3, Some("declare `(2)` after this instruction")));
+  }
+
+  #[test]
+  fn memory() {
+    let d = diag(".data 1, 2");
+    assert_eq!((d.code(), d.kind()), (E_MEMORY, "memory_layout"));
+    assert_eq!(d.message(), "`.data` does not follow a variable");
+
+    let d = diag(".reserve buf 1\n.data 1, 2 // Values");
+    assert_eq!(d.message(), "data of `buf` exceeds its reserved space");
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(2, 1)), 10));
+
+    let d = diag(".reserve buf 16368\n@x");
+    assert_eq!(d.message(), "variable `x` overlaps the screen memory map");
+
+    let d = diag(".reserve buf 0");
+    assert_eq!(d.message(), "array `buf` has a size of 0");
+
+    let d = diag(".var a @ 16\n.var b @ 16");
+    assert_eq!(d.message(), "variable `b` overlaps `a`");
+
+    let d = diag(".var a @ 16\n.data 1, 2\n.var b @ 17");
+    assert_eq!(d.message(), "data of `a` overlaps `b`");
+    assert_eq!((d.loc(), d.len()), (Some(Loc::new(2, 1)), 10));
+
+    let d = diag("(x)\n.var x @ 100");
+    assert_eq!(d.code(), E_DUPLICATE);
+    assert_eq!((d.message(), d.len()), ("variable `x` is declared more than once", 12));
+  }
+
+  #[test]
+  fn suggestions() {
+    let jumps = || ["JGT", "JEQ", "JMP"].iter().map(|s| s.to_string());
+    assert_eq!(suggest("JPM", jumps(), true), Some(String::from("did you mean `JMP`?")));
+    assert_eq!(suggest("JEG", jumps(), true), Some(String::from("did you mean `JEQ`?")));
+    assert_eq!(suggest("XYZ", jumps(), true), None);
+    assert_eq!(suggest("JEG", jumps(), false), None);
+    assert_eq!(distance("kitten", "sitting"), 3);
+  }
+
+  #[test]
+  fn memory_images() {
+    let buf = b":040000000010EA887B";
+    let d = Diagnostic::new(buf, &Prog::from_ihex(buf).err().unwrap());
+    assert_eq!((d.code(), d.message()), (E_DECODE, "record checksum mismatch"));
+    assert_eq!(d.loc(), Some(Loc::new(1, 18)));
+
+    let buf = b"0 ea88";
+    let d = Diagnostic::new(buf, &Prog::from_logisim(buf).err().unwrap());
+    assert_eq!(d.hint(), Some("Logisim memory images start with `v2.0 raw`"));
+
+    let buf = b"0000000000000000\n111";
+    let d = Diagnostic::new(buf, &Prog::from_bintext(buf).err().unwrap());
+    assert_eq!((d.code(), d.loc()), (E_INCOMPLETE, Some(Loc::new(2, 4))));
+  }
+}
@@ -0,0 +1,391 @@
+//! Assembler directives.
+//!
+//! Directives start with a `.` and control the assembler instead of
+//! producing instructions. For example, `.macro` and `.endm` delimit
+//! the definition of a [macro](crate::hack::macros), `.include`
+//! inserts another [source](crate::hack::source) file, `.equ`
+//! defines a constant and `.var`, `.reserve` and `.data` lay out and
+//! initialize the data memory.
+
+use crate::hack::Addr;
+use crate::hack::AddrErr;
+use crate::hack::Expr;
+use crate::hack::ExprErr;
+use crate::hack::Label;
+use crate::hack::LabelErr;
+use crate::parser;
+use crate::Buf;
+use derive_more::Display;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// An assembler directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directive<'b> {
+  /// Start of a macro definition, with the name and parameters of
+  /// the macro (e.g. `.macro SWAP a, b`).
+  Macro(Label<'b>, Vec<Label<'b>>),
+
+  /// End of a macro definition (`.endm`).
+  EndMacro,
+
+  /// Inclusion of another source file, with its path relative to the
+  /// including file (e.g. `.include "lib.asm"`).
+  Include(&'b str),
+
+  /// Definition of a constant, with its name and value (e.g. `.equ
+  /// WIDTH 32`).
+  Equ(Label<'b>, Addr<'b>),
+
+  /// Declaration of a variable at a fixed RAM address, with its name
+  /// and address (e.g. `.var counter @ 100`).
+  Var(Label<'b>, Addr<'b>),
+
+  /// Reservation of consecutive RAM words for an array, with its name
+  /// and size (e.g. `.reserve buf 32`).
+  Reserve(Label<'b>, Addr<'b>),
+
+  /// Initial values of the RAM words of the preceding variable or
+  /// array (e.g. `.data 1, 2, 3`).
+  Data(Vec<Expr<'b>>),
+}
+
+impl fmt::Display for Directive<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Directive::Macro(name, params) => {
+        write!(f, ".macro {}", name)?;
+
+        for (i, param) in params.iter().enumerate() {
+          write!(f, "{}{}", if i == 0 { " " } else { ", " }, param)?;
+        }
+
+        Ok(())
+      }
+      Directive::EndMacro => write!(f, ".endm"),
+      Directive::Include(path) => write!(f, ".include \"{}\"", path),
+      Directive::Equ(name, value) => {
+        write!(f, ".equ {} {}", name, &value.to_string()[1..])
+      }
+      Directive::Var(name, addr) => {
+        write!(f, ".var {} @ {}", name, &addr.to_string()[1..])
+      }
+      Directive::Reserve(name, size) => {
+        write!(f, ".reserve {} {}", name, &size.to_string()[1..])
+      }
+      Directive::Data(values) => {
+        write!(f, ".data")?;
+
+        for (i, value) in values.iter().enumerate() {
+          write!(f, "{}{}", if i == 0 { " " } else { ", " }, value)?;
+        }
+
+        Ok(())
+      }
+    }
+  }
+}
+
+/// Errors when parsing a directive.
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum Err {
+  /// Unknown directive.
+  #[display(fmt = "unknown directive `.{}`", _0)]
+  Unknown(String),
+
+  /// A directive is missing its name.
+  #[display(fmt = "directive `.{}` requires a name", _0)]
+  MissingName(String),
+
+  /// Invalid name or parameter.
+  #[display(fmt = "invalid name: {}", _0)]
+  InvalidName(LabelErr),
+
+  /// A directive is missing its quoted path.
+  #[display(fmt = "directive `.{}` requires a quoted path", _0)]
+  MissingPath(String),
+
+  /// A directive is missing its value.
+  #[display(fmt = "directive `.{}` requires a value", _0)]
+  MissingValue(String),
+
+  /// A directive is missing its `@` address.
+  #[display(fmt = "directive `.{}` requires an address (e.g. `@ 100`)", _0)]
+  MissingAddr(String),
+
+  /// Invalid value.
+  #[display(fmt = "invalid value: {}", _0)]
+  InvalidValue(AddrErr),
+
+  /// A path is not valid UTF-8.
+  #[display(fmt = "path is not valid UTF-8")]
+  InvalidPath,
+
+  /// Unexpected input after a directive.
+  #[display(fmt = "unexpected `{}` after directive", _0)]
+  Unexpected(String),
+}
+
+impl<'b> Directive<'b> {
+  /// Returns the name of a directive, without its leading `.`.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Directive;
+  ///
+  /// assert_eq!(Directive::EndMacro.name(), "endm");
+  /// assert_eq!(Directive::Include("lib.asm").name(), "include");
+  /// ```
+  pub fn name(&self) -> &'static str {
+    match self {
+      Directive::Macro(..) => "macro",
+      Directive::EndMacro => "endm",
+      Directive::Include(_) => "include",
+      Directive::Equ(..) => "equ",
+      Directive::Var(..) => "var",
+      Directive::Reserve(..) => "reserve",
+      Directive::Data(_) => "data",
+    }
+  }
+
+  /// Read a directive from a buffer, after its leading `.`.
+  ///
+  /// The rest of the line after the directive must be blank or a
+  /// comment.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Addr;
+  /// use has::hack::Directive;
+  /// use has::hack::DirectiveErr;
+  /// use has::hack::Label;
+  /// use std::convert::TryFrom;
+  ///
+  /// let label = |name: &'static str| Label::try_from(name.as_bytes()).unwrap();
+  ///
+  /// let (dir, rem, len) = Directive::read_from("macro SWAP a, b\n".as_bytes()).unwrap();
+  /// assert_eq!(dir, Directive::Macro(label("SWAP"), vec![label("a"), label("b")]));
+  /// assert_eq!((rem, len), ("\n".as_bytes(), 15));
+  ///
+  /// let (dir, _, len) = Directive::read_from("endm // Done".as_bytes()).unwrap();
+  /// assert_eq!((dir, len), (Directive::EndMacro, 4));
+  ///
+  /// let (dir, _, len) = Directive::read_from("include \"lib.asm\"".as_bytes()).unwrap();
+  /// assert_eq!((dir, len), (Directive::Include("lib.asm"), 17));
+  ///
+  /// let (dir, _, len) = Directive::read_from("equ WIDTH 0x20".as_bytes()).unwrap();
+  /// assert_eq!((dir.to_string().as_str(), len), (".equ WIDTH 0x20", 14));
+  ///
+  /// let (dir, _, len) = Directive::read_from("var counter @100".as_bytes()).unwrap();
+  /// assert_eq!((dir, len), (Directive::Var(label("counter"), Addr::Num(100)), 16));
+  ///
+  /// let (dir, _, len) = Directive::read_from("reserve buf 32".as_bytes()).unwrap();
+  /// assert_eq!((dir, len), (Directive::Reserve(label("buf"), Addr::Num(32)), 14));
+  ///
+  /// let (dir, _, len) = Directive::read_from("data 1, 'A', N*2 // Values".as_bytes()).unwrap();
+  /// assert_eq!((dir.to_string().as_str(), len), (".data 1, 'A', N*2", 16));
+  ///
+  /// let err = Directive::read_from("endm SWAP".as_bytes());
+  /// assert_eq!(err, Err(DirectiveErr::Unexpected(String::from("SWAP"))));
+  ///
+  /// let err = Directive::read_from("foo".as_bytes());
+  /// assert_eq!(err, Err(DirectiveErr::Unknown(String::from("foo"))));
+  /// ```
+  pub fn read_from(buf: Buf<'b>) -> Result<(Self, Buf<'b>, usize), Err> {
+    let (word, rem) = parser::read_while(buf, |b| b.is_ascii_alphabetic());
+    let word_str = String::from_utf8_lossy(word).into_owned();
+
+    let (directive, rem, len) = match word {
+      b"macro" => {
+        let (name, rem, name_len) = Self::read_name(rem, &word_str)?;
+        let (items, rem, len) = parser::read_list(rem);
+        let params = items
+          .into_iter()
+          .map(|(_, item)| Label::try_from(item).map_err(Err::InvalidName))
+          .collect::<Result<Vec<_>, _>>()?;
+        (Directive::Macro(name, params), rem, word.len() + name_len + len)
+      }
+      b"equ" | b"reserve" => {
+        let (name, rem, name_len) = Self::read_name(rem, &word_str)?;
+        let (blank, rem) = parser::read_while(rem, |b| b == b' ' || b == b'\t');
+
+        if blank.is_empty() || parser::is_line_end(rem) {
+          return Err(Err::MissingValue(word_str));
+        }
+
+        let (value, rem, len) = Addr::read_from(rem).map_err(Err::InvalidValue)?;
+        let len = word.len() + name_len + blank.len() + len;
+
+        match word {
+          b"equ" => (Directive::Equ(name, value), rem, len),
+          _ => (Directive::Reserve(name, value), rem, len),
+        }
+      }
+      b"var" => {
+        let (name, rem, name_len) = Self::read_name(rem, &word_str)?;
+        let (blank, rem) = parser::read_while(rem, |b| b == b' ' || b == b'\t');
+        let rem = match rem.split_first() {
+          Some((b'@', rem)) if !blank.is_empty() => rem,
+          _ => return Err(Err::MissingAddr(word_str)),
+        };
+        let (blank2, rem) = parser::read_while(rem, |b| b == b' ' || b == b'\t');
+
+        if parser::is_line_end(rem) {
+          return Err(Err::MissingAddr(word_str));
+        }
+
+        let (addr, rem, len) = Addr::read_from(rem).map_err(Err::InvalidValue)?;
+        let len = word.len() + name_len + blank.len() + 1 + blank2.len() + len;
+        (Directive::Var(name, addr), rem, len)
+      }
+      b"data" => {
+        let (items, rem, len) = parser::read_list(rem);
+
+        if items.is_empty() {
+          return Err(Err::MissingValue(word_str));
+        }
+
+        let values = items
+          .into_iter()
+          .map(|(_, item)| match Expr::read_from(item) {
+            Ok((value, _, len)) if len == item.len() => Ok(value),
+            Ok(_) => {
+              let item = String::from_utf8_lossy(item).into_owned();
+              Err(Err::InvalidValue(AddrErr::Expr(ExprErr::Invalid(item))))
+            }
+            Err(e) => Err(Err::InvalidValue(AddrErr::Expr(e))),
+          })
+          .collect::<Result<Vec<_>, _>>()?;
+        (Directive::Data(values), rem, word.len() + len)
+      }
+      b"endm" => (Directive::EndMacro, rem, word.len()),
+      b"include" => {
+        let (blank, rem) = parser::read_while(rem, |b| b == b' ' || b == b'\t');
+        let (path, rem) = match rem.split_first() {
+          Some((b'"', rem)) => parser::read_while(rem, |b| b != b'"' && b != b'\n'),
+          _ => return Err(Err::MissingPath(word_str)),
+        };
+        let rem = match rem.split_first() {
+          Some((b'"', rem)) if !path.is_empty() => rem,
+          _ => return Err(Err::MissingPath(word_str)),
+        };
+        let len = word.len() + blank.len() + path.len() + 2;
+        let path = std::str::from_utf8(path).map_err(|_| Err::InvalidPath)?;
+        (Directive::Include(path), rem, len)
+      }
+      _ => {
+        let (word, _) = parser::read_until_ws(buf);
+        return Err(Err::Unknown(String::from_utf8_lossy(word).into_owned()));
+      }
+    };
+
+    if !parser::is_line_end(rem) {
+      let (_, rest) = parser::read_while(rem, |b| b.is_ascii_whitespace());
+      let (rest, _) = parser::read_until_ws(rest);
+      return Err(Err::Unexpected(String::from_utf8_lossy(rest).into_owned()));
+    }
+
+    Ok((directive, rem, len))
+  }
+
+  /// Read the name of a directive, after blanks.
+  ///
+  /// Returns the name, the remainder of the buffer and the number of
+  /// bytes that have been consumed, including the blanks.
+  fn read_name(
+    buf: Buf<'b>,
+    directive: &str,
+  ) -> Result<(Label<'b>, Buf<'b>, usize), Err> {
+    let (blank, rem) = parser::read_while(buf, |b| b == b' ' || b == b'\t');
+    let (name, rem) = parser::read_while(rem, Label::is_label_byte);
+
+    if name.is_empty() {
+      return Err(Err::MissingName(String::from(directive)));
+    }
+
+    let len = blank.len() + name.len();
+    Ok((Label::try_from(name).map_err(Err::InvalidName)?, rem, len))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn display() {
+    let label = |name: &'static str| Label::try_from(name.as_bytes()).unwrap();
+
+    let dir = Directive::Macro(label("PUSH_D"), Vec::new());
+    assert_eq!(dir.to_string(), ".macro PUSH_D");
+
+    let dir = Directive::Macro(label("SWAP"), vec![label("a"), label("b")]);
+    assert_eq!(dir.to_string(), ".macro SWAP a, b");
+    assert_eq!(Directive::EndMacro.to_string(), ".endm");
+    assert_eq!(Directive::Include("lib.asm").to_string(), ".include \"lib.asm\"");
+
+    let dir = Directive::Equ(label("ROW"), Addr::Num(3));
+    assert_eq!(dir.to_string(), ".equ ROW 3");
+
+    let dir = Directive::Var(label("counter"), Addr::Num(100));
+    assert_eq!(dir.to_string(), ".var counter @ 100");
+
+    let dir = Directive::Reserve(label("buf"), Addr::Num(32));
+    assert_eq!(dir.to_string(), ".reserve buf 32");
+
+    let (dir, _, _) = Directive::read_from(b"data 1,2 ,  'A'").unwrap();
+    assert_eq!(dir.to_string(), ".data 1, 2, 'A'");
+  }
+
+  #[test]
+  fn errors() {
+    let err = Directive::read_from(b"macro");
+    assert_eq!(err, Err(Err::MissingName(String::from("macro"))));
+
+    let err = Directive::read_from(b"macro 1A");
+    assert!(matches!(err, Err(Err::InvalidName(LabelErr::InvalidStart(b'1')))));
+
+    let err = Directive::read_from(b"macro A,");
+    assert_eq!(err, Err(Err::InvalidName(LabelErr::Empty)));
+
+    let err = Directive::read_from(b"include lib.asm");
+    assert_eq!(err, Err(Err::MissingPath(String::from("include"))));
+
+    let err = Directive::read_from(b"include \"lib.asm\n\"");
+    assert_eq!(err, Err(Err::MissingPath(String::from("include"))));
+
+    let err = Directive::read_from(b"include \"\"");
+    assert_eq!(err, Err(Err::MissingPath(String::from("include"))));
+
+    let err = Directive::read_from(b"include \"\xff\"");
+    assert_eq!(err, Err(Err::InvalidPath));
+
+    let err = Directive::read_from(b"equ WIDTH // Width");
+    assert_eq!(err, Err(Err::MissingValue(String::from("equ"))));
+
+    let err = Directive::read_from(b"equ WIDTH 32 64");
+    assert_eq!(err, Err(Err::Unexpected(String::from("64"))));
+
+    let err = Directive::read_from(b"equ WIDTH 0x10000");
+    assert_eq!(err, Err(Err::InvalidValue(AddrErr::Range(65536))));
+
+    let err = Directive::read_from(b"var counter 100");
+    assert_eq!(err, Err(Err::MissingAddr(String::from("var"))));
+
+    let err = Directive::read_from(b"var counter @ // Counter");
+    assert_eq!(err, Err(Err::MissingAddr(String::from("var"))));
+
+    let err = Directive::read_from(b"reserve buf");
+    assert_eq!(err, Err(Err::MissingValue(String::from("reserve"))));
+
+    let err = Directive::read_from(b"data // Values");
+    assert_eq!(err, Err(Err::MissingValue(String::from("data"))));
+
+    let invalid =
+      |item: &str| Err::InvalidValue(AddrErr::Expr(ExprErr::Invalid(item.into())));
+    assert_eq!(Directive::read_from(b"data 1,,2"), Err(invalid("")));
+    assert_eq!(Directive::read_from(b"data 1+"), Err(invalid("1+")));
+  }
+}
@@ -0,0 +1,507 @@
+//! Emulator for the HACK CPU.
+//!
+//! [Emu] models the HACK computer: 32K words of instruction memory
+//! (ROM), 32K words of data memory (RAM) and the `A`, `D` and `PC`
+//! registers. Programs are loaded into ROM either from a [Prog] or
+//! from their encoded 16-bit words (e.g. as produced by
+//! [dec::Parser](crate::hack::dec::Parser)) and are executed one
+//! clock cycle at a time.
+
+use crate::hack::Cmd;
+use crate::hack::InstDecodeErr;
+use crate::hack::Jump;
+use crate::hack::Keyboard;
+use crate::hack::OwnedProg;
+use crate::hack::Prog;
+use crate::hack::ProgErr;
+use crate::hack::Screen;
+use derive_more::Display;
+use derive_more::From;
+use std::convert::TryFrom;
+
+/// Number of words in the instruction memory.
+pub const ROM_SIZE: usize = 32768;
+
+/// Number of words in the data memory.
+pub const RAM_SIZE: usize = 32768;
+
+/// Mask for the 15 bits used to address the instruction and data
+/// memories.
+const ADDR_MASK: u16 = 0b0111_1111_1111_1111;
+
+/// Errors when loading or running a program in the emulator.
+#[derive(Display, Debug, Clone, PartialEq, Eq, From)]
+pub enum Err {
+  /// The program does not fit in the instruction memory.
+  ///
+  /// Contains the number of instructions in the program.
+  #[display(fmt = "program of {} instructions does not fit in ROM", _0)]
+  #[from(ignore)]
+  RomSize(usize),
+
+  /// The program could not be encoded.
+  #[display(fmt = "Loading error: {}", _0)]
+  Prog(ProgErr),
+
+  /// An invalid instruction was fetched from ROM.
+  ///
+  /// Contains the ROM address, the instruction and the decoding
+  /// error.
+  #[display(fmt = "invalid instruction `{:#018b}` at ROM address {}: {}", _1, _0, _2)]
+  #[from(ignore)]
+  Decode(u16, u16, InstDecodeErr),
+}
+
+/// The state of a HACK computer.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::Emu;
+/// use has::hack::Prog;
+///
+/// let prog = Prog::from_source("@2\nD=A\n@3\nD=D+A\n@0\nM=D".as_bytes()).unwrap();
+/// let mut emu = Emu::from_prog(&prog).unwrap();
+///
+/// for _ in 0..prog.insts().len() {
+///   emu.step().unwrap();
+/// }
+///
+/// assert_eq!(emu.ram()[0], 5);
+/// assert_eq!(emu.pc(), 6);
+/// assert_eq!(emu.cycles(), 6);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Emu {
+  /// Instruction memory.
+  rom: Vec<u16>,
+
+  /// Data memory.
+  ram: Vec<u16>,
+
+  /// Address register.
+  a: u16,
+
+  /// Data register.
+  d: u16,
+
+  /// Program counter.
+  pc: u16,
+
+  /// Number of clock cycles executed so far.
+  cycles: u64,
+
+  /// The scripted keyboard.
+  keyboard: Keyboard,
+}
+
+/// Create an emulator with zeroed memories and registers.
+impl Default for Emu {
+  fn default() -> Self {
+    Self {
+      rom: vec![0; ROM_SIZE],
+      ram: vec![0; RAM_SIZE],
+      a: 0,
+      d: 0,
+      pc: 0,
+      cycles: 0,
+      keyboard: Keyboard::default(),
+    }
+  }
+}
+
+impl Emu {
+  /// Create an emulator with zeroed memories and registers.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Create an emulator with `prog` loaded into its instruction
+  /// memory and its [initial data](Prog::data) loaded into the data
+  /// memory.
+  ///
+  /// # Arguments
+  ///
+  /// * `prog` - The program to load.
+  pub fn from_prog(prog: &Prog) -> Result<Self, Err> {
+    let words = prog.to_words().collect::<Result<Vec<_>, _>>()?;
+    Self::from_data(&words, prog.data())
+  }
+
+  /// Create an emulator with an [owned program](OwnedProg) loaded, as
+  /// with [Emu::from_prog].
+  ///
+  /// # Arguments
+  ///
+  /// * `prog` - The program to load.
+  pub fn from_owned_prog(prog: &OwnedProg) -> Result<Self, Err> {
+    let words = prog.to_words().collect::<Result<Vec<_>, _>>()?;
+    Self::from_data(&words, prog.data())
+  }
+
+  /// Create an emulator with `words` loaded into its instruction
+  /// memory and `data` into its data memory.
+  fn from_data(words: &[u16], data: &[(u16, u16)]) -> Result<Self, Err> {
+    let mut emu = Self::from_words(words)?;
+
+    for &(addr, value) in data {
+      emu.ram[usize::from(addr)] = value;
+    }
+
+    Ok(emu)
+  }
+
+  /// Create an emulator with `words` loaded into its instruction
+  /// memory.
+  ///
+  /// # Arguments
+  ///
+  /// * `words` - The encoded instructions to load.
+  pub fn from_words(words: &[u16]) -> Result<Self, Err> {
+    let mut emu = Self::new();
+    emu.load(words)?;
+    Ok(emu)
+  }
+
+  /// Replace the contents of the instruction memory with `words`.
+  ///
+  /// The remainder of the instruction memory is zeroed and the
+  /// program counter is [reset](Emu::reset). The data memory and the
+  /// `A` and `D` registers are left untouched.
+  ///
+  /// # Arguments
+  ///
+  /// * `words` - The encoded instructions to load.
+  pub fn load(&mut self, words: &[u16]) -> Result<(), Err> {
+    if words.len() > ROM_SIZE {
+      return Err(Err::RomSize(words.len()));
+    }
+
+    self.rom[..words.len()].copy_from_slice(words);
+    self.rom[words.len()..].iter_mut().for_each(|word| *word = 0);
+    self.reset();
+    Ok(())
+  }
+
+  /// Reset the program counter to `0`.
+  ///
+  /// Like the reset input of the HACK CPU, this does not affect the
+  /// memories or the `A` and `D` registers.
+  pub fn reset(&mut self) {
+    self.pc = 0;
+  }
+
+  /// Decode the instruction at the current program counter.
+  pub fn inst(&self) -> Result<Cmd<'static>, Err> {
+    let word = self.rom[usize::from(self.pc)];
+    Cmd::try_from(word).map_err(|e| Err::Decode(self.pc, word, e))
+  }
+
+  /// Execute a single clock cycle.
+  ///
+  /// Fetches, decodes and executes the instruction at the current
+  /// program counter. As in the HACK CPU, the memory address and the
+  /// jump target are both taken from the value of the `A` register
+  /// before the instruction is executed. Keyboard events that are due
+  /// are applied to the data memory before the instruction is
+  /// fetched.
+  pub fn step(&mut self) -> Result<(), Err> {
+    self.keyboard.update(self.cycles, &mut self.ram);
+    let next = self.pc.wrapping_add(1) & ADDR_MASK;
+
+    match self.inst()? {
+      Cmd::Addr(_) => {
+        self.a = self.rom[usize::from(self.pc)];
+        self.pc = next;
+      }
+      Cmd::Inst(inst) => {
+        let addr = usize::from(self.a & ADDR_MASK);
+        let out = inst.comp().eval(self.d, self.a, self.ram[addr]);
+        let dest = inst.dest();
+
+        self.pc = if inst.jump().eval(out) { self.a & ADDR_MASK } else { next };
+
+        if dest.has_m() {
+          self.ram[addr] = out;
+        }
+
+        if dest.has_d() {
+          self.d = out;
+        }
+
+        if dest.has_a() {
+          self.a = out;
+        }
+      }
+    }
+
+    self.cycles += 1;
+    Ok(())
+  }
+
+  /// Whether the program is stuck in a tight infinite loop.
+  ///
+  /// HACK programs conventionally terminate with an unconditional
+  /// jump to itself (e.g. `(END) @END 0;JMP`). The program is
+  /// considered halted when the instruction at the program counter
+  /// loads its own address and is followed by an unconditional jump
+  /// that has no destination.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Emu;
+  /// use has::hack::Prog;
+  ///
+  /// let prog = Prog::from_source("@1\n(END)\n@END\n0;JMP".as_bytes()).unwrap();
+  /// let mut emu = Emu::from_prog(&prog).unwrap();
+  /// assert!(!emu.is_halted());
+  ///
+  /// emu.step().unwrap();
+  /// assert!(emu.is_halted());
+  /// ```
+  pub fn is_halted(&self) -> bool {
+    let pc = usize::from(self.pc);
+
+    if self.rom[pc] != self.pc {
+      return false;
+    }
+
+    match self.rom.get(pc + 1).map(|&word| Cmd::try_from(word)) {
+      Some(Ok(Cmd::Inst(inst))) => inst.jump() == Jump::JMP && inst.dest().is_null(),
+      _ => false,
+    }
+  }
+
+  /// Run the program until it halts or for at most `limit` clock
+  /// cycles.
+  ///
+  /// Returns whether the program [halted](Emu::is_halted).
+  ///
+  /// # Arguments
+  ///
+  /// * `limit` - The maximum number of clock cycles to execute.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Emu;
+  /// use has::hack::Prog;
+  ///
+  /// let prog = Prog::from_source("@3\nD=A\n@0\nM=D\n(END)\n@END\n0;JMP".as_bytes()).unwrap();
+  /// let mut emu = Emu::from_prog(&prog).unwrap();
+  ///
+  /// assert_eq!(emu.run(1000), Ok(true));
+  /// assert_eq!(emu.cycles(), 4);
+  /// assert_eq!(emu.ram()[0], 3);
+  /// ```
+  pub fn run(&mut self, limit: u64) -> Result<bool, Err> {
+    for _ in 0..limit {
+      if self.is_halted() {
+        return Ok(true);
+      }
+
+      self.step()?;
+    }
+
+    Ok(self.is_halted())
+  }
+
+  /// Returns the value of the Address register.
+  pub fn a(&self) -> u16 {
+    self.a
+  }
+
+  /// Set the value of the Address register.
+  pub fn set_a(&mut self, a: u16) {
+    self.a = a;
+  }
+
+  /// Returns the value of the Data register.
+  pub fn d(&self) -> u16 {
+    self.d
+  }
+
+  /// Set the value of the Data register.
+  pub fn set_d(&mut self, d: u16) {
+    self.d = d;
+  }
+
+  /// Returns the value of the program counter.
+  pub fn pc(&self) -> u16 {
+    self.pc
+  }
+
+  /// Set the value of the program counter.
+  ///
+  /// Only the lower 15 bits of `pc` are used.
+  pub fn set_pc(&mut self, pc: u16) {
+    self.pc = pc & ADDR_MASK;
+  }
+
+  /// Returns the number of clock cycles executed so far.
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
+  /// Returns the instruction memory.
+  pub fn rom(&self) -> &[u16] {
+    &self.rom
+  }
+
+  /// Returns the data memory.
+  pub fn ram(&self) -> &[u16] {
+    &self.ram
+  }
+
+  /// Returns a mutable reference to the data memory.
+  pub fn ram_mut(&mut self) -> &mut [u16] {
+    &mut self.ram
+  }
+
+  /// Returns the scripted [keyboard](Keyboard).
+  pub fn keyboard(&self) -> &Keyboard {
+    &self.keyboard
+  }
+
+  /// Replace the scripted [keyboard](Keyboard).
+  ///
+  /// Event clock cycles are compared to the number of [executed
+  /// cycles](Emu::cycles).
+  pub fn set_keyboard(&mut self, keyboard: Keyboard) {
+    self.keyboard = keyboard;
+  }
+
+  /// Returns a view of the memory-mapped [screen](Screen).
+  pub fn screen(&self) -> Screen<'_> {
+    Screen::new(&self.ram)
+  }
+}
+
+#[cfg(test)]
+#[allow(clippy::unusual_byte_groupings)] // Instructions are grouped by field.
+mod tests {
+  use super::Emu;
+  use super::Err;
+  use super::ROM_SIZE;
+  use crate::hack::kbd::Event;
+  use crate::hack::Keyboard;
+  use crate::hack::Prog;
+
+  macro_rules! emu {
+    ($f:expr) => {{
+      let bytes = include_bytes!(concat!("../../tests/programs/", $f, ".asm"));
+      Emu::from_prog(&Prog::from_source(&bytes[..]).unwrap()).unwrap()
+    }};
+  }
+
+  fn run(emu: &mut Emu, cycles: u64) {
+    for _ in 0..cycles {
+      emu.step().unwrap();
+    }
+  }
+
+  #[test]
+  fn add() {
+    let mut emu = emu!("Add");
+    run(&mut emu, 6);
+    assert_eq!(emu.ram()[0], 5);
+    assert_eq!(emu.d(), 5);
+    assert_eq!(emu.a(), 0);
+  }
+
+  #[test]
+  fn max() {
+    let mut emu = emu!("Max");
+    emu.ram_mut()[0] = 3;
+    emu.ram_mut()[1] = 5;
+    run(&mut emu, 14);
+    assert_eq!(emu.ram()[2], 5);
+
+    emu.reset();
+    emu.ram_mut()[0] = 23456;
+    emu.ram_mut()[1] = 12345;
+    run(&mut emu, 14);
+    assert_eq!(emu.ram()[2], 23456);
+  }
+
+  #[test]
+  fn mult() {
+    let mut emu = emu!("Mult");
+    emu.ram_mut()[0] = 6;
+    emu.ram_mut()[1] = 7;
+    run(&mut emu, 200);
+    assert_eq!(emu.ram()[2], 42);
+  }
+
+  #[test]
+  fn data() {
+    // Sum the array into R0.
+    let buf = b".reserve nums 3\n.data 4, 0x10, 'A'\n.var sum @ R0\n.data 0-1\n\
+                @nums\nD=M\n@nums+1\nD=D+M\n@nums+2\nD=D+M\n@sum\nM=D+M";
+    let mut emu = Emu::from_prog(&Prog::from_source(&buf[..]).unwrap()).unwrap();
+    assert_eq!(emu.ram()[16..19], [4, 16, 65]);
+    assert_eq!(emu.ram()[0], 0xFFFF);
+    run(&mut emu, 8);
+    assert_eq!(emu.ram()[0], 84);
+  }
+
+  #[test]
+  fn halt() {
+    let mut emu = emu!("Mult");
+    emu.ram_mut()[0] = 6;
+    emu.ram_mut()[1] = 7;
+    assert_eq!(emu.run(10_000), Ok(true));
+    assert_eq!(emu.ram()[2], 42);
+    assert_eq!(emu.pc(), 14);
+
+    let mut emu = emu!("Fill");
+    assert_eq!(emu.run(10_000), Ok(false));
+    assert_eq!(emu.cycles(), 10_000);
+  }
+
+  #[test]
+  fn keyboard() {
+    let mut emu = emu!("Fill");
+    emu.set_keyboard(Keyboard::new(vec![Event::new(0, 65), Event::new(400_000, 0)]));
+
+    run(&mut emu, 300_000);
+    assert_eq!(emu.ram()[0x6000], 65);
+    assert!(emu.screen().mem().iter().all(|&word| word == 0xFFFF));
+
+    run(&mut emu, 500_000);
+    assert_eq!(emu.ram()[0x6000], 0);
+    assert!(emu.screen().mem().iter().all(|&word| word == 0));
+  }
+
+  #[test]
+  fn jump_uses_previous_a() {
+    // @4, A=A-1;JMP jumps to 4 and leaves 3 in A.
+    let mut emu = Emu::from_words(&[4, 0b111_0110010_100_111]).unwrap();
+    run(&mut emu, 2);
+    assert_eq!(emu.pc(), 4);
+    assert_eq!(emu.a(), 3);
+  }
+
+  #[test]
+  fn memory_uses_previous_a() {
+    // @5, AM=A+1 writes 6 to RAM[5] and leaves 6 in A.
+    let mut emu = Emu::from_words(&[5, 0b111_0110111_101_000]).unwrap();
+    run(&mut emu, 2);
+    assert_eq!(emu.ram()[5], 6);
+    assert_eq!(emu.ram()[6], 0);
+    assert_eq!(emu.a(), 6);
+  }
+
+  #[test]
+  fn rom_size() {
+    let words = vec![0; ROM_SIZE + 1];
+    assert_eq!(Emu::from_words(&words), Err(Err::RomSize(ROM_SIZE + 1)));
+  }
+
+  #[test]
+  fn invalid_inst() {
+    let mut emu = Emu::from_words(&[0b111_1111111_000_000]).unwrap();
+    assert!(matches!(emu.step(), Err(Err::Decode(0, 0b111_1111111_000_000, _))));
+  }
+}
@@ -0,0 +1,467 @@
+//! Constant expressions in A-instructions.
+//!
+//! An [Expr] combines numbers, character literals, predefined symbols
+//! and names (labels, variables and `.equ` constants) with `+`, `-`,
+//! `*`, `/`, `%` and parentheses, e.g. `@SCREEN+32*ROW+COL`. Numbers
+//! are decimal, hexadecimal (`0x4000`) or binary (`0b1010`) and
+//! character literals (`'A'`) stand for their ASCII code.
+//!
+//! Expressions are evaluated at assembly time, once the values of all
+//! names are known. Local labels (e.g. `.loop`) in an expression are
+//! resolved in the [scope](Expr::scoped) of the instruction.
+
+use crate::hack::Label;
+use crate::hack::Sym;
+use crate::Buf;
+use derive_more::Display;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A constant expression.
+///
+/// The expression is kept as source text, which has been checked to
+/// be a valid expression, and evaluated with [Expr::eval].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expr<'b> {
+  /// The text of the expression.
+  text: &'b str,
+
+  /// The global label that local labels are scoped to.
+  scope: Option<Label<'b>>,
+}
+
+impl fmt::Display for Expr<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.text)
+  }
+}
+
+/// Errors when parsing or evaluating an expression.
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum Err {
+  /// The text is not a valid expression.
+  #[display(fmt = "invalid expression `{}`", _0)]
+  Invalid(String),
+
+  /// A name is not a label, variable, constant or predefined symbol.
+  #[display(fmt = "undefined symbol `{}`", _0)]
+  Undefined(String),
+
+  /// Division or remainder by zero.
+  #[display(fmt = "division by zero")]
+  DivZero,
+
+  /// An intermediate value does not fit in 64 bits.
+  #[display(fmt = "arithmetic overflow")]
+  Overflow,
+}
+
+/// Value of a (sub-)expression, if known.
+type Value = Option<i64>;
+
+/// Recursive descent evaluator over the text of an expression.
+struct Eval<'e, 'b> {
+  /// The remaining text.
+  buf: Buf<'b>,
+
+  /// Returns the value of a name, or `None` if it is not known yet.
+  resolve: &'e mut dyn FnMut(Label<'b>) -> Result<Value, Err>,
+
+  /// The first error other than a syntax error, which is reported
+  /// once the whole expression has been checked.
+  err: Option<Err>,
+}
+
+impl<'b> Expr<'b> {
+  /// Read an expression from a buffer.
+  ///
+  /// The expression extends up to the next whitespace (outside of
+  /// character literals) or comment. Returns the expression, the
+  /// remainder of the input buffer and the number of bytes that have
+  /// been consumed for parsing.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Expr;
+  /// use has::hack::ExprErr;
+  ///
+  /// let (expr, rem, len) = Expr::read_from("SCREEN+32*(ROW+1) // Row".as_bytes()).unwrap();
+  /// assert_eq!((expr.to_string().as_str(), rem, len), ("SCREEN+32*(ROW+1)", " // Row".as_bytes(), 17));
+  ///
+  /// let (expr, _, len) = Expr::read_from("' '".as_bytes()).unwrap();
+  /// assert_eq!((expr.to_string().as_str(), len), ("' '", 3));
+  ///
+  /// let err = Expr::read_from("A+*2".as_bytes());
+  /// assert_eq!(err, Err(ExprErr::Invalid(String::from("A+*2"))));
+  /// ```
+  pub fn read_from(buf: Buf<'b>) -> Result<(Self, Buf<'b>, usize), Err> {
+    let len = Self::extent(buf);
+    let (text, rem) = buf.split_at(len);
+    let invalid = || Err::Invalid(String::from_utf8_lossy(text).into_owned());
+    let text = std::str::from_utf8(text).map_err(|_| invalid())?;
+    let expr = Self { text, scope: None };
+
+    match expr.value(&mut |_| Ok(None)) {
+      Err(Err::Invalid(_)) => Err(invalid()),
+      _ => Ok((expr, rem, len)),
+    }
+  }
+
+  /// Returns the value of the expression if it does not refer to
+  /// labels, variables or constants.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Expr;
+  ///
+  /// let value = |text: &str| Expr::read_from(text.as_bytes()).unwrap().0.constant();
+  /// assert_eq!(value("0x4000+0b10*'A'"), Some(Ok(16384 + 2 * 65)));
+  /// assert_eq!(value("SCREEN-1"), Some(Ok(16383)));
+  /// assert_eq!(value("WIDTH*2"), None);
+  /// ```
+  pub fn constant(&self) -> Option<Result<i64, Err>> {
+    self.value(&mut |_| Ok(None)).transpose()
+  }
+
+  /// Evaluate the expression.
+  ///
+  /// # Arguments
+  ///
+  /// * `lookup` - Returns the value of a label, variable or constant,
+  ///   or `None` if it is undefined.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Expr;
+  /// use has::hack::ExprErr;
+  ///
+  /// let (expr, _, _) = Expr::read_from("(ROW*32+COL)/WIDTH".as_bytes()).unwrap();
+  ///
+  /// let lookup = |label: has::hack::Label| match label.name() {
+  ///   "ROW" => Some(2),
+  ///   "COL" => Some(5),
+  ///   "WIDTH" => Some(3),
+  ///   _ => None,
+  /// };
+  /// assert_eq!(expr.eval(lookup), Ok(23));
+  ///
+  /// let err = expr.eval(|label| if label.name() == "WIDTH" { Some(0) } else { Some(1) });
+  /// assert_eq!(err, Err(ExprErr::DivZero));
+  ///
+  /// assert_eq!(expr.eval(|_| None), Err(ExprErr::Undefined(String::from("ROW"))));
+  /// ```
+  pub fn eval<F>(&self, mut lookup: F) -> Result<i64, Err>
+  where
+    F: FnMut(Label<'b>) -> Option<u16>,
+  {
+    let mut resolve = |label: Label<'b>| match lookup(label) {
+      Some(value) => Ok(Some(i64::from(value))),
+      None => Err(Err::Undefined(label.to_string())),
+    };
+
+    // All names are resolved, so the value is always known.
+    Ok(self.value(&mut resolve)?.unwrap_or_default())
+  }
+
+  /// Scope the local labels of the expression to a global label (see
+  /// [Label::scoped]).
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Expr;
+  /// use has::hack::Label;
+  /// use std::convert::TryFrom;
+  ///
+  /// let scope = Label::try_from("MULT".as_bytes()).unwrap();
+  /// let (expr, _, _) = Expr::read_from(".end-.loop".as_bytes()).unwrap();
+  /// let expr = expr.scoped(scope);
+  /// assert_eq!(expr.to_string(), ".end-.loop");
+  ///
+  /// let lookup = |label: Label| match label.to_string().as_str() {
+  ///   "MULT.loop" => Some(4),
+  ///   "MULT.end" => Some(10),
+  ///   _ => None,
+  /// };
+  /// assert_eq!(expr.eval(lookup), Ok(6));
+  /// ```
+  pub fn scoped(self, scope: Label<'b>) -> Self {
+    Self { scope: Some(scope), ..self }
+  }
+
+  /// Returns the global label that local labels in the expression are
+  /// scoped to, if any.
+  pub fn scope(&self) -> Option<Label<'b>> {
+    self.scope
+  }
+
+  /// Replace names in the expression.
+  ///
+  /// Returns the text of the expression with each name for which
+  /// `subst` returns a replacement replaced by it in parentheses, or
+  /// `None` if no name is replaced.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Expr;
+  ///
+  /// let (expr, _, _) = Expr::read_from("n*2+'n'+m".as_bytes()).unwrap();
+  /// let subst = |name: &str| expr.subst(|label| (label.name() == name).then(|| String::from("x+1")));
+  /// assert_eq!(subst("n").as_deref(), Some("(x+1)*2+'n'+m"));
+  /// assert_eq!(subst("k"), None);
+  /// ```
+  pub fn subst<F>(&self, mut subst: F) -> Option<String>
+  where
+    F: FnMut(Label<'b>) -> Option<String>,
+  {
+    let mut text = String::with_capacity(self.text.len());
+    let mut rest = self.text;
+    let mut replaced = false;
+
+    while let Some(c) = rest.chars().next() {
+      let len = match rest.as_bytes() {
+        [b'\'', _, b'\'', ..] => 3,
+        [b, ..] if Label::is_label_byte(*b) => {
+          rest.bytes().take_while(|&b| Label::is_label_byte(b)).count()
+        }
+        _ => c.len_utf8(),
+      };
+
+      let (word, rem) = rest.split_at(len);
+      rest = rem;
+
+      let label = Some(word)
+        .filter(|word| Label::is_label_start(word.as_bytes()[0]))
+        .and_then(|word| Label::try_from(word.as_bytes()).ok());
+
+      match label.and_then(&mut subst) {
+        Some(value) => {
+          text.push('(');
+          text.push_str(&value);
+          text.push(')');
+          replaced = true;
+        }
+        None => text.push_str(word),
+      }
+    }
+
+    replaced.then_some(text)
+  }
+
+  /// Create an expression from its text and scope.
+  ///
+  /// The text is not checked and must have been read with
+  /// [Expr::read_from] before.
+  pub(crate) fn from_parts(text: &'b str, scope: Option<Label<'b>>) -> Self {
+    Self { text, scope }
+  }
+
+  /// Evaluate the expression, resolving names with `resolve`.
+  fn value(
+    &self,
+    resolve: &mut dyn FnMut(Label<'b>) -> Result<Value, Err>,
+  ) -> Result<Value, Err> {
+    let scope = self.scope;
+    let resolve = &mut |label: Label<'b>| match scope {
+      Some(scope) => resolve(label.scoped(scope)),
+      None => resolve(label),
+    };
+    let mut eval = Eval { buf: self.text.as_bytes(), resolve, err: None };
+    let value = eval.sum().map_err(|_| Err::Invalid(String::from(self.text)))?;
+
+    if !eval.buf.is_empty() {
+      return Err(Err::Invalid(String::from(self.text)));
+    }
+
+    match eval.err {
+      Some(e) => Err(e),
+      None => Ok(value),
+    }
+  }
+
+  /// Returns the length of the expression at the start of `buf`.
+  fn extent(buf: Buf) -> usize {
+    let mut len = 0;
+
+    while let Some(&b) = buf.get(len) {
+      match b {
+        b'\'' => match buf[len + 1..].iter().position(|&b| b == b'\'' || b == b'\n') {
+          Some(end) if buf[len + 1 + end] == b'\'' => len += end + 2,
+          _ => len += 1,
+        },
+        b'/' if buf.get(len + 1) == Some(&b'/') => break,
+        _ if b.is_ascii_whitespace() => break,
+        _ => len += 1,
+      }
+    }
+
+    len
+  }
+}
+
+impl<'b> Eval<'_, 'b> {
+  /// A syntax error (the text is filled in by the caller).
+  fn invalid() -> Err {
+    Err::Invalid(String::new())
+  }
+
+  /// Record an error other than a syntax error, which makes the value
+  /// unknown.
+  fn fail(&mut self, err: Err) -> Value {
+    self.err.get_or_insert(err);
+    None
+  }
+
+  /// Consume `byte` if it is next.
+  fn eat(&mut self, byte: u8) -> bool {
+    match self.buf.split_first() {
+      Some((&b, rem)) if b == byte => {
+        self.buf = rem;
+        true
+      }
+      _ => false,
+    }
+  }
+
+  /// Apply a binary operator to two values.
+  fn apply(&mut self, op: u8, lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = match (lhs, rhs) {
+      (_, Some(0)) if op == b'/' || op == b'%' => return self.fail(Err::DivZero),
+      (Some(lhs), Some(rhs)) => (lhs, rhs),
+      _ => return None,
+    };
+
+    let value = match op {
+      b'+' => lhs.checked_add(rhs),
+      b'-' => lhs.checked_sub(rhs),
+      b'*' => lhs.checked_mul(rhs),
+      b'/' => lhs.checked_div(rhs),
+      _ => lhs.checked_rem(rhs),
+    };
+
+    value.or_else(|| self.fail(Err::Overflow))
+  }
+
+  /// `sum := product (('+' | '-') product)*`
+  fn sum(&mut self) -> Result<Value, Err> {
+    let mut value = self.product()?;
+
+    while let Some(&op @ (b'+' | b'-')) = self.buf.first() {
+      self.buf = &self.buf[1..];
+      let rhs = self.product()?;
+      value = self.apply(op, value, rhs);
+    }
+
+    Ok(value)
+  }
+
+  /// `product := atom (('*' | '/' | '%') atom)*`
+  fn product(&mut self) -> Result<Value, Err> {
+    let mut value = self.atom()?;
+
+    while let Some(&op @ (b'*' | b'/' | b'%')) = self.buf.first() {
+      self.buf = &self.buf[1..];
+      let rhs = self.atom()?;
+      value = self.apply(op, value, rhs);
+    }
+
+    Ok(value)
+  }
+
+  /// `atom := number | char | name | '(' sum ')'`
+  fn atom(&mut self) -> Result<Value, Err> {
+    let (&first, _) = self.buf.split_first().ok_or_else(Self::invalid)?;
+
+    if self.eat(b'(') {
+      let value = self.sum()?;
+      return if self.eat(b')') { Ok(value) } else { Err(Self::invalid()) };
+    }
+
+    if first == b'\'' {
+      return match self.buf {
+        [b'\'', c @ b' '..=b'~', b'\'', rem @ ..] => {
+          self.buf = rem;
+          Ok(Some(i64::from(*c)))
+        }
+        _ => Err(Self::invalid()),
+      };
+    }
+
+    let len = self.buf.iter().take_while(|&&b| Label::is_label_byte(b)).count();
+    let (word, rem) = self.buf.split_at(len);
+    self.buf = rem;
+
+    if first.is_ascii_digit() {
+      let text = std::str::from_utf8(word).map_err(|_| Self::invalid())?;
+      let value = match (text.get(..2), text.get(2..)) {
+        (Some("0x" | "0X"), Some(digits)) => i64::from_str_radix(digits, 16),
+        (Some("0b" | "0B"), Some(digits)) => i64::from_str_radix(digits, 2),
+        _ => text.parse(),
+      };
+
+      return value.map(Some).map_err(|_| Self::invalid());
+    }
+
+    if let Ok(sym) = Sym::try_from(word) {
+      return Ok(Some(i64::from(u16::from(sym))));
+    }
+
+    let label = Label::try_from(word).map_err(|_| Self::invalid())?;
+    Ok((self.resolve)(label).unwrap_or_else(|e| self.fail(e)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn constant(text: &str) -> Option<Result<i64, Err>> {
+    Expr::read_from(text.as_bytes()).unwrap().0.constant()
+  }
+
+  #[test]
+  fn precedence() {
+    assert_eq!(constant("1+2*3"), Some(Ok(7)));
+    assert_eq!(constant("(1+2)*3"), Some(Ok(9)));
+    assert_eq!(constant("10-4-3"), Some(Ok(3)));
+    assert_eq!(constant("17/5%2"), Some(Ok(1)));
+    assert_eq!(constant("2-3"), Some(Ok(-1)));
+    assert_eq!(constant("0xFFFF*0xFFFF*0xFFFF*0xFFFF"), Some(Err(Err::Overflow)));
+    assert_eq!(constant("1/(2-2)"), Some(Err(Err::DivZero)));
+    assert_eq!(constant("X/0"), Some(Err(Err::DivZero)));
+    assert_eq!(constant("X/(1-1)"), Some(Err(Err::DivZero)));
+  }
+
+  #[test]
+  fn literals() {
+    assert_eq!(constant("0x7fff"), Some(Ok(32767)));
+    assert_eq!(constant("0b1010"), Some(Ok(10)));
+    assert_eq!(constant("'a'+1"), Some(Ok(98)));
+    assert_eq!(constant("' '"), Some(Ok(32)));
+    assert_eq!(constant("'/'"), Some(Ok(47)));
+    assert_eq!(constant("R15+THAT"), Some(Ok(19)));
+  }
+
+  #[test]
+  fn errors() {
+    let invalid = |text: &str| Err(Err::Invalid(String::from(text)));
+    let read =
+      |text: &'static str| Expr::read_from(text.as_bytes()).map(|(e, _, _)| e.text);
+
+    assert_eq!(read("1+"), invalid("1+"));
+    assert_eq!(read("(1"), invalid("(1"));
+    assert_eq!(read("1)"), invalid("1)"));
+    assert_eq!(read("0x"), invalid("0x"));
+    assert_eq!(read("0b12"), invalid("0b12"));
+    assert_eq!(read("'ab'"), invalid("'ab'"));
+    assert_eq!(read("'a"), invalid("'a"));
+    assert_eq!(read("-1"), invalid("-1"));
+    assert_eq!(read("A+%B"), invalid("A+%B"));
+    assert_eq!(read("1/0+*"), invalid("1/0+*"));
+    assert_eq!(read("A+1//2"), Ok("A+1"));
+  }
+}
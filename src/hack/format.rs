@@ -0,0 +1,265 @@
+//! Formatting of HACK assembly source code.
+//!
+//! [format()] re-emits a HACK assembly program in a canonical style
+//! while preserving its comments, using a [lossless](Parser::lossless)
+//! parser:
+//!
+//! * Labels and directives start at the beginning of a line,
+//!   addresses, instructions and macro calls are indented by
+//!   [INDENT].
+//!
+//! * Every label, directive, address, instruction and macro call is
+//!   on a line of its own, and the arguments of directives and macro
+//!   calls are separated by `, `.
+//!
+//! * Mnemonics are spelled canonically (e.g. `DM=M+D` becomes
+//!   `MD=D+M`).
+//!
+//! * Comments on lines of their own stay at the beginning of the line
+//!   if they were there and are indented by [INDENT] otherwise.
+//!
+//! * Trailing comments of consecutive lines are aligned one space
+//!   after the longest of those lines.
+//!
+//! * Trailing whitespace and leading, trailing and repeated blank
+//!   lines are removed, and the output ends with a newline.
+
+use crate::hack::ElementKind;
+use crate::hack::Parser;
+use crate::hack::ProgErr;
+use crate::hack::TokenKind;
+use crate::Buf;
+
+/// Indentation of addresses and instructions.
+pub const INDENT: &str = "    ";
+
+/// A line of formatted output.
+enum Line<'b> {
+  /// An empty line.
+  Blank,
+
+  /// A comment on a line of its own, with its indentation.
+  Comment(&'static str, Buf<'b>),
+
+  /// A label, address or instruction with an optional trailing
+  /// comment.
+  Code(String, Option<Buf<'b>>),
+}
+
+/// Format a HACK assembly program.
+///
+/// Returns the errors of the program if it cannot be parsed. Since
+/// formatting does not resolve symbols, undefined and duplicate
+/// labels are not errors.
+///
+/// # Arguments
+///
+/// * `buf` - The HACK assembly source code.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::format;
+///
+/// let src = "// Add\n\n\n(LOOP)  @LOOP // Jump\n  DM=A+D;JMP\n".as_bytes();
+/// let out = format::format(src).unwrap();
+/// assert_eq!(out, "// Add\n\n(LOOP)\n    @LOOP // Jump\n    MD=D+A;JMP\n");
+///
+/// let errs = format::format("D=X".as_bytes()).err().unwrap();
+/// assert_eq!(errs.len(), 1);
+/// ```
+pub fn format(buf: Buf) -> Result<String, Vec<ProgErr>> {
+  let mut errs = Vec::new();
+  let mut lines = Vec::new();
+  let mut code = Vec::new();
+  let mut comment = None;
+  let mut indented = false;
+  let mut line_start = true;
+
+  for elem in Parser::from(buf).lossless() {
+    match elem.kind() {
+      ElementKind::Token(TokenKind::Label(label)) => code.push(format!("({})", label)),
+      ElementKind::Token(TokenKind::Addr(addr)) => {
+        code.push(format!("{}{}", INDENT, addr))
+      }
+      ElementKind::Token(TokenKind::Inst(inst)) => {
+        code.push(format!("{}{}", INDENT, inst))
+      }
+      ElementKind::Token(TokenKind::Call(call)) => {
+        code.push(format!("{}{}", INDENT, call))
+      }
+      ElementKind::Token(TokenKind::Directive(directive)) => {
+        code.push(directive.to_string())
+      }
+      ElementKind::Error(e) => errs.push(ProgErr::Asm(e.clone())),
+      ElementKind::Whitespace => indented |= line_start,
+      ElementKind::Comment => {
+        let text = elem.text();
+        let len =
+          text.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |i| i + 1);
+        comment = Some(&text[..len])
+      }
+      ElementKind::Newline => {
+        push_line(&mut lines, &mut code, comment.take(), indented);
+        indented = false;
+      }
+    }
+
+    line_start = *elem.kind() == ElementKind::Newline;
+  }
+
+  push_line(&mut lines, &mut code, comment, indented);
+
+  if !errs.is_empty() {
+    return Err(errs);
+  }
+
+  Ok(render(&lines))
+}
+
+/// Add the formatted tokens and comment of an input line to `lines`.
+///
+/// # Arguments
+///
+/// * `lines` - The formatted lines.
+///
+/// * `code` - The formatted tokens of the line, which are drained.
+///
+/// * `comment` - The comment of the line.
+///
+/// * `indented` - Whether the line starts with whitespace.
+fn push_line<'b>(
+  lines: &mut Vec<Line<'b>>,
+  code: &mut Vec<String>,
+  comment: Option<Buf<'b>>,
+  indented: bool,
+) {
+  let count = code.len();
+
+  for (i, text) in code.drain(..).enumerate() {
+    lines.push(Line::Code(text, if i + 1 == count { comment } else { None }));
+  }
+
+  if count > 0 {
+    return;
+  }
+
+  match comment {
+    Some(comment) => {
+      lines.push(Line::Comment(if indented { INDENT } else { "" }, comment))
+    }
+    None => lines.push(Line::Blank),
+  }
+}
+
+/// Render formatted lines, aligning trailing comments and removing
+/// redundant blank lines.
+fn render(lines: &[Line]) -> String {
+  let mut out = String::new();
+  let mut blank = false;
+
+  for (i, line) in lines.iter().enumerate() {
+    match line {
+      Line::Blank => {
+        blank = !out.is_empty();
+        continue;
+      }
+      _ if blank => out.push('\n'),
+      _ => {}
+    }
+
+    blank = false;
+
+    match line {
+      Line::Blank => {}
+      Line::Comment(indent, comment) => {
+        out.push_str(indent);
+        out.push_str(&String::from_utf8_lossy(comment));
+      }
+      Line::Code(text, None) => out.push_str(text),
+      Line::Code(text, Some(comment)) => {
+        let width = comment_column(lines, i);
+        out.push_str(&format!("{:width$} {}", text, String::from_utf8_lossy(comment)));
+      }
+    }
+
+    out.push('\n');
+  }
+
+  out
+}
+
+/// The width of the longest line with a trailing comment in the
+/// block of consecutive code lines around line `i`.
+fn comment_column(lines: &[Line], i: usize) -> usize {
+  let is_code = |line: &&Line| matches!(line, Line::Code(..));
+  let before = lines[..i].iter().rev().take_while(is_code);
+  let after = lines[i..].iter().take_while(is_code);
+
+  before
+    .chain(after)
+    .filter_map(|line| match line {
+      Line::Code(text, Some(_)) => Some(text.len()),
+      _ => None,
+    })
+    .max()
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn layout() {
+    let src = b"\n\n// Header\n\n\n   // Body\n@R0   \r\nD=M // D\n(END) @END\n0;JMP\n\n";
+    let expected =
+      "// Header\n\n    // Body\n    @R0\n    D=M // D\n(END)\n    @END\n    0;JMP\n";
+    assert_eq!(format(src).unwrap(), expected);
+  }
+
+  #[test]
+  fn alignment() {
+    let src = b"@R0 // R0\nM=D+1 // Increment\n@R1\n\nD=M // Read\n";
+    let expected =
+      "    @R0   // R0\n    M=D+1 // Increment\n    @R1\n\n    D=M // Read\n";
+    assert_eq!(format(src).unwrap(), expected);
+  }
+
+  #[test]
+  fn mnemonics() {
+    let src = b"AMD=M|D\nDA=1+A;JGE\nMA=A&D";
+    let expected = "    AMD=D|M\n    AD=A+1;JGE\n    AM=D&A\n";
+    assert_eq!(format(src).unwrap(), expected);
+  }
+
+  #[test]
+  fn macros() {
+    let src =
+      b".macro  COPY from,to\n@from\nD=M\nSTORE   to\n.endm\nCOPY R0,R1 // Copy\n";
+    let expected = ".macro COPY from, to\n    @from\n    D=M\n    STORE to\n.endm\n    \
+                    COPY R0, R1 // Copy\n";
+    assert_eq!(format(src).unwrap(), expected);
+  }
+
+  #[test]
+  fn exprs() {
+    let src = b".equ  ROW\t2 // Row\n@SCREEN+ROW*32\n@' '  // Space\n@0x4000";
+    let expected =
+      ".equ ROW 2 // Row\n    @SCREEN+ROW*32\n    @' '   // Space\n    @0x4000\n";
+    assert_eq!(format(src).unwrap(), expected);
+  }
+
+  #[test]
+  fn idempotent() {
+    let src = include_bytes!("../../tests/programs/Max.asm");
+    let once = format(src).unwrap();
+    assert_eq!(format(once.as_bytes()).unwrap(), once);
+  }
+
+  #[test]
+  fn errors() {
+    let errs = format(b"@-1\nD=M\n(FOO)\n(FOO)\n0;JPM").err().unwrap();
+    assert_eq!(errs.len(), 2);
+  }
+}
@@ -289,4 +289,40 @@ impl Jump {
   pub fn is_null(&self) -> bool {
     matches!(self, Jump::Null)
   }
+
+  /// Whether the [jump](Jump) is taken for the output of a
+  /// computation.
+  ///
+  /// The output `value` is interpreted as a 16-bit two's complement
+  /// integer as done by the HACK CPU.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Jump;
+  ///
+  /// assert!(!Jump::Null.eval(0));
+  /// assert!(Jump::JGT.eval(1));
+  /// assert!(!Jump::JGT.eval(0xFFFF));
+  /// assert!(Jump::JEQ.eval(0));
+  /// assert!(Jump::JGE.eval(0));
+  /// assert!(Jump::JLT.eval(0x8000));
+  /// assert!(Jump::JNE.eval(0xFFFF));
+  /// assert!(Jump::JLE.eval(0));
+  /// assert!(Jump::JMP.eval(42));
+  /// ```
+  pub fn eval(&self, value: u16) -> bool {
+    let value = value as i16;
+
+    match self {
+      Jump::Null => false,
+      Jump::JGT => value > 0,
+      Jump::JEQ => value == 0,
+      Jump::JGE => value >= 0,
+      Jump::JLT => value < 0,
+      Jump::JNE => value != 0,
+      Jump::JLE => value <= 0,
+      Jump::JMP => true,
+    }
+  }
 }
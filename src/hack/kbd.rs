@@ -0,0 +1,354 @@
+//! The memory-mapped keyboard of the HACK computer.
+//!
+//! The keyboard is mapped to the data memory word at [KBD](Sym::KBD),
+//! which holds the code of the currently pressed key or `0` when no
+//! key is pressed. Printable characters use their ASCII codes and
+//! special keys use the codes defined in this module (e.g.
+//! [NEWLINE]).
+//!
+//! [Keyboard] drives the keyboard from a script of [events](Event)
+//! so that interactive programs can be run deterministically.
+
+use crate::hack::Sym;
+use crate::Buf;
+use crate::Loc;
+use derive_more::Display;
+
+/// Key code of the newline (enter) key.
+pub const NEWLINE: u16 = 128;
+
+/// Key code of the backspace key.
+pub const BACKSPACE: u16 = 129;
+
+/// Key code of the left arrow key.
+pub const LEFT: u16 = 130;
+
+/// Key code of the up arrow key.
+pub const UP: u16 = 131;
+
+/// Key code of the right arrow key.
+pub const RIGHT: u16 = 132;
+
+/// Key code of the down arrow key.
+pub const DOWN: u16 = 133;
+
+/// Key code of the home key.
+pub const HOME: u16 = 134;
+
+/// Key code of the end key.
+pub const END: u16 = 135;
+
+/// Key code of the page up key.
+pub const PAGE_UP: u16 = 136;
+
+/// Key code of the page down key.
+pub const PAGE_DOWN: u16 = 137;
+
+/// Key code of the insert key.
+pub const INSERT: u16 = 138;
+
+/// Key code of the delete key.
+pub const DELETE: u16 = 139;
+
+/// Key code of the escape key.
+pub const ESC: u16 = 140;
+
+/// Key code of the F1 key. The codes of F2 to F12 follow
+/// consecutively.
+pub const F1: u16 = 141;
+
+/// Returns the key code for a key name.
+///
+/// Key names are `NEWLINE`, `BACKSPACE`, `LEFT`, `UP`, `RIGHT`,
+/// `DOWN`, `HOME`, `END`, `PAGEUP`, `PAGEDOWN`, `INSERT`, `DELETE`,
+/// `ESC`, `F1` to `F12`, `SPACE` and `RELEASE` (no key pressed).
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::kbd;
+///
+/// assert_eq!(kbd::key_code("NEWLINE"), Some(kbd::NEWLINE));
+/// assert_eq!(kbd::key_code("F12"), Some(152));
+/// assert_eq!(kbd::key_code("RELEASE"), Some(0));
+/// assert_eq!(kbd::key_code("F13"), None);
+/// ```
+pub fn key_code(name: &str) -> Option<u16> {
+  let code = match name {
+    "NEWLINE" => NEWLINE,
+    "BACKSPACE" => BACKSPACE,
+    "LEFT" => LEFT,
+    "UP" => UP,
+    "RIGHT" => RIGHT,
+    "DOWN" => DOWN,
+    "HOME" => HOME,
+    "END" => END,
+    "PAGEUP" => PAGE_UP,
+    "PAGEDOWN" => PAGE_DOWN,
+    "INSERT" => INSERT,
+    "DELETE" => DELETE,
+    "ESC" => ESC,
+    "SPACE" => u16::from(b' '),
+    "RELEASE" => 0,
+    _ => match name.strip_prefix('F')?.parse::<u16>() {
+      Ok(n @ 1..=12) => F1 + n - 1,
+      _ => return None,
+    },
+  };
+
+  Some(code)
+}
+
+/// Errors when parsing a keyboard script.
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum Err {
+  /// Invalid cycle number.
+  #[display(fmt = "Invalid cycle `{}` at {}", _0, _1)]
+  InvalidCycle(String, Loc),
+
+  /// Invalid key code, name or character.
+  #[display(fmt = "Invalid key `{}` at {}", _0, _1)]
+  InvalidKey(String, Loc),
+
+  /// An event is missing its key.
+  #[display(fmt = "Missing key at {}", _0)]
+  MissingKey(Loc),
+}
+
+/// A keyboard event.
+///
+/// From clock cycle `cycle` on, the keyboard holds `key` (or no key
+/// if `key` is `0`).
+#[derive(new, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+  /// The clock cycle at which the event occurs.
+  cycle: u64,
+
+  /// The key code.
+  key: u16,
+}
+
+impl Event {
+  /// Returns the clock cycle at which the event occurs.
+  pub fn cycle(&self) -> u64 {
+    self.cycle
+  }
+
+  /// Returns the key code.
+  pub fn key(&self) -> u16 {
+    self.key
+  }
+}
+
+/// A keyboard driven by a script of events.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::kbd;
+/// use has::hack::kbd::Event;
+/// use has::hack::Keyboard;
+///
+/// let mut keyboard = Keyboard::new(vec![Event::new(5, 0), Event::new(2, kbd::UP)]);
+/// assert_eq!(keyboard.poll(0), None);
+/// assert_eq!(keyboard.poll(2), Some(kbd::UP));
+/// assert_eq!(keyboard.poll(3), None);
+/// assert_eq!(keyboard.poll(7), Some(0));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keyboard {
+  /// The events, ordered by clock cycle.
+  events: Vec<Event>,
+
+  /// The index of the next pending event.
+  next: usize,
+}
+
+impl Keyboard {
+  /// Create a keyboard from a list of events.
+  ///
+  /// The events are ordered by their clock cycle. Events at the same
+  /// clock cycle keep their relative order.
+  ///
+  /// # Arguments
+  ///
+  /// * `events` - The keyboard events.
+  pub fn new(mut events: Vec<Event>) -> Self {
+    events.sort_by_key(|event| event.cycle);
+    Self { events, next: 0 }
+  }
+
+  /// Parse a keyboard script.
+  ///
+  /// Each non-empty line contains a clock cycle followed by a key,
+  /// which is either a decimal key code, a [key name](key_code) or a
+  /// quoted character (e.g. `'a'`). Comments start with `//`.
+  ///
+  /// # Arguments
+  ///
+  /// * `buf` - The contents of the keyboard script.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::kbd;
+  /// use has::hack::Keyboard;
+  ///
+  /// let keyboard = Keyboard::parse("0 'a'\n10 LEFT // Move\n20 0".as_bytes()).unwrap();
+  /// let keys = keyboard.events().iter().map(|e| e.key()).collect::<Vec<_>>();
+  /// assert_eq!(keys, [97, kbd::LEFT, 0]);
+  /// ```
+  pub fn parse(buf: Buf) -> Result<Self, Err> {
+    let mut events = Vec::new();
+    let mut index = 0;
+
+    for line in buf.split(|&b| b == b'\n') {
+      let line_index = index;
+      index += line.len() + 1;
+
+      let line = String::from_utf8_lossy(line);
+      let line = line.split("//").next().unwrap_or_default();
+      let line = line.trim();
+
+      if line.is_empty() {
+        continue;
+      }
+
+      let loc = Loc::from_index(buf, line_index);
+      let (cycle, key) =
+        line.split_once(char::is_whitespace).ok_or(Err::MissingKey(loc))?;
+      let cycle =
+        cycle.parse::<u64>().map_err(|_| Err::InvalidCycle(String::from(cycle), loc))?;
+      let key = key.trim_start();
+
+      let code = match key.as_bytes() {
+        [b'\'', c, b'\''] if c.is_ascii_graphic() || *c == b' ' => Some(u16::from(*c)),
+        _ => key.parse::<u16>().ok().or_else(|| key_code(key)),
+      };
+
+      let code = code.ok_or_else(|| Err::InvalidKey(String::from(key), loc))?;
+      events.push(Event::new(cycle, code));
+    }
+
+    Ok(Self::new(events))
+  }
+
+  /// Returns the events, ordered by clock cycle.
+  pub fn events(&self) -> &[Event] {
+    &self.events
+  }
+
+  /// Consume the events that occur up to clock cycle `cycle`.
+  ///
+  /// Returns the key of the last consumed event, or `None` if no
+  /// event occurred since the previous call.
+  ///
+  /// # Arguments
+  ///
+  /// * `cycle` - The current clock cycle.
+  pub fn poll(&mut self, cycle: u64) -> Option<u16> {
+    let pending = &self.events[self.next..];
+    let count = pending.iter().take_while(|event| event.cycle <= cycle).count();
+    self.next += count;
+    pending[..count].last().map(|event| event.key)
+  }
+
+  /// Apply the events that occur up to clock cycle `cycle` to the
+  /// data memory `ram`.
+  ///
+  /// # Arguments
+  ///
+  /// * `cycle` - The current clock cycle.
+  ///
+  /// * `ram` - The data memory of the HACK computer.
+  pub fn update(&mut self, cycle: u64, ram: &mut [u16]) {
+    if let Some(key) = self.poll(cycle) {
+      ram[usize::from(u16::from(Sym::KBD))] = key;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn key_codes() {
+    let names = [
+      "NEWLINE",
+      "BACKSPACE",
+      "LEFT",
+      "UP",
+      "RIGHT",
+      "DOWN",
+      "HOME",
+      "END",
+      "PAGEUP",
+      "PAGEDOWN",
+      "INSERT",
+      "DELETE",
+      "ESC",
+      "F1",
+      "F2",
+      "F3",
+      "F4",
+      "F5",
+      "F6",
+      "F7",
+      "F8",
+      "F9",
+      "F10",
+      "F11",
+      "F12",
+    ];
+
+    for (code, name) in (128..).zip(names.iter()) {
+      assert_eq!(key_code(name), Some(code), "{}", name);
+    }
+
+    assert_eq!(key_code("SPACE"), Some(32));
+    assert_eq!(key_code("F0"), None);
+    assert_eq!(key_code("left"), None);
+  }
+
+  #[test]
+  fn parse() {
+    let buf = "// Script\n\n5 ' '\n1 65\n3 ESC\n".as_bytes();
+    let keyboard = Keyboard::parse(buf).unwrap();
+    assert_eq!(
+      keyboard.events(),
+      [Event::new(1, 65), Event::new(3, ESC), Event::new(5, u16::from(b' '))]
+    );
+
+    assert_eq!(
+      Keyboard::parse(b"x 65"),
+      Err(Err::InvalidCycle("x".into(), Loc::new(1, 1)))
+    );
+    assert_eq!(
+      Keyboard::parse(b"\n1 FOO"),
+      Err(Err::InvalidKey("FOO".into(), Loc::new(2, 1)))
+    );
+    assert_eq!(
+      Keyboard::parse(b"1 'ab'"),
+      Err(Err::InvalidKey("'ab'".into(), Loc::new(1, 1)))
+    );
+    assert_eq!(Keyboard::parse(b"1"), Err(Err::MissingKey(Loc::new(1, 1))));
+  }
+
+  #[test]
+  fn poll() {
+    let mut keyboard =
+      Keyboard::new(vec![Event::new(3, 1), Event::new(3, 2), Event::new(4, 0)]);
+    let mut ram = vec![7; 0x6001];
+
+    keyboard.update(2, &mut ram);
+    assert_eq!(ram[0x6000], 7);
+
+    keyboard.update(3, &mut ram);
+    assert_eq!(ram[0x6000], 2);
+
+    keyboard.update(10, &mut ram);
+    assert_eq!(ram[0x6000], 0);
+    assert_eq!(keyboard.poll(100), None);
+  }
+}
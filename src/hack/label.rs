@@ -4,6 +4,9 @@ use crate::hack::Sym;
 use crate::Buf;
 use derive_more::Display;
 use std::convert::TryFrom;
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
 
 /// User defined labels as defined by the HACK assembly reference.
 ///
@@ -19,19 +22,210 @@ use std::convert::TryFrom;
 /// let label = Label::try_from("label".as_bytes()).unwrap();
 /// assert_eq!(label.name(), "label");
 /// ```
-#[derive(Display, Debug, PartialEq, Eq, Hash)]
-#[display(fmt = "{}", _0)]
-pub struct Label<'b>(&'b str);
+///
+/// Labels starting with a `.` are local: they are
+/// [scoped](Label::scoped) to the preceding global label. Labels made
+/// of digits are [anonymous](Label::anonymous).
+#[derive(Clone, Copy)]
+pub struct Label<'b>(Name<'b>);
+
+/// Maximum length of a generated label name (`LABEL_32767`).
+const GENERATED_LEN: usize = 11;
+
+/// Storage of a [Label] name.
+#[derive(Clone, Copy)]
+enum Name<'b> {
+  /// A name referring to the input buffer.
+  Buf(&'b str),
 
-impl<'b> Copy for Label<'b> {}
+  /// A generated name stored inline, with its length.
+  Generated([u8; GENERATED_LEN], u8),
 
-/// Clone a [Label].
+  /// A local name (e.g. `.loop`) qualified with the global label it
+  /// is scoped to (e.g. `MULT`).
+  Scoped(&'b str, &'b str),
+}
+
+/// Anonymous labels and references to them.
 ///
-/// This is a shallow clone. Only the reference to the label name
-/// string is copied, not the string itself.
-impl<'b> Clone for Label<'b> {
-  fn clone(&self) -> Self {
-    *self
+/// An anonymous label is declared with a number (e.g. `(1)`), which
+/// can be reused any number of times. `@1f` refers to the next
+/// declaration of `(1)` and `@1b` to the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anon {
+  /// Declaration of an anonymous label (e.g. `(1)`).
+  Decl(u16),
+
+  /// Reference to the next anonymous label (e.g. `@1f`).
+  Forward(u16),
+
+  /// Reference to the previous anonymous label (e.g. `@1b`).
+  Backward(u16),
+}
+
+impl<'b> Label<'b> {
+  /// Create a generated label named `LABEL_<addr>` for a ROM address.
+  ///
+  /// Generated labels are used when recovering labels from
+  /// disassembled programs, where there is no source to refer to.
+  /// They compare equal to parsed labels with the same name.
+  ///
+  /// # Arguments
+  ///
+  /// * `addr` - The ROM address the label points to.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Label;
+  /// use std::convert::TryFrom;
+  ///
+  /// let label = Label::generated(10);
+  /// assert_eq!(label.name(), "LABEL_10");
+  /// assert_eq!(label, Label::try_from("LABEL_10".as_bytes()).unwrap());
+  /// ```
+  pub fn generated(addr: u16) -> Self {
+    let name = format!("LABEL_{}", addr);
+    let mut bytes = [0; GENERATED_LEN];
+    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    Self(Name::Generated(bytes, name.len() as u8))
+  }
+
+  /// Create an anonymous label or a reference to one from a buffer.
+  ///
+  /// Returns `None` if `buf` is neither a number (e.g. `1`) nor a
+  /// number followed by `f` or `b` (e.g. `1f`).
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Anon;
+  /// use has::hack::Label;
+  ///
+  /// let label = Label::anonymous("1".as_bytes()).unwrap();
+  /// assert_eq!(label.anon(), Some(Anon::Decl(1)));
+  ///
+  /// let label = Label::anonymous("12b".as_bytes()).unwrap();
+  /// assert_eq!((label.name(), label.anon()), ("12b", Some(Anon::Backward(12))));
+  ///
+  /// assert!(Label::anonymous("1x".as_bytes()).is_none());
+  /// assert!(Label::anonymous("f".as_bytes()).is_none());
+  /// ```
+  pub fn anonymous(buf: Buf<'b>) -> Option<Self> {
+    let name = std::str::from_utf8(buf).ok()?;
+    let label = Self(Name::Buf(name));
+    label.anon().map(|_| label)
+  }
+
+  /// Returns the anonymous label or reference this label stands for,
+  /// if any.
+  pub fn anon(&self) -> Option<Anon> {
+    let name = match self.0 {
+      Name::Buf(name) => name,
+      _ => return None,
+    };
+
+    let (num, anon): (_, fn(u16) -> Anon) = match name.as_bytes().last()? {
+      b'f' => (&name[..name.len() - 1], Anon::Forward),
+      b'b' => (&name[..name.len() - 1], Anon::Backward),
+      _ => (name, Anon::Decl),
+    };
+
+    if num.is_empty() || !num.bytes().all(|b| b.is_ascii_digit()) {
+      return None;
+    }
+
+    num.parse().ok().map(anon)
+  }
+
+  /// Whether this is a local label (e.g. `.loop`) that has not been
+  /// [scoped](Label::scoped) yet.
+  pub fn is_local(&self) -> bool {
+    matches!(self.0, Name::Buf(name) if name.starts_with('.'))
+  }
+
+  /// Scope a local label to a global label.
+  ///
+  /// The scoped label is named after both (e.g. `.loop` scoped to
+  /// `MULT` is `MULT.loop`). Other labels are returned unchanged.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Label;
+  /// use std::convert::TryFrom;
+  ///
+  /// let label = |name: &'static str| Label::try_from(name.as_bytes()).unwrap();
+  ///
+  /// let scoped = label(".loop").scoped(label("MULT"));
+  /// assert_eq!(scoped, label("MULT.loop"));
+  /// assert_eq!((scoped.to_string().as_str(), scoped.name()), ("MULT.loop", ".loop"));
+  /// assert_eq!(scoped.scope(), Some("MULT"));
+  ///
+  /// assert_eq!(label("END").scoped(label("MULT")), label("END"));
+  /// ```
+  pub fn scoped(self, scope: Label<'b>) -> Self {
+    match (self.0, scope.0) {
+      (Name::Buf(name), Name::Buf(scope)) if self.is_local() => {
+        Self(Name::Scoped(scope, name))
+      }
+      _ => self,
+    }
+  }
+
+  /// Create a label from its name and, for a [scoped](Label::scoped)
+  /// local label, the name of the global label it is scoped to.
+  ///
+  /// The names are not checked.
+  pub(crate) fn from_parts(scope: Option<&'b str>, name: &'b str) -> Self {
+    match scope {
+      Some(scope) => Self(Name::Scoped(scope, name)),
+      None => Self(Name::Buf(name)),
+    }
+  }
+
+  /// Returns the name of the global label a local label is scoped to.
+  pub fn scope(&self) -> Option<&'b str> {
+    match self.0 {
+      Name::Scoped(scope, _) => Some(scope),
+      _ => None,
+    }
+  }
+
+  /// Returns the bytes of the full name of a label.
+  fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+    self.scope().unwrap_or("").bytes().chain(self.name().bytes())
+  }
+}
+
+impl PartialEq for Label<'_> {
+  fn eq(&self, other: &Self) -> bool {
+    let len = |label: &Label| label.scope().map_or(0, str::len) + label.name().len();
+    len(self) == len(other) && self.bytes().eq(other.bytes())
+  }
+}
+
+impl Eq for Label<'_> {}
+
+impl Hash for Label<'_> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    // Hash byte by byte, so that scoped labels hash like the
+    // equivalent unscoped ones with any hasher.
+    self.bytes().for_each(|b| state.write_u8(b));
+    state.write_u8(0xff);
+  }
+}
+
+impl fmt::Debug for Label<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Label").field(&self.to_string()).finish()
+  }
+}
+
+impl fmt::Display for Label<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.scope().unwrap_or(""))?;
+    f.write_str(self.name())
   }
 }
 
@@ -61,7 +255,11 @@ impl Label<'_> {
     byte.is_ascii_alphanumeric() || Label::is_label_sym(byte)
   }
 
-  /// Returns the name byte slice of a [Label].
+  /// Returns the name of a [Label].
+  ///
+  /// For a [scoped](Label::scoped) local label, this is the local
+  /// name as written in the source (e.g. `.loop`). The full name is
+  /// given by [Display](fmt::Display).
   ///
   /// # Examples
   ///
@@ -72,7 +270,11 @@ impl Label<'_> {
   /// let label = Label::try_from("label".as_bytes()).unwrap();
   /// assert_eq!(label.name(), "label");
   pub fn name(&self) -> &str {
-    self.0
+    match &self.0 {
+      Name::Buf(name) | Name::Scoped(_, name) => name,
+      Name::Generated(bytes, len) => std::str::from_utf8(&bytes[..usize::from(*len)])
+        .expect("Generated label names are ASCII"),
+    }
   }
 }
 
@@ -175,6 +377,6 @@ impl<'b> TryFrom<Buf<'b>> for Label<'b> {
       return Err(Err::Sym(sym));
     }
 
-    Ok(Self(unsafe { std::str::from_utf8_unchecked(buf) }))
+    Ok(Self(Name::Buf(unsafe { std::str::from_utf8_unchecked(buf) })))
   }
 }
@@ -0,0 +1,1014 @@
+//! A Language Server Protocol server for HACK assembly.
+//!
+//! [Server] speaks the [Language Server
+//! Protocol](https://microsoft.github.io/language-server-protocol/)
+//! over a reader and a writer (e.g. standard input and output) and
+//! provides the following features for HACK assembly documents:
+//!
+//! * Diagnostics for all errors in a document (see
+//!   [Prog::from_sources] and [Diagnostic]), or its warnings if it
+//!   has none (see [Prog::warnings]). Files included by
+//!   `file://` documents are read from open documents or from disk.
+//! * Go-to-definition and find-references for labels and variables.
+//! * Hover showing the encoded 16-bit value of an instruction and the
+//!   address of a label or variable.
+//! * Completion of destination, computation and jump mnemonics,
+//!   pseudo-instructions, predefined symbols, labels and variables.
+//!
+//! Documents are synchronized in full on every change. Positions are
+//! counted in UTF-16 code units, as required by the protocol, and
+//! converted from and to the byte columns of [locations](Loc).
+
+use crate::hack::macros;
+use crate::hack::macros::Macros;
+use crate::hack::macros::PSEUDOS;
+use crate::hack::source;
+use crate::hack::Addr;
+use crate::hack::Comp;
+use crate::hack::Dest;
+use crate::hack::Diagnostic;
+use crate::hack::Directive;
+use crate::hack::Jump;
+use crate::hack::Label;
+use crate::hack::Parser;
+use crate::hack::Prog;
+use crate::hack::Source;
+use crate::hack::Sym;
+use crate::hack::TokenKind;
+use crate::Loc;
+use derive_more::Display;
+use derive_more::From;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap as Map;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Predefined symbols offered as completions.
+const SYMS: [Sym; 23] = [
+  Sym::SP,
+  Sym::LCL,
+  Sym::ARG,
+  Sym::THIS,
+  Sym::THAT,
+  Sym::R0,
+  Sym::R1,
+  Sym::R2,
+  Sym::R3,
+  Sym::R4,
+  Sym::R5,
+  Sym::R6,
+  Sym::R7,
+  Sym::R8,
+  Sym::R9,
+  Sym::R10,
+  Sym::R11,
+  Sym::R12,
+  Sym::R13,
+  Sym::R14,
+  Sym::R15,
+  Sym::SCREEN,
+  Sym::KBD,
+];
+
+/// JSON-RPC error code for requests after a shutdown.
+const INVALID_REQUEST: i64 = -32600;
+
+/// JSON-RPC error code for unknown methods.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// LSP diagnostic severity for errors.
+const ERROR: u8 = 1;
+
+/// LSP diagnostic severity for warnings.
+const WARNING: u8 = 2;
+
+/// LSP completion item kind for keywords.
+const KIND_KEYWORD: u8 = 14;
+
+/// LSP completion item kind for constants.
+const KIND_CONSTANT: u8 = 21;
+
+/// LSP completion item kind for variables.
+const KIND_VARIABLE: u8 = 6;
+
+/// LSP completion item kind for references.
+const KIND_REFERENCE: u8 = 18;
+
+/// LSP completion item kind for functions.
+const KIND_FUNCTION: u8 = 3;
+
+/// Errors when running the server.
+#[derive(Display, Debug, From)]
+pub enum Err {
+  /// Reading or writing messages failed.
+  #[display(fmt = "IO error: {}", _0)]
+  Io(io::Error),
+
+  /// A message is not valid JSON.
+  #[display(fmt = "Invalid message: {}", _0)]
+  Json(serde_json::Error),
+
+  /// A message header is missing or invalid.
+  #[display(fmt = "Invalid message header `{}`", _0)]
+  #[from(ignore)]
+  Header(String),
+}
+
+/// A Language Server Protocol server for HACK assembly.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::LspServer;
+/// use serde_json::json;
+///
+/// let mut server = LspServer::new();
+/// let open = json!({
+///   "jsonrpc": "2.0",
+///   "method": "textDocument/didOpen",
+///   "params": {"textDocument": {"uri": "file:///a.asm", "text": "D;JPM"}},
+/// });
+///
+/// let out = server.handle(&open);
+/// assert_eq!(out[0]["method"], "textDocument/publishDiagnostics");
+/// assert_eq!(out[0]["params"]["diagnostics"][0]["code"], "E0005");
+/// ```
+#[derive(Debug, Default)]
+pub struct Server {
+  /// The contents of the open documents by URI.
+  docs: Map<String, String>,
+
+  /// Whether the client requested a shutdown.
+  shutdown: bool,
+
+  /// Whether the client requested the server to exit.
+  exit: bool,
+}
+
+impl Server {
+  /// Create a server without open documents.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns whether the client requested the server to exit.
+  pub fn exited(&self) -> bool {
+    self.exit
+  }
+
+  /// Serve messages from `reader` and write responses and
+  /// notifications to `writer` until the client requests the server
+  /// to exit or closes the connection.
+  ///
+  /// Messages are framed with a `Content-Length` header as specified
+  /// by the protocol.
+  ///
+  /// # Arguments
+  ///
+  /// * `reader` - The stream to read messages from.
+  ///
+  /// * `writer` - The stream to write messages to.
+  pub fn run<R: BufRead, W: Write>(
+    &mut self,
+    mut reader: R,
+    mut writer: W,
+  ) -> Result<(), Err> {
+    while !self.exit {
+      let msg = match read_message(&mut reader)? {
+        Some(msg) => msg,
+        None => break,
+      };
+
+      for out in self.handle(&msg) {
+        let body = out.to_string();
+        write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        writer.flush()?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Handle a message from the client.
+  ///
+  /// Returns the response, if the message is a request, and any
+  /// notifications to send to the client.
+  ///
+  /// # Arguments
+  ///
+  /// * `msg` - The JSON-RPC message.
+  pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+    let method = msg["method"].as_str().unwrap_or_default();
+    let params = &msg["params"];
+    let id = msg.get("id").cloned();
+
+    if self.shutdown && method != "exit" {
+      return match id {
+        Some(id) => vec![json!({
+          "jsonrpc": "2.0",
+          "id": id,
+          "error": {"code": INVALID_REQUEST, "message": "The server is shutting down"},
+        })],
+        None => Vec::new(),
+      };
+    }
+
+    let result = match method {
+      "initialize" => Some(json!({
+        "capabilities": {
+          "textDocumentSync": 1,
+          "definitionProvider": true,
+          "referencesProvider": true,
+          "hoverProvider": true,
+          "completionProvider": {"triggerCharacters": ["@", "=", ";"]},
+        },
+        "serverInfo": {"name": "has", "version": env!("CARGO_PKG_VERSION")},
+      })),
+      "shutdown" => {
+        self.shutdown = true;
+        Some(Value::Null)
+      }
+      "exit" => {
+        self.exit = true;
+        return Vec::new();
+      }
+      "textDocument/didOpen" => {
+        let uri = uri(params);
+        let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+        self.docs.insert(uri.clone(), String::from(text));
+        return vec![self.diagnostics(&uri)];
+      }
+      "textDocument/didChange" => {
+        let uri = uri(params);
+        let changes = params["contentChanges"].as_array();
+
+        if let Some(text) =
+          changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str())
+        {
+          self.docs.insert(uri.clone(), String::from(text));
+        }
+
+        return vec![self.diagnostics(&uri)];
+      }
+      "textDocument/didClose" => {
+        let uri = uri(params);
+        self.docs.remove(&uri);
+        return vec![notification(
+          "textDocument/publishDiagnostics",
+          json!({"uri": uri, "diagnostics": []}),
+        )];
+      }
+      "textDocument/definition" => Some(self.definition(params)),
+      "textDocument/references" => Some(self.references(params)),
+      "textDocument/hover" => Some(self.hover(params)),
+      "textDocument/completion" => Some(self.completion(params)),
+      _ => None,
+    };
+
+    match (id, result) {
+      (Some(id), Some(result)) => {
+        vec![json!({"jsonrpc": "2.0", "id": id, "result": result})]
+      }
+      (Some(id), None) => vec![json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": METHOD_NOT_FOUND, "message": format!("Unknown method `{}`", method)},
+      })],
+      (None, _) => Vec::new(),
+    }
+  }
+
+  /// Returns the contents of the document referred to by `params` and
+  /// the byte index of the position in it.
+  fn doc_at<'s>(&'s self, params: &Value) -> Option<(&'s str, usize)> {
+    let text = self.docs.get(&uri(params))?;
+    let pos = &params["position"];
+    let line = pos["line"].as_u64()? as usize;
+    let character = pos["character"].as_u64()? as usize;
+    Some((text, index_of(text, line, character)?))
+  }
+
+  /// Load the source files of the program of a document: the
+  /// document itself, followed by the files it includes.
+  ///
+  /// Only `file://` documents can include files. Included files that
+  /// cannot be read are left out (see [source::load]). Open documents
+  /// are matched to included files by their paths, so that URIs that
+  /// encode the same path differently refer to the same file.
+  fn sources(&self, uri: &str) -> Vec<(PathBuf, Vec<u8>)> {
+    let text = self.docs.get(uri).map(String::as_str).unwrap_or_default();
+    let doc = || vec![(PathBuf::new(), text.as_bytes().to_vec())];
+
+    let path = match path_of(uri) {
+      Some(path) => path,
+      None => return doc(),
+    };
+
+    let read = |path: &Path| {
+      let open = self
+        .docs
+        .iter()
+        .find(|(uri, _)| path_of(uri).is_some_and(|doc| source::normalize(&doc) == path));
+
+      match open {
+        Some((_, text)) => Ok(text.as_bytes().to_vec()),
+        None => fs::read(path),
+      }
+    };
+
+    source::load(&[path], read).unwrap_or_else(|_| doc())
+  }
+
+  /// Build the diagnostics notification for a document, with the
+  /// [warnings](Prog::warnings) of the program if it has no errors.
+  ///
+  /// Errors in included files are not reported.
+  fn diagnostics(&self, uri: &str) -> Value {
+    let text = self.docs.get(uri).map(String::as_str).unwrap_or_default();
+    let files = self.sources(uri);
+    let sources =
+      files.iter().map(|(path, buf)| Source::new(path, buf)).collect::<Vec<_>>();
+    let (errs, severity) = match Prog::from_sources(&sources) {
+      Ok(prog) => (prog.warnings(), WARNING),
+      Err(errs) => (errs, ERROR),
+    };
+
+    let diagnostics = errs
+      .iter()
+      .filter(|err| err.loc().map_or(true, |loc| loc.file() == 0))
+      .map(|err| {
+        let diag = Diagnostic::new(text.as_bytes(), err);
+        let message = match diag.hint() {
+          Some(hint) => format!("{}\nhelp: {}", diag.message(), hint),
+          None => String::from(diag.message()),
+        };
+
+        json!({
+          "range": range(text, diag.loc().unwrap_or_default(), diag.len()),
+          "severity": severity,
+          "code": diag.code(),
+          "source": "has",
+          "message": message,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    notification(
+      "textDocument/publishDiagnostics",
+      json!({"uri": uri, "diagnostics": diagnostics}),
+    )
+  }
+
+  /// Find the declaration of the label at a position, or the first
+  /// use of the variable at a position.
+  fn definition(&self, params: &Value) -> Value {
+    let (text, index) = match self.doc_at(params) {
+      Some(doc) => doc,
+      None => return Value::Null,
+    };
+
+    let name = match name_at(text, index) {
+      Some(name) => name,
+      None => return Value::Null,
+    };
+
+    let symbols = symbols(text);
+    let decl = symbols.iter().find(|s| s.decl && s.name == name);
+    let first = symbols.iter().find(|s| s.name == name);
+
+    match decl.or(first) {
+      Some(symbol) => {
+        json!({"uri": uri(params), "range": range(text, symbol.loc, symbol.len)})
+      }
+      None => Value::Null,
+    }
+  }
+
+  /// Find the declaration (if requested) and uses of the label or
+  /// variable at a position.
+  fn references(&self, params: &Value) -> Value {
+    let (text, index) = match self.doc_at(params) {
+      Some(doc) => doc,
+      None => return Value::Null,
+    };
+
+    let name = match name_at(text, index) {
+      Some(name) => name,
+      None => return Value::Null,
+    };
+
+    let include_decl = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+    let uri = uri(params);
+
+    let locations = symbols(text)
+      .into_iter()
+      .filter(|s| s.name == name && (include_decl || !s.decl))
+      .map(|s| json!({"uri": uri, "range": range(text, s.loc, s.len)}))
+      .collect::<Vec<_>>();
+
+    Value::from(locations)
+  }
+
+  /// Describe the instruction, label or variable at a position.
+  fn hover(&self, params: &Value) -> Value {
+    let (text, index) = match self.doc_at(params) {
+      Some(doc) => doc,
+      None => return Value::Null,
+    };
+
+    let files = self.sources(&uri(params));
+    let sources =
+      files.iter().map(|(path, buf)| Source::new(path, buf)).collect::<Vec<_>>();
+    let prog = Prog::from_sources(&sources).ok();
+    let (token, start, len) = match token_at(text, index) {
+      Some(token) => token,
+      None => return Value::Null,
+    };
+
+    let scope = scope_at(text, start);
+    let scoped = |label| scope.map_or(label, |scope| Label::scoped(label, scope));
+    let address_of = |label| prog.as_ref().and_then(|p| p.lookup(scoped(label)));
+
+    let contents = match token {
+      TokenKind::Inst(inst) => encoding(&inst.to_string(), u16::from(inst)),
+      TokenKind::Addr(addr) => match addr.value(address_of).ok() {
+        Some(value) => encoding(&addr.to_string(), value),
+        None => format!("`{}`", addr),
+      },
+      TokenKind::Label(label) => match address_of(label) {
+        Some(addr) => format!("label `{}` at ROM address {}", scoped(label), addr),
+        None => format!("label `{}`", scoped(label)),
+      },
+      TokenKind::Call(call) => match macros::expand(&call, &Macros::new()) {
+        Ok(cmds) => {
+          let cmds = cmds.iter().map(|cmd| format!("`{}`", cmd)).collect::<Vec<_>>();
+          format!("`{}` expands to {}", call, cmds.join(", "))
+        }
+        Err(_) => format!("macro `{}`", call.name()),
+      },
+      TokenKind::Directive(directive) => format!("directive `{}`", directive),
+    };
+
+    json!({
+      "contents": {"kind": "markdown", "value": contents},
+      "range": range(text, loc_of(text, start), len),
+    })
+  }
+
+  /// Complete mnemonics, predefined symbols, labels and variables at
+  /// a position.
+  fn completion(&self, params: &Value) -> Value {
+    let (text, index) = match self.doc_at(params) {
+      Some(doc) => doc,
+      None => return Value::Null,
+    };
+
+    let line_start = text[..index].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..index];
+    let item = |label: String, kind: u8| json!({"label": label, "kind": kind});
+
+    let items = if line.contains('@') || line.trim_start().contains(' ') {
+      let mut names = symbols(text)
+        .into_iter()
+        .map(|s| (s.name, if s.decl { KIND_REFERENCE } else { KIND_VARIABLE }))
+        .collect::<Vec<_>>();
+
+      // Prefer the kind of declared labels over that of their uses.
+      names.sort_by(|(n1, k1), (n2, k2)| (n1, k2).cmp(&(n2, k1)));
+      names.dedup_by(|(n1, _), (n2, _)| n1 == n2);
+
+      let syms = SYMS.iter().map(|sym| item(sym.to_string(), KIND_CONSTANT));
+      syms.chain(names.into_iter().map(|(name, kind)| item(name, kind))).collect()
+    } else if line.contains(';') {
+      mnemonics::<Jump>(1..8).map(|jump| item(jump, KIND_KEYWORD)).collect()
+    } else if line.contains('=') {
+      mnemonics::<Comp>(0..128).map(|comp| item(comp, KIND_KEYWORD)).collect()
+    } else {
+      let dests = mnemonics::<Dest>(1..8)
+        .map(|dest| json!({"label": format!("{}=", dest), "kind": KIND_KEYWORD}));
+      let comps = mnemonics::<Comp>(0..128).map(|comp| item(comp, KIND_KEYWORD));
+      let pseudos = PSEUDOS.iter().map(|name| item(name.to_string(), KIND_FUNCTION));
+      dests.chain(comps).chain(pseudos).collect::<Vec<_>>()
+    };
+
+    Value::from(items)
+  }
+}
+
+/// A declaration or use of a label or variable in a document.
+struct Symbol {
+  /// The name of the label or variable.
+  name: String,
+
+  /// Whether this is the declaration of a label or variable.
+  decl: bool,
+
+  /// The index of the declaration or use.
+  index: usize,
+
+  /// The location of the declaration or use.
+  loc: Loc,
+
+  /// The length of the declaration or use.
+  len: usize,
+}
+
+/// Returns the declarations and uses of labels and variables in a
+/// document, skipping invalid lines.
+///
+/// Variables declared with `.var` and `.reserve` are declarations,
+/// local labels are named after the global label they are scoped to
+/// and anonymous labels are skipped.
+fn symbols<'t>(text: &'t str) -> Vec<Symbol> {
+  let mut symbols = Vec::new();
+  let mut scope: Option<Label<'t>> = None;
+
+  // The offset of the name in a `.var` or `.reserve` directive.
+  let name_offset = |index: usize, directive: &str| {
+    let rest = &text[index + 1 + directive.len()..];
+    1 + directive.len() + rest.len() - rest.trim_start().len()
+  };
+
+  for token in Parser::from(text.as_bytes()).filter_map(Result::ok) {
+    let index = token.index();
+    let found = match token.kind() {
+      TokenKind::Label(label) => {
+        if !label.is_local() && label.anon().is_none() {
+          scope = Some(label);
+        }
+
+        vec![(label, true, index, label.name().len() + 2)]
+      }
+      TokenKind::Addr(Addr::Label(label)) => {
+        vec![(label, false, index, label.name().len() + 1)]
+      }
+      TokenKind::Directive(Directive::Var(name, _)) => {
+        vec![(name, true, index + name_offset(index, "var"), name.name().len())]
+      }
+      TokenKind::Directive(Directive::Reserve(name, _)) => {
+        vec![(name, true, index + name_offset(index, "reserve"), name.name().len())]
+      }
+      TokenKind::Call(call) => call
+        .args()
+        .iter()
+        .filter_map(|arg| match arg.addr() {
+          Addr::Label(label) => {
+            Some((label, false, index + arg.offset(), label.name().len()))
+          }
+          _ => None,
+        })
+        .collect(),
+      _ => Vec::new(),
+    };
+
+    for (label, decl, index, len) in found {
+      if label.anon().is_some() {
+        continue;
+      }
+
+      let label = scope.map_or(label, |scope| label.scoped(scope));
+      let loc = loc_of(text, index);
+      symbols.push(Symbol { name: label.to_string(), decl, index, loc, len });
+    }
+  }
+
+  symbols
+}
+
+/// Returns the global label that local labels at `index` are scoped
+/// to.
+fn scope_at(text: &str, index: usize) -> Option<Label<'_>> {
+  Parser::from(text.as_bytes())
+    .filter_map(Result::ok)
+    .take_while(|token| token.index() <= index)
+    .filter_map(|token| match token.kind() {
+      TokenKind::Label(label) if !label.is_local() && label.anon().is_none() => {
+        Some(label)
+      }
+      _ => None,
+    })
+    .last()
+}
+
+/// Returns the token containing the byte at `index`, with its start
+/// and length.
+fn token_at(text: &str, index: usize) -> Option<(TokenKind<'_>, usize, usize)> {
+  Parser::from(text.as_bytes()).filter_map(Result::ok).find_map(|token| {
+    let start = token.index();
+    let rest = &text[start..];
+    let len = rest.find(|c: char| c.is_whitespace() || c == '/').unwrap_or(rest.len());
+
+    if (start..=start + len).contains(&index) {
+      Some((token.kind(), start, len))
+    } else {
+      None
+    }
+  })
+}
+
+/// Returns the name of the label or variable at `index`.
+fn name_at(text: &str, index: usize) -> Option<String> {
+  let (_, start, _) = token_at(text, index)?;
+  symbols(text).into_iter().find(|s| s.index == start).map(|s| s.name)
+}
+
+/// Describe the 16-bit encoding of an instruction.
+fn encoding(inst: &str, value: u16) -> String {
+  format!("`{}` encodes to `{:016b}` (`{:#06X}`, {})", inst, value, value, value)
+}
+
+/// Returns the mnemonics of the values of `T` in `range`.
+fn mnemonics<T>(range: std::ops::Range<u16>) -> impl Iterator<Item = String>
+where
+  T: TryFrom<u16> + std::fmt::Display,
+{
+  range.filter_map(|v| T::try_from(v).ok()).map(|v| v.to_string())
+}
+
+/// Returns the URI of the document in `params`.
+fn uri(params: &Value) -> String {
+  String::from(params["textDocument"]["uri"].as_str().unwrap_or_default())
+}
+
+/// Returns the path of a `file://` URI, or `None` for other URIs.
+///
+/// The path is percent-decoded, and on Windows the `/` before a drive
+/// letter (as in `file:///C:/Main.asm`) is removed.
+fn path_of(uri: &str) -> Option<PathBuf> {
+  let rest = uri.strip_prefix("file://")?;
+  // Skip the authority, which is empty or `localhost` for local files.
+  let rest = &rest[rest.find('/')?..];
+  let rest = rest.split(['?', '#']).next().unwrap_or_default();
+
+  let mut bytes = Vec::with_capacity(rest.len());
+  let mut input = rest.as_bytes();
+
+  while let Some((&b, tail)) = input.split_first() {
+    let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+
+    match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+      Some(decoded) if b == b'%' => {
+        bytes.push(decoded);
+        input = &tail[2..];
+      }
+      _ => {
+        bytes.push(b);
+        input = tail;
+      }
+    }
+  }
+
+  let path = String::from_utf8(bytes).ok()?;
+
+  match path.as_bytes() {
+    [b'/', drive, b':', ..] if cfg!(windows) && drive.is_ascii_alphabetic() => {
+      Some(PathBuf::from(&path[1..]))
+    }
+    _ => Some(PathBuf::from(path)),
+  }
+}
+
+/// Build a notification message.
+fn notification(method: &str, params: Value) -> Value {
+  json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+/// Build an LSP range of `len` bytes starting at `loc` in `text`.
+fn range(text: &str, loc: Loc, len: usize) -> Value {
+  let line = text.split('\n').nth(loc.line() - 1).unwrap_or_default();
+  let character = |col: usize| {
+    let col = floor_char_boundary(line, col);
+    line[..col].encode_utf16().count()
+  };
+
+  let start = json!({"line": loc.line() - 1, "character": character(loc.col() - 1)});
+  let end = json!({"line": loc.line() - 1, "character": character(loc.col() - 1 + len)});
+  json!({"start": start, "end": end})
+}
+
+/// Returns the location of byte `index` in `text`.
+fn loc_of(text: &str, index: usize) -> Loc {
+  Loc::from_index(text.as_bytes(), index)
+}
+
+/// Returns the byte index of a zero-based line and character (in
+/// UTF-16 code units) in `text`.
+///
+/// Characters past the end of the line refer to its end, and
+/// characters inside a UTF-16 surrogate pair to its start.
+fn index_of(text: &str, line: usize, character: usize) -> Option<usize> {
+  let mut start = 0;
+
+  for _ in 0..line {
+    start += text[start..].find('\n')? + 1;
+  }
+
+  let line = text[start..].split('\n').next().unwrap_or_default();
+  let mut units = 0;
+
+  for (i, c) in line.char_indices() {
+    units += c.len_utf16();
+
+    if units > character {
+      return Some(start + i);
+    }
+  }
+
+  Some(start + line.len())
+}
+
+/// Returns the largest character boundary in `text` that is not
+/// greater than `index`.
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+  let mut index = index.min(text.len());
+
+  while !text.is_char_boundary(index) {
+    index -= 1;
+  }
+
+  index
+}
+
+/// Read a message framed with a `Content-Length` header.
+///
+/// Returns `None` at the end of the stream.
+fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Value>, Err> {
+  let mut len = None;
+
+  loop {
+    let mut header = String::new();
+
+    if reader.read_line(&mut header)? == 0 {
+      return Ok(None);
+    }
+
+    let header = header.trim_end();
+
+    if header.is_empty() {
+      break;
+    }
+
+    if let Some(value) = header.strip_prefix("Content-Length:") {
+      len = Some(value.trim().parse::<usize>().map_err(|_| Err::Header(header.into()))?);
+    }
+  }
+
+  let len = len.ok_or_else(|| Err::Header(String::new()))?;
+  let mut body = vec![0; len];
+  reader.read_exact(&mut body)?;
+  Ok(Some(serde_json::from_slice(&body)?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const URI: &str = "file:///Max.asm";
+  const SRC: &str = "@R0\nD=M\n@END\nD;JGT\n(LOOP)\n@i\nM=0\n@LOOP\n0;JMP\n(END)\n";
+
+  fn server() -> Server {
+    let mut server = Server::new();
+    let open = json!({
+      "method": "textDocument/didOpen",
+      "params": {"textDocument": {"uri": URI, "text": SRC}},
+    });
+    let out = server.handle(&open);
+    assert_eq!(out[0]["params"]["diagnostics"], json!([]));
+    server
+  }
+
+  fn request(server: &mut Server, method: &str, line: u64, character: u64) -> Value {
+    let msg = json!({
+      "jsonrpc": "2.0",
+      "id": 1,
+      "method": method,
+      "params": {
+        "textDocument": {"uri": URI},
+        "position": {"line": line, "character": character},
+        "context": {"includeDeclaration": true},
+      },
+    });
+    server.handle(&msg).remove(0)["result"].take()
+  }
+
+  #[test]
+  fn definition_and_references() {
+    let mut server = server();
+
+    let def = request(&mut server, "textDocument/definition", 2, 2);
+    assert_eq!(def["range"]["start"], json!({"line": 9, "character": 0}));
+
+    let refs = request(&mut server, "textDocument/references", 7, 1);
+    let lines =
+      refs.as_array().unwrap().iter().map(|r| r["range"]["start"]["line"].clone());
+    assert_eq!(lines.collect::<Vec<_>>(), [json!(4), json!(7)]);
+
+    let def = request(&mut server, "textDocument/definition", 5, 1);
+    assert_eq!(def["range"]["start"], json!({"line": 5, "character": 0}));
+
+    assert_eq!(request(&mut server, "textDocument/definition", 1, 0), Value::Null);
+  }
+
+  #[test]
+  fn hover() {
+    let mut server = server();
+
+    let hover = request(&mut server, "textDocument/hover", 3, 2);
+    let value = hover["contents"]["value"].as_str().unwrap();
+    assert_eq!(value, "`D;JGT` encodes to `1110001100000001` (`0xE301`, 58113)");
+
+    let hover = request(&mut server, "textDocument/hover", 5, 0);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("`0000000000010000`"));
+
+    let hover = request(&mut server, "textDocument/hover", 4, 2);
+    assert_eq!(hover["contents"]["value"], "label `LOOP` at ROM address 4");
+  }
+
+  #[test]
+  fn local_labels() {
+    let mut server = Server::new();
+    let text = "(A)\n(.loop)\n@.loop\n0;JMP\n(B)\n(.loop)\n@.loop\n0;JMP\n";
+    server.handle(&json!({
+      "method": "textDocument/didOpen",
+      "params": {"textDocument": {"uri": URI, "text": text}},
+    }));
+
+    let def = request(&mut server, "textDocument/definition", 6, 1);
+    assert_eq!(def["range"]["start"], json!({"line": 5, "character": 0}));
+
+    let refs = request(&mut server, "textDocument/references", 1, 1);
+    let lines =
+      refs.as_array().unwrap().iter().map(|r| r["range"]["start"]["line"].clone());
+    assert_eq!(lines.collect::<Vec<_>>(), [json!(1), json!(2)]);
+
+    let hover = request(&mut server, "textDocument/hover", 5, 1);
+    assert_eq!(hover["contents"]["value"], "label `B.loop` at ROM address 2");
+  }
+
+  #[test]
+  fn variables() {
+    let mut server = Server::new();
+    let text = "@n\nM=0\n.reserve  buf 4\n.var n @ 100\n@buf\n";
+    server.handle(&json!({
+      "method": "textDocument/didOpen",
+      "params": {"textDocument": {"uri": URI, "text": text}},
+    }));
+
+    let def = request(&mut server, "textDocument/definition", 0, 1);
+    assert_eq!(def["range"]["start"], json!({"line": 3, "character": 5}));
+
+    let def = request(&mut server, "textDocument/definition", 4, 1);
+    assert_eq!(def["range"]["start"], json!({"line": 2, "character": 10}));
+    assert_eq!(def["range"]["end"], json!({"line": 2, "character": 13}));
+
+    let hover = request(&mut server, "textDocument/hover", 4, 1);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("(`0x0010`, 16)"));
+  }
+
+  #[test]
+  fn completion() {
+    let mut server = server();
+    let labels = |items: Value| {
+      items.as_array().unwrap().iter().map(|i| i["label"].clone()).collect::<Vec<_>>()
+    };
+
+    let items = labels(request(&mut server, "textDocument/completion", 0, 1));
+    assert!(items.contains(&json!("SCREEN")));
+    assert!(items.contains(&json!("LOOP")));
+    assert!(items.contains(&json!("i")));
+
+    let items = labels(request(&mut server, "textDocument/completion", 3, 2));
+    assert_eq!(items.len(), 7);
+    assert!(items.contains(&json!("JMP")));
+
+    let items = labels(request(&mut server, "textDocument/completion", 1, 2));
+    assert_eq!(items.len(), 28);
+  }
+
+  #[test]
+  fn non_ascii() {
+    let text = "// é𝄞\n@R0\n";
+    assert_eq!(index_of(text, 0, 4), Some(5));
+    assert_eq!(index_of(text, 0, 5), Some(5));
+    assert_eq!(index_of(text, 0, 6), Some(9));
+    assert_eq!(index_of(text, 0, 100), Some(9));
+    assert_eq!(index_of(text, 1, 1), Some(11));
+
+    let mut server = Server::new();
+    let open = json!({
+      "method": "textDocument/didOpen",
+      "params": {"textDocument": {"uri": URI, "text": "// é\n@R0\n(é)\n"}},
+    });
+    let out = server.handle(&open);
+    let range = &out[0]["params"]["diagnostics"][0]["range"];
+    assert_eq!(range["end"], json!({"line": 2, "character": 3}));
+
+    let items = request(&mut server, "textDocument/completion", 0, 4);
+    assert!(items.as_array().is_some());
+  }
+
+  #[test]
+  fn includes() {
+    let mut server = Server::new();
+    let open = |uri: &str, text: &str| {
+      json!({
+        "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": uri, "text": text}},
+      })
+    };
+
+    let out = server.handle(&open("file:///lib/End.asm", "(END)\n@END\nD;JPM\n"));
+    assert_eq!(out[0]["params"]["diagnostics"].as_array().unwrap().len(), 1);
+
+    let out = server.handle(&open(URI, ".include \"lib/End.asm\"\n@END\n0;JMP\n"));
+    assert_eq!(out[0]["params"]["diagnostics"], json!([]));
+
+    let out = server.handle(&open("untitled:1", ".include \"lib/End.asm\"\n"));
+    let diagnostics = &out[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics[0]["code"], "E0013");
+  }
+
+  #[test]
+  fn warnings() {
+    let mut server = Server::new();
+    let open = json!({
+      "method": "textDocument/didOpen",
+      "params": {"textDocument": {"uri": URI, "text": "(LOOP)\n@LOPO\n0;JMP\n"}},
+    });
+    let out = server.handle(&open);
+    let diagnostics = &out[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["severity"], WARNING);
+    assert_eq!(diagnostics[0]["code"], "E0007");
+  }
+
+  #[test]
+  fn uris() {
+    assert_eq!(
+      path_of("file:///My%20Project/Main.asm"),
+      Some("/My Project/Main.asm".into())
+    );
+    assert_eq!(path_of("file://localhost/a%2Fb%zz.asm"), Some("/a/b%zz.asm".into()));
+    assert_eq!(path_of("file:///%C3%A9.asm#L1"), Some("/é.asm".into()));
+    assert_eq!(path_of("untitled:1"), None);
+
+    let drive = if cfg!(windows) { "c:/Main.asm" } else { "/c:/Main.asm" };
+    assert_eq!(path_of("file:///c%3A/Main.asm"), Some(drive.into()));
+
+    let mut server = Server::new();
+    let open = |uri: &str, text: &str| {
+      json!({
+        "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": uri, "text": text}},
+      })
+    };
+
+    server.handle(&open("file:///My%20Project/End.asm", "(END)\n"));
+    let out = server.handle(&open(
+      "file:///My%20Project/Main.asm",
+      ".include \"End.asm\"\n@END\n0;JMP\n",
+    ));
+    assert_eq!(out[0]["params"]["diagnostics"], json!([]));
+  }
+
+  #[test]
+  fn lifecycle() {
+    let input = [
+      json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+      json!({"jsonrpc": "2.0", "id": 2, "method": "foo"}),
+      json!({"jsonrpc": "2.0", "id": 3, "method": "shutdown"}),
+      json!({"jsonrpc": "2.0", "id": 4, "method": "textDocument/hover"}),
+      json!({"jsonrpc": "2.0", "method": "exit"}),
+    ];
+
+    let input = input
+      .iter()
+      .map(|msg| {
+        let body = msg.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+      })
+      .collect::<String>();
+
+    let mut server = Server::new();
+    let mut output = Vec::new();
+    server.run(input.as_bytes(), &mut output).unwrap();
+    assert!(server.exited());
+
+    let mut reader = output.as_slice();
+    let init = read_message(&mut reader).unwrap().unwrap();
+    assert_eq!(init["result"]["capabilities"]["hoverProvider"], true);
+    let unknown = read_message(&mut reader).unwrap().unwrap();
+    assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+    let shutdown = read_message(&mut reader).unwrap().unwrap();
+    assert_eq!(shutdown["result"], Value::Null);
+    let hover = read_message(&mut reader).unwrap().unwrap();
+    assert_eq!(hover["error"]["code"], INVALID_REQUEST);
+    assert!(read_message(&mut reader).unwrap().is_none());
+  }
+}
@@ -0,0 +1,554 @@
+//! Assembler macros and pseudo-instructions.
+//!
+//! A macro is defined between a `.macro NAME [PARAM, ...]` and an
+//! `.endm` [directive](crate::hack::Directive), and is invoked with a
+//! [call](Call) of its name followed by comma-separated arguments.
+//! Each argument is an address (a number, a label, a predefined
+//! symbol or a constant expression) that replaces the corresponding
+//! parameter wherever it is used as an address or in a constant
+//! expression in the body of the macro:
+//!
+//! ```text
+//! .macro PUSH value
+//!     @value
+//!     D=A
+//!     @SP
+//!     M=M+1
+//!     A=M-1
+//!     M=D
+//! .endm
+//!
+//!     PUSH 7
+//! ```
+//!
+//! In a constant expression, a parameter is replaced by its argument
+//! in parentheses, so `@n+1` with `n` bound to `x*2` becomes
+//! `@(x*2)+1`.
+//!
+//! The bodies of macros can call other macros but cannot declare
+//! labels.
+//!
+//! The following pseudo-instructions are built in:
+//!
+//! | Pseudo-instruction     | Expansion                  |
+//! |------------------------|----------------------------|
+//! | `GOTO label`           | `@label`, `0;JMP`          |
+//! | `JMP_IF_ZERO label`    | `@label`, `D;JEQ`          |
+//! | `JMP_IF_NONZERO label` | `@label`, `D;JNE`          |
+//! | `JMP_IF_NEG label`     | `@label`, `D;JLT`          |
+//! | `JMP_IF_POS label`     | `@label`, `D;JGT`          |
+//! | `LOAD addr`            | `@addr`, `D=M`             |
+//! | `STORE addr`           | `@addr`, `M=D`             |
+//! | `SET addr, value`      | `@value`, `D=A`, `@addr`, `M=D` |
+//!
+//! `SET` with a value of `0` or `1` expands to `@addr`, `M=0` or
+//! `M=1` instead.
+
+use crate::hack::Addr;
+use crate::hack::AddrErr;
+use crate::hack::Cmd;
+use crate::hack::Comp;
+use crate::hack::Dest;
+use crate::hack::Inst;
+use crate::hack::Jump;
+use crate::hack::Label;
+use crate::hack::LabelErr;
+use crate::parser;
+use crate::Buf;
+use derive_more::Display;
+use std::collections::HashMap as Map;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Names of the built-in pseudo-instructions.
+pub const PSEUDOS: [&str; 8] = [
+  "GOTO",
+  "JMP_IF_ZERO",
+  "JMP_IF_NONZERO",
+  "JMP_IF_NEG",
+  "JMP_IF_POS",
+  "LOAD",
+  "STORE",
+  "SET",
+];
+
+/// Macro definitions by name.
+pub type Macros<'b> = Map<Label<'b>, Macro<'b>>;
+
+/// Errors when parsing or expanding macro calls.
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum Err {
+  /// Invalid macro name.
+  #[display(fmt = "invalid macro name: {}", _0)]
+  InvalidName(LabelErr),
+
+  /// An argument is missing between commas.
+  #[display(fmt = "missing argument")]
+  MissingArg,
+
+  /// Invalid argument.
+  #[display(fmt = "invalid argument: {}", _0)]
+  InvalidArg(AddrErr),
+
+  /// Unexpected input after a macro call.
+  #[display(fmt = "unexpected `{}` after macro call", _0)]
+  Unexpected(String),
+
+  /// Call of an undefined macro.
+  #[display(fmt = "unknown macro or instruction `{}`", _0)]
+  Unknown(String),
+
+  /// Call with the wrong number of arguments.
+  ///
+  /// Contains the name of the macro, and the expected and given
+  /// number of arguments.
+  #[display(fmt = "`{}` takes {} argument(s) but {} were given", _0, _1, _2)]
+  Arity(String, usize, usize),
+
+  /// A macro calls itself, directly or indirectly.
+  #[display(fmt = "macro `{}` expands to itself", _0)]
+  Recursive(String),
+}
+
+/// An argument of a macro [call](Call).
+#[derive(new, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arg<'b> {
+  /// The offset of the argument from the start of the call.
+  offset: usize,
+
+  /// The value of the argument.
+  addr: Addr<'b>,
+}
+
+impl<'b> Arg<'b> {
+  /// Returns the offset of the argument from the start of the call.
+  pub fn offset(&self) -> usize {
+    self.offset
+  }
+
+  /// Returns the value of the argument.
+  pub fn addr(&self) -> Addr<'b> {
+    self.addr
+  }
+}
+
+/// A call of a macro or pseudo-instruction (e.g. `SET R0, 5`).
+#[derive(new, Debug, Clone, PartialEq, Eq)]
+pub struct Call<'b> {
+  /// The name of the called macro.
+  name: Label<'b>,
+
+  /// The arguments of the call.
+  args: Vec<Arg<'b>>,
+}
+
+impl fmt::Display for Call<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name)?;
+
+    for (i, arg) in self.args.iter().enumerate() {
+      let addr = arg.addr.to_string();
+      write!(f, "{}{}", if i == 0 { " " } else { ", " }, &addr[1..])?;
+    }
+
+    Ok(())
+  }
+}
+
+impl<'b> Call<'b> {
+  /// Returns the name of the called macro.
+  pub fn name(&self) -> Label<'b> {
+    self.name
+  }
+
+  /// Returns the arguments of the call.
+  pub fn args(&self) -> &[Arg<'b>] {
+    &self.args
+  }
+
+  /// Whether `buf` starts with something that can only be a call:
+  /// a name followed by whitespace, a comment or the end of the
+  /// input.
+  pub(crate) fn is_call(buf: Buf) -> bool {
+    let (name, rem) = parser::read_while(buf, Label::is_label_byte);
+    let end = matches!(rem.first(), None | Some(b'/'))
+      || rem.first().is_some_and(u8::is_ascii_whitespace);
+    buf.first().is_some_and(|&b| Label::is_label_start(b)) && !name.is_empty() && end
+  }
+
+  /// Read a call from a buffer.
+  ///
+  /// The rest of the line after the call must be blank or a comment.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Addr;
+  /// use has::hack::Call;
+  /// use has::hack::Sym;
+  ///
+  /// let (call, rem, len) = Call::read_from("SET R0, 5 // Init".as_bytes()).unwrap();
+  /// assert_eq!(call.name().name(), "SET");
+  /// assert_eq!(call.args()[0].addr(), Addr::Sym(Sym::R0));
+  /// assert_eq!(call.args()[1].addr(), Addr::Num(5));
+  /// assert_eq!(call.args()[1].offset(), 8);
+  /// assert_eq!((rem, len), (" // Init".as_bytes(), 9));
+  /// assert_eq!(call.to_string(), "SET R0, 5");
+  /// ```
+  pub fn read_from(buf: Buf<'b>) -> Result<(Self, Buf<'b>, usize), Err> {
+    let (name, rem) = parser::read_while(buf, Label::is_label_byte);
+    let name = Label::try_from(name).map_err(Err::InvalidName)?;
+    let (items, rem, len) = parser::read_list(rem);
+    let offset = name.name().len();
+
+    let args = items
+      .into_iter()
+      .map(|(item_offset, item)| match Addr::read_from(item) {
+        _ if item.is_empty() => Err(Err::MissingArg),
+        Ok((addr, _, _)) => Ok(Arg::new(offset + item_offset, addr)),
+        Err(e) => Err(Err::InvalidArg(e)),
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+
+    if !parser::is_line_end(rem) {
+      let (_, rest) = parser::read_while(rem, |b| b.is_ascii_whitespace());
+      let (rest, _) = parser::read_until_ws(rest);
+      return Err(Err::Unexpected(String::from_utf8_lossy(rest).into_owned()));
+    }
+
+    Ok((Self { name, args }, rem, offset + len))
+  }
+}
+
+/// An entry in the body of a [macro](Macro).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item<'b> {
+  /// An A- or C-instruction.
+  Cmd(Cmd<'b>),
+
+  /// A call of another macro or pseudo-instruction.
+  Call(Call<'b>),
+}
+
+/// An instruction from the expansion of a macro call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expanded<'b> {
+  /// An A- or C-instruction.
+  Cmd(Cmd<'b>),
+
+  /// An A-instruction with the text of a constant expression in which
+  /// parameters were replaced by arguments.
+  Expr(String),
+}
+
+impl fmt::Display for Expanded<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Expanded::Cmd(cmd) => write!(f, "{}", cmd),
+      Expanded::Expr(text) => write!(f, "@{}", text),
+    }
+  }
+}
+
+impl<'b> From<Addr<'b>> for Expanded<'b> {
+  fn from(addr: Addr<'b>) -> Self {
+    Expanded::Cmd(Cmd::Addr(addr))
+  }
+}
+
+impl Expanded<'_> {
+  /// Returns the text of the address of an A-instruction, to be
+  /// substituted into a constant expression.
+  fn operand(&self) -> String {
+    match self {
+      Expanded::Cmd(Cmd::Addr(addr)) => addr.to_string()[1..].to_owned(),
+      Expanded::Cmd(cmd) => cmd.to_string(),
+      Expanded::Expr(text) => text.clone(),
+    }
+  }
+}
+
+/// A macro definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Macro<'b> {
+  /// The name of the macro.
+  name: Label<'b>,
+
+  /// The parameters of the macro.
+  params: Vec<Label<'b>>,
+
+  /// The body of the macro.
+  body: Vec<Item<'b>>,
+}
+
+impl<'b> Macro<'b> {
+  /// Create a macro with an empty body.
+  ///
+  /// # Arguments
+  ///
+  /// * `name` - The name of the macro.
+  ///
+  /// * `params` - The parameters of the macro.
+  pub fn new(name: Label<'b>, params: Vec<Label<'b>>) -> Self {
+    Self { name, params, body: Vec::new() }
+  }
+
+  /// Returns the name of the macro.
+  pub fn name(&self) -> Label<'b> {
+    self.name
+  }
+
+  /// Returns the parameters of the macro.
+  pub fn params(&self) -> &[Label<'b>] {
+    &self.params
+  }
+
+  /// Returns the body of the macro.
+  pub fn body(&self) -> &[Item<'b>] {
+    &self.body
+  }
+
+  /// Append an entry to the body of the macro.
+  pub fn push(&mut self, item: Item<'b>) {
+    self.body.push(item);
+  }
+
+  /// Replace parameters with the corresponding arguments in `addr`,
+  /// including in constant expressions.
+  fn subst(&self, addr: Addr<'b>, args: &[Expanded<'b>]) -> Expanded<'b> {
+    let arg = |label| self.params.iter().position(|&p| p == label).map(|i| &args[i]);
+
+    match addr {
+      Addr::Label(label) => arg(label).cloned().unwrap_or_else(|| addr.into()),
+      Addr::Expr(expr) => match expr.subst(|label| arg(label).map(Expanded::operand)) {
+        Some(text) => Expanded::Expr(text),
+        None => addr.into(),
+      },
+      _ => addr.into(),
+    }
+  }
+}
+
+/// Whether `name` is the name of a built-in pseudo-instruction.
+pub fn is_pseudo(name: Label) -> bool {
+  PSEUDOS.contains(&name.name())
+}
+
+/// Expand a call of a macro or pseudo-instruction into instructions.
+///
+/// # Arguments
+///
+/// * `call` - The call to expand.
+///
+/// * `macros` - The macros defined so far.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::macros;
+/// use has::hack::macros::Macros;
+/// use has::hack::Call;
+///
+/// let (call, _, _) = Call::read_from("GOTO LOOP".as_bytes()).unwrap();
+/// let cmds = macros::expand(&call, &Macros::new()).unwrap();
+/// let cmds = cmds.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+/// assert_eq!(cmds, ["@LOOP", "0;JMP"]);
+///
+/// let (call, _, _) = Call::read_from("GOTO".as_bytes()).unwrap();
+/// assert!(macros::expand(&call, &Macros::new()).is_err());
+/// ```
+pub fn expand<'b>(
+  call: &Call<'b>,
+  macros: &Macros<'b>,
+) -> Result<Vec<Expanded<'b>>, Err> {
+  let args = call.args.iter().map(|arg| arg.addr.into()).collect::<Vec<_>>();
+  let mut cmds = Vec::new();
+  expand_into(call.name, &args, macros, &mut Vec::new(), &mut cmds)?;
+  Ok(cmds)
+}
+
+/// Expand a call of the macro `name` with the (expanded) arguments
+/// `args` into `cmds`, with `stack` holding the names of the macros
+/// being expanded.
+fn expand_into<'b>(
+  name: Label<'b>,
+  args: &[Expanded<'b>],
+  macros: &Macros<'b>,
+  stack: &mut Vec<Label<'b>>,
+  cmds: &mut Vec<Expanded<'b>>,
+) -> Result<(), Err> {
+  let mac = match macros.get(&name) {
+    Some(mac) => mac,
+    None => return pseudo(name, args, cmds),
+  };
+
+  if args.len() != mac.params.len() {
+    return Err(Err::Arity(String::from(name.name()), mac.params.len(), args.len()));
+  }
+
+  if stack.contains(&name) {
+    return Err(Err::Recursive(String::from(name.name())));
+  }
+
+  stack.push(name);
+
+  for item in &mac.body {
+    match item {
+      Item::Cmd(Cmd::Addr(addr)) => cmds.push(mac.subst(*addr, args)),
+      Item::Cmd(cmd) => cmds.push(Expanded::Cmd(*cmd)),
+      Item::Call(inner) => {
+        let inner_args =
+          inner.args.iter().map(|arg| mac.subst(arg.addr, args)).collect::<Vec<_>>();
+        expand_into(inner.name, &inner_args, macros, stack, cmds)?;
+      }
+    }
+  }
+
+  stack.pop();
+  Ok(())
+}
+
+/// Expand a call of a built-in pseudo-instruction into `cmds`.
+fn pseudo<'b>(
+  label: Label<'b>,
+  args: &[Expanded<'b>],
+  cmds: &mut Vec<Expanded<'b>>,
+) -> Result<(), Err> {
+  let name = label.name();
+
+  let inst = |dest, comp, jump| {
+    Expanded::Cmd(Cmd::Inst(
+      Inst::new(dest, comp, jump).expect("pseudo-instructions are valid"),
+    ))
+  };
+
+  let jump = match name {
+    "GOTO" => Some((Comp::Zero, Jump::JMP)),
+    "JMP_IF_ZERO" => Some((Comp::D, Jump::JEQ)),
+    "JMP_IF_NONZERO" => Some((Comp::D, Jump::JNE)),
+    "JMP_IF_NEG" => Some((Comp::D, Jump::JLT)),
+    "JMP_IF_POS" => Some((Comp::D, Jump::JGT)),
+    _ => None,
+  };
+
+  let expected = match name {
+    "SET" => 2,
+    _ if is_pseudo(label) => 1,
+    _ => return Err(Err::Unknown(String::from(name))),
+  };
+
+  if args.len() != expected {
+    return Err(Err::Arity(String::from(name), expected, args.len()));
+  }
+
+  if let Some((comp, jump)) = jump {
+    cmds.extend([args[0].clone(), inst(Dest::Null, comp, jump)]);
+    return Ok(());
+  }
+
+  match (name, args) {
+    ("LOAD", [addr]) => cmds.extend([addr.clone(), inst(Dest::D, Comp::M, Jump::Null)]),
+    ("STORE", [addr]) => cmds.extend([addr.clone(), inst(Dest::M, Comp::D, Jump::Null)]),
+    ("SET", [addr, Expanded::Cmd(Cmd::Addr(Addr::Num(value @ 0..=1)))]) => {
+      let comp = if *value == 0 { Comp::Zero } else { Comp::One };
+      cmds.extend([addr.clone(), inst(Dest::M, comp, Jump::Null)]);
+    }
+    ("SET", [addr, value]) => cmds.extend([
+      value.clone(),
+      inst(Dest::D, Comp::A, Jump::Null),
+      addr.clone(),
+      inst(Dest::M, Comp::D, Jump::Null),
+    ]),
+    _ => unreachable!("pseudo-instructions are checked above"),
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hack::Expr;
+
+  fn call(src: &'static str) -> Call<'static> {
+    Call::read_from(src.as_bytes()).unwrap().0
+  }
+
+  fn expand_str(src: &'static str, macros: &Macros<'static>) -> Result<Vec<String>, Err> {
+    let cmds = expand(&call(src), macros)?;
+    Ok(cmds.iter().map(|cmd| cmd.to_string()).collect())
+  }
+
+  #[test]
+  fn read() {
+    assert!(Call::is_call(b"PUSH_D"));
+    assert!(Call::is_call(b"GOTO LOOP"));
+    assert!(Call::is_call(b"RET// Return"));
+    assert!(!Call::is_call(b"D=M"));
+    assert!(!Call::is_call(b"0;JMP"));
+
+    assert_eq!(Call::read_from(b"SET R0,,5").err(), Some(Err::MissingArg));
+    assert_eq!(Call::read_from(b"GOTO A B").err(), Some(Err::Unexpected("B".into())));
+    assert!(matches!(Call::read_from(b"LOAD -1"), Err(Err::InvalidArg(_))));
+  }
+
+  #[test]
+  fn pseudos() {
+    let macros = Macros::new();
+    assert_eq!(expand_str("JMP_IF_ZERO END", &macros).unwrap(), ["@END", "D;JEQ"]);
+    assert_eq!(expand_str("JMP_IF_NEG END", &macros).unwrap(), ["@END", "D;JLT"]);
+    assert_eq!(expand_str("LOAD i", &macros).unwrap(), ["@i", "D=M"]);
+    assert_eq!(expand_str("STORE R13", &macros).unwrap(), ["@R13", "M=D"]);
+    assert_eq!(expand_str("SET R0, 1", &macros).unwrap(), ["@R0", "M=1"]);
+    assert_eq!(expand_str("SET R0, 5", &macros).unwrap(), ["@5", "D=A", "@R0", "M=D"]);
+    assert_eq!(expand_str("SET R0", &macros), Err(Err::Arity("SET".into(), 2, 1)));
+    assert_eq!(expand_str("FOO", &macros), Err(Err::Unknown("FOO".into())));
+  }
+
+  #[test]
+  fn macros() {
+    let label = |name: &'static str| Label::try_from(name.as_bytes()).unwrap();
+    let mut macros = Macros::new();
+
+    let mut copy = Macro::new(label("COPY"), vec![label("from"), label("to")]);
+    copy.push(Item::Call(call("LOAD from")));
+    copy.push(Item::Call(call("STORE to")));
+    macros.insert(copy.name(), copy);
+
+    let mut swap = Macro::new(label("SWAP"), vec![label("a"), label("b")]);
+    swap.push(Item::Call(call("COPY a, R13")));
+    swap.push(Item::Call(call("COPY b, a")));
+    swap.push(Item::Call(call("COPY R13, b")));
+    macros.insert(swap.name(), swap);
+
+    let cmds = expand_str("SWAP x, 7", &macros).unwrap();
+    assert_eq!(cmds[..4], ["@x", "D=M", "@R13", "M=D"]);
+    assert_eq!(cmds[4..8], ["@7", "D=M", "@x", "M=D"]);
+    assert_eq!(cmds.len(), 12);
+
+    assert_eq!(expand_str("COPY x", &macros), Err(Err::Arity("COPY".into(), 2, 1)));
+
+    let mut inc = Macro::new(label("INC"), vec![label("n")]);
+    let expr =
+      |text: &'static str| Addr::Expr(Expr::read_from(text.as_bytes()).unwrap().0);
+    inc.push(Item::Cmd(Cmd::Addr(expr("n+1"))));
+    inc.push(Item::Call(call("SET R0, n*2")));
+    macros.insert(inc.name(), inc);
+
+    let mut add = Macro::new(label("ADD"), vec![label("a"), label("b")]);
+    add.push(Item::Call(call("INC a-b")));
+    macros.insert(add.name(), add);
+
+    let cmds = expand_str("INC 5", &macros).unwrap();
+    assert_eq!(cmds, ["@(5)+1", "@(5)*2", "D=A", "@R0", "M=D"]);
+    let cmds = expand_str("ADD x+1, SCREEN", &macros).unwrap();
+    assert_eq!(cmds[..2], ["@((x+1)-(SCREEN))+1", "@((x+1)-(SCREEN))*2"]);
+    assert_eq!(expand_str("INC 'n'", &macros).unwrap()[0], "@('n')+1");
+
+    let mut rec = Macro::new(label("REC"), Vec::new());
+    rec.push(Item::Cmd(Cmd::Addr(Addr::Num(1))));
+    rec.push(Item::Call(call("REC")));
+    macros.insert(rec.name(), rec);
+    assert_eq!(expand_str("REC", &macros), Err(Err::Recursive("REC".into())));
+  }
+}
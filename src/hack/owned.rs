@@ -0,0 +1,447 @@
+//! Owned HACK programs.
+//!
+//! A [Prog](crate::hack::Prog) borrows the names of its labels,
+//! variables and constants from the buffer it was parsed from. An
+//! owned [Prog] holds them as strings shared through an [Interner]
+//! instead, so it does not borrow anything: it can be stored, sent
+//! across threads and kept after the input buffer is dropped.
+//!
+//! An owned program is created from a borrowed one with [Prog::new]
+//! (or [From]). It can be [encoded](Prog::encode) and
+//! [run](crate::hack::Emu::from_owned_prog) directly, and
+//! [borrowed](Prog::borrow) again to inspect it further.
+
+use crate::hack;
+use crate::hack::dec;
+use crate::hack::prog::Encoder;
+use crate::hack::Expr;
+use crate::hack::Inst;
+use crate::hack::Sym;
+use crate::Loc;
+use std::collections::HashMap as Map;
+use std::collections::HashSet as Set;
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io;
+use std::sync::Arc;
+
+/// A set of interned strings.
+///
+/// Each distinct string is only stored once, no matter how many
+/// labels or programs share it. Interned strings stay valid after the
+/// interner is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::Interner;
+/// use std::sync::Arc;
+///
+/// let mut interner = Interner::new();
+/// let a = interner.intern("LOOP");
+/// let b = interner.intern("LOOP");
+/// assert!(Arc::ptr_eq(&a, &b));
+/// assert_eq!(interner.len(), 1);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Interner(Set<Arc<str>>);
+
+impl Interner {
+  /// Create an empty interner.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns the interned copy of `name`, interning it first if
+  /// needed.
+  pub fn intern(&mut self, name: &str) -> Arc<str> {
+    if let Some(name) = self.0.get(name) {
+      return Arc::clone(name);
+    }
+
+    let name = Arc::<str>::from(name);
+    self.0.insert(Arc::clone(&name));
+    name
+  }
+
+  /// Returns the number of interned strings.
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  /// Whether no strings have been interned.
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+}
+
+/// A label that owns its (interned) name.
+///
+/// Owned labels compare and hash like the [labels](hack::Label) they
+/// are created from.
+#[derive(Clone)]
+pub struct Label {
+  /// The global label a local label is scoped to.
+  scope: Option<Arc<str>>,
+
+  /// The name of the label.
+  name: Arc<str>,
+}
+
+impl Label {
+  /// Create an owned label from a label, interning its name.
+  ///
+  /// # Arguments
+  ///
+  /// * `label` - The label.
+  ///
+  /// * `interner` - The interner to store the name in.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Interner;
+  /// use has::hack::Label;
+  /// use has::hack::OwnedLabel;
+  /// use std::convert::TryFrom;
+  ///
+  /// let label = |name: &'static str| Label::try_from(name.as_bytes()).unwrap();
+  ///
+  /// let owned = OwnedLabel::new(label(".loop").scoped(label("MULT")), &mut Interner::new());
+  /// assert_eq!((owned.scope(), owned.name()), (Some("MULT"), ".loop"));
+  /// assert_eq!(owned.to_string(), "MULT.loop");
+  /// assert_eq!(owned.borrow(), label("MULT.loop"));
+  /// ```
+  pub fn new(label: hack::Label, interner: &mut Interner) -> Self {
+    let scope = label.scope().map(|scope| interner.intern(scope));
+    Self { scope, name: interner.intern(label.name()) }
+  }
+
+  /// Returns the name of the label.
+  ///
+  /// As for [labels](hack::Label::name), this is the local name of a
+  /// scoped local label.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Returns the name of the global label a local label is scoped to.
+  pub fn scope(&self) -> Option<&str> {
+    self.scope.as_deref()
+  }
+
+  /// Returns a label borrowing the name of this one.
+  pub fn borrow(&self) -> hack::Label<'_> {
+    hack::Label::from_parts(self.scope(), self.name())
+  }
+}
+
+impl PartialEq for Label {
+  fn eq(&self, other: &Self) -> bool {
+    self.borrow() == other.borrow()
+  }
+}
+
+impl Eq for Label {}
+
+impl Hash for Label {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.borrow().hash(state)
+  }
+}
+
+impl fmt::Debug for Label {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Label").field(&self.to_string()).finish()
+  }
+}
+
+impl fmt::Display for Label {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.borrow().fmt(f)
+  }
+}
+
+/// An instruction that owns the names it refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cmd {
+  /// A-instruction with a numerical address.
+  Num(u16),
+
+  /// A-instruction with a label address.
+  Label(Label),
+
+  /// A-instruction with a predefined symbol address.
+  Sym(Sym),
+
+  /// A-instruction with a constant expression address, with the
+  /// global label that local labels in it are scoped to.
+  Expr(Arc<str>, Option<Label>),
+
+  /// C-instruction.
+  Inst(Inst),
+}
+
+impl Cmd {
+  /// Create an owned instruction from an instruction, interning the
+  /// names it refers to.
+  ///
+  /// # Arguments
+  ///
+  /// * `cmd` - The instruction.
+  ///
+  /// * `interner` - The interner to store the names in.
+  pub fn new(cmd: hack::Cmd, interner: &mut Interner) -> Self {
+    match cmd {
+      hack::Cmd::Addr(hack::Addr::Num(num)) => Cmd::Num(num),
+      hack::Cmd::Addr(hack::Addr::Label(label)) => {
+        Cmd::Label(Label::new(label, interner))
+      }
+      hack::Cmd::Addr(hack::Addr::Sym(sym)) => Cmd::Sym(sym),
+      hack::Cmd::Addr(hack::Addr::Expr(expr)) => {
+        let scope = expr.scope().map(|scope| Label::new(scope, interner));
+        Cmd::Expr(interner.intern(&expr.to_string()), scope)
+      }
+      hack::Cmd::Inst(inst) => Cmd::Inst(inst),
+    }
+  }
+
+  /// Returns an instruction borrowing the names of this one.
+  pub fn borrow(&self) -> hack::Cmd<'_> {
+    match self {
+      Cmd::Num(num) => hack::Cmd::Addr(hack::Addr::Num(*num)),
+      Cmd::Label(label) => hack::Cmd::Addr(hack::Addr::Label(label.borrow())),
+      Cmd::Sym(sym) => hack::Cmd::Addr(hack::Addr::Sym(*sym)),
+      Cmd::Expr(text, scope) => {
+        let expr = Expr::from_parts(text, scope.as_ref().map(Label::borrow));
+        hack::Cmd::Addr(hack::Addr::Expr(expr))
+      }
+      Cmd::Inst(inst) => hack::Cmd::Inst(*inst),
+    }
+  }
+}
+
+impl fmt::Display for Cmd {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.borrow().fmt(f)
+  }
+}
+
+/// A HACK program that owns the names of its labels, variables and
+/// constants.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::Emu;
+/// use has::hack::OwnedProg;
+/// use has::hack::Prog;
+///
+/// let owned = {
+///   let buf = String::from("@i\nM=1\n(LOOP)\n@LOOP\n0;JMP");
+///   OwnedProg::from(&Prog::from_source(buf.as_bytes()).unwrap())
+/// };
+///
+/// // The program outlives its input buffer.
+/// assert_eq!(owned.to_words().collect::<Result<Vec<_>, _>>(), Ok(vec![16, 0xEFC8, 2, 0xEA87]));
+/// let prog = owned.borrow();
+/// assert_eq!(prog.to_symbols().collect::<Vec<_>>(), ["label LOOP 2", "var i 16"]);
+///
+/// let mut emu = Emu::from_owned_prog(&owned).unwrap();
+/// emu.run(10).unwrap();
+/// assert_eq!(emu.ram()[16], 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Prog {
+  /// The symbol table.
+  symtable: Map<Label, u16>,
+
+  /// The entries of the symbol table that are user-defined variables.
+  vars: Set<Label>,
+
+  /// Constants defined with `.equ`.
+  consts: Map<Label, u16>,
+
+  /// List of instructions.
+  insts: Vec<Cmd>,
+
+  /// Source map: the location of each instruction in the input
+  /// buffer.
+  locs: Vec<Loc>,
+
+  /// Initial contents of the data memory.
+  data: Vec<(u16, u16)>,
+}
+
+impl Prog {
+  /// Create an owned program from a program, interning its names.
+  ///
+  /// Programs converted with the same interner share the storage of
+  /// their common names.
+  ///
+  /// # Arguments
+  ///
+  /// * `prog` - The program.
+  ///
+  /// * `interner` - The interner to store the names in.
+  pub fn new(prog: &hack::Prog, interner: &mut Interner) -> Self {
+    let mut label = |label: &hack::Label| Label::new(*label, interner);
+    let symtable = prog.symtable().iter().map(|(l, &addr)| (label(l), addr)).collect();
+    let vars = prog.vars().map(|(l, _)| label(l)).collect();
+    let consts = prog.consts().map(|(l, &value)| (label(l), value)).collect();
+    let insts = prog.insts().iter().map(|&cmd| Cmd::new(cmd, interner)).collect();
+    let locs = prog.source_map().to_vec();
+    let data = prog.data().to_vec();
+    Self { symtable, vars, consts, insts, locs, data }
+  }
+
+  /// Get the list of instructions in a program.
+  pub fn insts(&self) -> &[Cmd] {
+    &self.insts
+  }
+
+  /// Get the initial contents of the data memory, as pairs of
+  /// addresses and values.
+  pub fn data(&self) -> &[(u16, u16)] {
+    &self.data
+  }
+
+  /// Returns the value of a label, variable or constant.
+  fn lookup(&self, label: &Label) -> Option<u16> {
+    self.symtable.get(label).or_else(|| self.consts.get(label)).copied()
+  }
+
+  /// Create and return an encoder producing the 16-bit words of this
+  /// program, as [for borrowed programs](hack::Prog::to_words).
+  pub fn to_words(&self) -> impl Iterator<Item = Result<u16, hack::ProgErr>> + '_ {
+    self.insts.iter().enumerate().map(move |(i, cmd)| match (cmd, cmd.borrow()) {
+      (Cmd::Label(label), _) => {
+        self.lookup(label).ok_or_else(|| hack::ProgErr::LabelNotFound(label.to_string()))
+      }
+      (_, hack::Cmd::Inst(inst)) => Ok(u16::from(inst)),
+      // Labels in expressions are only owned again to be looked up.
+      (_, hack::Cmd::Addr(addr)) => {
+        addr.value(|label| self.lookup(&Label::new(label, &mut Interner::new()))).map_err(
+          |e| hack::ProgErr::Eval(e, self.locs.get(i).copied().unwrap_or_default()),
+        )
+      }
+    })
+  }
+
+  /// Encode this program and write it to `writer`, as
+  /// [for borrowed programs](hack::Prog::encode).
+  ///
+  /// # Arguments
+  ///
+  /// * `encoding` - The output encoding.
+  ///
+  /// * `writer` - The output stream.
+  pub fn encode(
+    &self,
+    encoding: dec::Encoding,
+    writer: &mut impl io::Write,
+  ) -> io::Result<()> {
+    Encoder(self.to_words()).write(encoding, writer)
+  }
+
+  /// Returns a program borrowing the names of this one.
+  ///
+  /// The borrowed program can be used like one parsed from a buffer
+  /// (e.g. to [list](hack::Prog::to_source) it). Its tables are built
+  /// on each call, so owned programs are better encoded and run
+  /// directly.
+  pub fn borrow(&self) -> hack::Prog<'_> {
+    hack::Prog::from_parts(
+      self.symtable.iter().map(|(label, &addr)| (label.borrow(), addr)).collect(),
+      self.vars.iter().map(Label::borrow).collect(),
+      self.consts.iter().map(|(label, &value)| (label.borrow(), value)).collect(),
+      self.insts.iter().map(Cmd::borrow).collect(),
+      self.locs.clone(),
+      self.data.clone(),
+    )
+  }
+}
+
+impl From<&hack::Prog<'_>> for Prog {
+  fn from(prog: &hack::Prog) -> Self {
+    Self::new(prog, &mut Interner::new())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hack::Source;
+  use std::path::Path;
+  use std::thread;
+
+  #[test]
+  fn round_trip() {
+    let main = b".equ N 2\n.var x @ 100\n.data 5\n@N\nD=A\n(MULT)\n(.loop)\n@.loop+N\nD;JGT\n@x\nM=D";
+    let lib = b"(END)\n@END\n0;JMP";
+    let sources =
+      [Source::new(Path::new("Main.asm"), main), Source::new(Path::new("Lib.asm"), lib)];
+    let prog = hack::Prog::from_sources(&sources).unwrap();
+    let owned = Prog::from(&prog);
+    let borrowed = owned.borrow();
+
+    let words =
+      |prog: &hack::Prog| prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(words(&borrowed), words(&prog));
+    assert_eq!(
+      borrowed.to_symbols().collect::<Vec<_>>(),
+      prog.to_symbols().collect::<Vec<_>>()
+    );
+    assert_eq!(
+      borrowed.to_source().collect::<Vec<_>>(),
+      prog.to_source().collect::<Vec<_>>()
+    );
+    assert_eq!(borrowed.source_map(), prog.source_map());
+    assert_eq!(borrowed.data(), prog.data());
+    assert_eq!(borrowed.consts().count(), 1);
+    assert_eq!(owned.insts()[2].to_string(), "@.loop+N");
+    assert_eq!(borrowed.insts()[2], prog.insts()[2]);
+
+    assert_eq!(owned.to_words().collect::<Result<Vec<_>, _>>(), Ok(words(&prog)));
+    assert_eq!(owned.data(), prog.data());
+    let (mut ours, mut theirs) = (Vec::new(), Vec::new());
+    owned.encode(dec::Encoding::Ihex, &mut ours).unwrap();
+    prog.encode(dec::Encoding::Ihex, &mut theirs).unwrap();
+    assert_eq!(ours, theirs);
+  }
+
+  #[test]
+  fn threads() {
+    let buf = String::from("(LOOP)\n@LOOP\n0;JMP");
+    let owned = Prog::from(&hack::Prog::from_source(buf.as_bytes()).unwrap());
+    drop(buf);
+
+    let owned = Arc::new(owned);
+    let handles = (0..2)
+      .map(|_| {
+        let owned = Arc::clone(&owned);
+        thread::spawn(move || owned.borrow().to_words().collect::<Result<Vec<_>, _>>())
+      })
+      .collect::<Vec<_>>();
+
+    for handle in handles {
+      assert_eq!(handle.join().unwrap(), Ok(vec![0, 0xEA87]));
+    }
+  }
+
+  #[test]
+  fn shared_names() {
+    let mut interner = Interner::new();
+    let a =
+      Prog::new(&hack::Prog::from_source(b"(LOOP)\n@LOOP\n@x").unwrap(), &mut interner);
+    let b =
+      Prog::new(&hack::Prog::from_source(b"@x\n(LOOP)\n@LOOP").unwrap(), &mut interner);
+    assert_eq!(interner.len(), 2);
+
+    match (&a.insts()[0], &b.insts()[1]) {
+      (Cmd::Label(a), Cmd::Label(b)) => assert!(Arc::ptr_eq(&a.name, &b.name)),
+      _ => panic!("expected labels"),
+    }
+  }
+}
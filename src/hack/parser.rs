@@ -5,16 +5,22 @@
 
 use crate::hack::Addr;
 use crate::hack::AddrErr;
+use crate::hack::Anon;
+use crate::hack::Call;
+use crate::hack::Directive;
+use crate::hack::DirectiveErr;
 use crate::hack::Inst;
 use crate::hack::InstErr;
 use crate::hack::Label;
 use crate::hack::LabelErr;
+use crate::hack::MacroErr;
 use crate::parser;
 use crate::Buf;
 use crate::Index;
 use crate::Loc;
 use derive_more::Display;
 use std::convert::TryFrom;
+use std::ops::Range;
 
 /// Parser state for parsing HACK programs.
 ///
@@ -25,9 +31,10 @@ use std::convert::TryFrom;
 /// # impl `Iterator`
 ///
 /// The parse tree of HACK programs is a flat list. A parser object
-/// returns [tokens](Token) when iterated over. Perhaps at a later
-/// point a conversion to an abstract syntax tree could reflect the
-/// structure of labels and jumps in the program.
+/// returns [tokens](Token) when iterated over, skipping whitespace
+/// and comments (see [Parser::lossless] to keep them). Perhaps at a
+/// later point a conversion to an abstract syntax tree could reflect
+/// the structure of labels and jumps in the program.
 ///
 /// ## Examples
 ///
@@ -58,6 +65,20 @@ use std::convert::TryFrom;
 /// let inst = Token::new(11, TokenKind::Inst(inst));
 /// assert_eq!(parser.next(), Some(Ok(inst)));
 /// ```
+///
+/// ## Error recovery
+///
+/// After returning an error, the parser skips the rest of the line
+/// on which the error occurred, so iteration can continue to find
+/// further errors.
+///
+/// ```
+/// use has::hack::Parser;
+///
+/// let prog = "@-1\nD=A\nD=X\n@FOO".as_bytes();
+/// let results = Parser::from(prog).map(|t| t.is_ok()).collect::<Vec<_>>();
+/// assert_eq!(results, [false, true, false, true]);
+/// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Parser<'b> {
   /// The current point in the input buffer.
@@ -75,6 +96,14 @@ impl<'b> Parser<'b> {
   pub fn orig(&self) -> Buf<'b> {
     self.orig
   }
+
+  /// Skip the rest of the current line and return `err`.
+  fn recover(&mut self, err: Err) -> Option<Result<Token<'b>, Err>> {
+    let (skipped, rem) = parser::read_until_nl(self.buf);
+    self.index += skipped.len();
+    self.buf = rem;
+    Some(Err(err))
+  }
 }
 
 impl<'b> From<Buf<'b>> for Parser<'b> {
@@ -95,6 +124,11 @@ pub enum TokenKind<'b> {
   /// An instruction as defined by the HACK assembly reference
   /// (e.g. `D=A+1;JMP`).
   Inst(Inst),
+  /// An assembler [directive](Directive) (e.g. `.macro PUSH_D`).
+  Directive(Directive<'b>),
+  /// A [call](Call) of a macro or pseudo-instruction (e.g. `GOTO
+  /// LOOP`).
+  Call(Call<'b>),
 }
 
 /// Units returned by iterating over a [Parser].
@@ -143,6 +177,16 @@ impl<'b> Token<'b> {
   pub fn inst(index: Index, inst: Inst) -> Self {
     Token::new(index, TokenKind::Inst(inst))
   }
+
+  /// Create a token with the `TokenKind::Directive` variant.
+  pub fn directive(index: Index, directive: Directive<'b>) -> Self {
+    Token::new(index, TokenKind::Directive(directive))
+  }
+
+  /// Create a token with the `TokenKind::Call` variant.
+  pub fn call(index: Index, call: Call<'b>) -> Self {
+    Token::new(index, TokenKind::Call(call))
+  }
 }
 
 /// Kind of parsing error.
@@ -167,6 +211,14 @@ pub enum ErrKind {
   /// Invalid instruction.
   #[display(fmt = "invalid instruction: {}", _0)]
   InvalidInst(InstErr),
+
+  /// Invalid directive.
+  #[display(fmt = "invalid directive: {}", _0)]
+  InvalidDirective(DirectiveErr),
+
+  /// Invalid macro call.
+  #[display(fmt = "invalid macro call: {}", _0)]
+  InvalidCall(MacroErr),
 }
 
 /// Error during parsing.
@@ -205,6 +257,12 @@ impl Err {
     &self.kind
   }
 
+  /// Returns the same error located in the source file with index
+  /// `file`.
+  pub fn in_file(self, file: usize) -> Self {
+    Self { loc: self.loc.in_file(file), ..self }
+  }
+
   /// Create an error with the `ErrKind::ExpectedComment` variant.
   pub fn expected_comment(parser: &Parser) -> Self {
     Err::new(parser.orig, parser.index + 1, ErrKind::ExpectedComment)
@@ -229,6 +287,16 @@ impl Err {
   pub fn invalid_inst(parser: &Parser, err: InstErr) -> Self {
     Err::new(parser.orig, parser.index, ErrKind::InvalidInst(err))
   }
+
+  /// Create an error with the `ErrKind::InvalidDirective` variant.
+  pub fn invalid_directive(parser: &Parser, err: DirectiveErr) -> Self {
+    Err::new(parser.orig, parser.index, ErrKind::InvalidDirective(err))
+  }
+
+  /// Create an error with the `ErrKind::InvalidCall` variant.
+  pub fn invalid_call(parser: &Parser, err: MacroErr) -> Self {
+    Err::new(parser.orig, parser.index, ErrKind::InvalidCall(err))
+  }
 }
 
 impl<'b> Iterator for Parser<'b> {
@@ -244,10 +312,10 @@ impl<'b> Iterator for Parser<'b> {
         self.buf = rem;
         continue 'MAIN;
       } else if b == b'/' {
-        match self.buf.first() {
+        match self.buf.get(1) {
           Some(b'/') => {}
-          Some(_) => return Some(Err(Err::expected_comment(self))),
-          None => return Some(Err(Err::expected_comment(self))),
+          Some(_) => return self.recover(Err::expected_comment(self)),
+          None => return self.recover(Err::expected_comment(self)),
         }
 
         let (com, rem) = parser::read_until_nl(self.buf);
@@ -258,12 +326,15 @@ impl<'b> Iterator for Parser<'b> {
         let (txt, rem) = parser::read_while(&self.buf[1..], |b| b != b')');
         let label = match Label::try_from(txt) {
           Ok(label) => label,
-          Err(e) => return Some(Err(Err::invalid_label(self, e))),
+          Err(e) => match Label::anonymous(txt) {
+            Some(label) if matches!(label.anon(), Some(Anon::Decl(_))) => label,
+            _ => return self.recover(Err::invalid_label(self, e)),
+          },
         };
 
         self.buf = match parser::read_one(rem, |b| b == b')') {
           Some((_, rem)) => rem,
-          None => return Some(Err(Err::missing_lparen(self, txt.len()))),
+          None => return self.recover(Err::missing_lparen(self, txt.len())),
         };
 
         let tok = Token::label(self.index, label);
@@ -277,25 +348,197 @@ impl<'b> Iterator for Parser<'b> {
             self.index += len + 1;
             return Some(Ok(tok));
           }
-          Err(e) => return Some(Err(Err::invalid_addr(self, e))),
+          Err(e) => return self.recover(Err::invalid_addr(self, e)),
+        }
+      } else if b == b'.' {
+        match Directive::read_from(&self.buf[1..]) {
+          Ok((directive, rem, len)) => {
+            let tok = Token::directive(self.index, directive);
+            self.buf = rem;
+            self.index += len + 1;
+            return Some(Ok(tok));
+          }
+          Err(e) => return self.recover(Err::invalid_directive(self, e)),
         }
       } else {
-        match Inst::read_from(self.buf) {
+        let err = match Inst::read_from(self.buf) {
           Ok((inst, rem, len)) => {
             let tok = Token::inst(self.index, inst);
             self.buf = rem;
             self.index += len;
             return Some(Ok(tok));
           }
-          Err(e) => return Some(Err(Err::invalid_inst(self, e))),
+          Err(e) => e,
+        };
+
+        if !Call::is_call(self.buf) {
+          return self.recover(Err::invalid_inst(self, err));
+        }
+
+        match Call::read_from(self.buf) {
+          Ok((call, rem, len)) => {
+            let tok = Token::call(self.index, call);
+            self.buf = rem;
+            self.index += len;
+            return Some(Ok(tok));
+          }
+          Err(e) => return self.recover(Err::invalid_call(self, e)),
         }
       }
     }
   }
 }
 
+impl<'b> Parser<'b> {
+  /// Turn the parser into a [lossless](Lossless) one that also
+  /// returns whitespace, newlines and comments.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::ElementKind;
+  /// use has::hack::Parser;
+  ///
+  /// let prog = "@FOO // Bar\n".as_bytes();
+  /// let elems = Parser::from(prog).lossless().collect::<Vec<_>>();
+  /// let texts = elems.iter().map(|e| e.text()).collect::<Vec<_>>();
+  /// assert_eq!(texts, ["@FOO".as_bytes(), b" ", b"// Bar", b"\n"]);
+  /// assert_eq!(elems[2].kind(), &ElementKind::Comment);
+  /// assert_eq!(elems[2].span(), 5..11);
+  /// ```
+  pub fn lossless(self) -> Lossless<'b> {
+    Lossless { parser: self }
+  }
+}
+
+/// A parser that, unlike [Parser], does not discard anything from
+/// its input.
+///
+/// Iterating over a lossless parser returns [elements](Element) that
+/// cover the input buffer exactly: concatenating their texts in order
+/// reproduces the input. This makes it possible for tools such as
+/// formatters to round-trip a file, including its comments and
+/// blank lines.
+///
+/// Parsing errors are elements too, covering the rest of the line on
+/// which they occurred.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::Parser;
+///
+/// let prog = "(LOOP)\n  @LOOP  \r\n\n  0;JPM // Oops\n".as_bytes();
+/// let elems = Parser::from(prog).lossless();
+/// let texts = elems.map(|e| e.text()).collect::<Vec<_>>();
+/// assert_eq!(texts.concat(), prog);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lossless<'b> {
+  /// The underlying parser.
+  parser: Parser<'b>,
+}
+
+/// The kind of an [Element].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElementKind<'b> {
+  /// A label, address or instruction.
+  Token(TokenKind<'b>),
+
+  /// Invalid input.
+  Error(Err),
+
+  /// Whitespace other than newlines.
+  Whitespace,
+
+  /// A single newline. Consecutive newlines form blank lines.
+  Newline,
+
+  /// A comment, including the leading `//` but not the newline.
+  Comment,
+}
+
+/// Units returned by iterating over a [Lossless] parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element<'b> {
+  /// Index of the element in the original input buffer.
+  index: Index,
+
+  /// The text of the element.
+  text: Buf<'b>,
+
+  /// The type of element.
+  kind: ElementKind<'b>,
+}
+
+impl<'b> Element<'b> {
+  /// Returns the element's index in the input buffer.
+  pub fn index(&self) -> Index {
+    self.index
+  }
+
+  /// Returns the element's span in the input buffer.
+  pub fn span(&self) -> Range<Index> {
+    self.index..self.index + self.text.len()
+  }
+
+  /// Returns the element's text.
+  pub fn text(&self) -> Buf<'b> {
+    self.text
+  }
+
+  /// Returns the kind of element.
+  pub fn kind(&self) -> &ElementKind<'b> {
+    &self.kind
+  }
+
+  /// Whether the element is whitespace, a newline or a comment.
+  pub fn is_trivia(&self) -> bool {
+    matches!(
+      self.kind,
+      ElementKind::Whitespace | ElementKind::Newline | ElementKind::Comment
+    )
+  }
+}
+
+impl<'b> Iterator for Lossless<'b> {
+  type Item = Element<'b>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let parser = &mut self.parser;
+    let index = parser.index;
+    let &b = parser.buf.first()?;
+
+    let kind = if b == b'\n' {
+      parser.buf = &parser.buf[1..];
+      parser.index += 1;
+      ElementKind::Newline
+    } else if b.is_ascii_whitespace() {
+      let (ws, rem) =
+        parser::read_while(parser.buf, |b| b.is_ascii_whitespace() && b != b'\n');
+      parser.buf = rem;
+      parser.index += ws.len();
+      ElementKind::Whitespace
+    } else if parser.buf.starts_with(b"//") {
+      let (com, rem) = parser::read_until_nl(parser.buf);
+      parser.buf = rem;
+      parser.index += com.len();
+      ElementKind::Comment
+    } else {
+      match parser.next()? {
+        Ok(tok) => ElementKind::Token(tok.kind),
+        Err(e) => ElementKind::Error(e),
+      }
+    };
+
+    let text = &parser.orig[index..parser.index];
+    Some(Element { index, text, kind })
+  }
+}
+
 #[cfg(test)]
 mod tests {
+  use super::ErrKind;
   use super::Parser;
   use super::TokenKind;
   use crate::hack::Addr;
@@ -418,4 +661,83 @@ mod tests {
 
     assert_eq!(p.next(), None);
   }
+
+  #[test]
+  fn local_labels() {
+    let mut p = Parser::from(&b"(.loop)\n@.loop\n(1)\n@1f\n@12b\n(1f)\n@1x"[..]);
+    let anon = |name: &'static str| Label::anonymous(name.as_bytes()).unwrap();
+
+    next!(p, 1, 1, TokenKind::Label, label!(".loop"));
+    next!(p, 2, 1, TokenKind::Addr, Addr::Label(label!(".loop")));
+    next!(p, 3, 1, TokenKind::Label, anon("1"));
+    next!(p, 4, 1, TokenKind::Addr, Addr::Label(anon("1f")));
+    next!(p, 5, 1, TokenKind::Addr, Addr::Label(anon("12b")));
+    let err = p.next().unwrap().unwrap_err();
+    assert_eq!(err.loc(), Loc::new(6, 1));
+    let err = p.next().unwrap().unwrap_err();
+    assert_eq!(err.loc(), Loc::new(7, 1));
+    assert_eq!(p.next(), None);
+  }
+
+  #[test]
+  fn recovery() {
+    let mut p = Parser::from(&b"(1A) @FOO\n@-1 D=A\nD=A\n(FOO\n@BAR\n/ D=A\n/"[..]);
+
+    let err = p.next().unwrap().unwrap_err();
+    assert_eq!(err.loc(), Loc::new(1, 1));
+    let err = p.next().unwrap().unwrap_err();
+    assert_eq!(err.loc(), Loc::new(2, 1));
+    next!(p, 3, 1, TokenKind::Inst, inst!(Dest::D, Comp::A, Jump::Null));
+    let err = p.next().unwrap().unwrap_err();
+    assert_eq!(err.loc(), Loc::new(4, 1));
+    next!(p, 5, 1, TokenKind::Addr, Addr::Label(label!("BAR")));
+    let err = p.next().unwrap().unwrap_err();
+    assert_eq!((err.loc(), err.kind()), (Loc::new(6, 2), &ErrKind::ExpectedComment));
+    let err = p.next().unwrap().unwrap_err();
+    assert_eq!(err.loc(), Loc::new(7, 2));
+    assert_eq!(p.next(), None);
+  }
+
+  #[test]
+  fn lossless() {
+    use super::ElementKind;
+
+    let snippets: [&[u8]; 5] = [
+      include_bytes!("../../tests/snippets/comments.asm"),
+      include_bytes!("../../tests/snippets/spaces.asm"),
+      include_bytes!("../../tests/snippets/label.asm"),
+      include_bytes!("../../tests/snippets/instructions.asm"),
+      include_bytes!("../../tests/snippets/addr_labels.asm"),
+    ];
+
+    for buf in snippets {
+      let texts = Parser::from(buf).lossless().map(|e| e.text()).collect::<Vec<_>>();
+      assert_eq!(texts.concat(), buf);
+    }
+
+    let buf = &b"\t@1 D=A\r\n\n(1A) //C\n// C\n/x"[..];
+    let elems = Parser::from(buf).lossless().collect::<Vec<_>>();
+    let kinds = elems.iter().map(|e| e.kind()).collect::<Vec<_>>();
+    assert!(matches!(
+      kinds[..],
+      [
+        ElementKind::Whitespace,
+        ElementKind::Token(TokenKind::Addr(Addr::Num(1))),
+        ElementKind::Whitespace,
+        ElementKind::Token(TokenKind::Inst(_)),
+        ElementKind::Whitespace,
+        ElementKind::Newline,
+        ElementKind::Newline,
+        ElementKind::Error(_),
+        ElementKind::Newline,
+        ElementKind::Comment,
+        ElementKind::Newline,
+        ElementKind::Error(_),
+      ]
+    ));
+    assert_eq!(elems[4].text(), b"\r");
+    assert_eq!(elems[7].span(), 10..18);
+    assert!(elems[9].is_trivia() && !elems[7].is_trivia());
+    assert_eq!(elems[11].text(), b"/x");
+  }
 }
@@ -2,23 +2,42 @@
 //!
 //! [Prog] can be used to represent the (flat) parse tree of a HACK
 //! assembly program. The program can be parsed from HACK assembly
-//! source code or disassembled from a compiled HACK binary or bintext
-//! file.
+//! source code or disassembled from a compiled HACK binary, bintext,
+//! Intel HEX, Logisim or Verilog memory image file.
 
 use crate::conv;
 use crate::hack::dec;
+use crate::hack::macros;
+use crate::hack::macros::Expanded;
+use crate::hack::macros::Item;
+use crate::hack::macros::Macros;
+use crate::hack::source;
 use crate::hack::Addr;
+use crate::hack::AddrErr;
+use crate::hack::Anon;
 use crate::hack::Cmd;
 use crate::hack::CmdErr;
+use crate::hack::Directive;
+use crate::hack::Expr;
 use crate::hack::Label;
+use crate::hack::Macro;
+use crate::hack::MacroErr;
 use crate::hack::Parser;
 use crate::hack::ParserErr;
+use crate::hack::Source;
+use crate::hack::Sym;
 use crate::hack::TokenKind;
+use crate::utils::loc::Locator;
 use crate::Buf;
 use crate::Loc;
+use atoi::FromRadix10Checked;
 use derive_more::Display;
 use derive_more::From;
 use std::collections::HashMap as Map;
+use std::collections::HashSet as Set;
+use std::convert::TryFrom;
+use std::io;
+use std::path::Path;
 
 /// Symbol table.
 pub type Symtable<'b> = Map<Label<'b>, u16>;
@@ -31,8 +50,22 @@ pub struct Prog<'b> {
   /// The symbol table for forward declarations.
   symtable: Symtable<'b>,
 
+  /// The entries of the symbol table that are user-defined variables
+  /// (as opposed to labels).
+  vars: Set<Label<'b>>,
+
+  /// Constants defined with `.equ`.
+  consts: Map<Label<'b>, u16>,
+
   /// List of collected instructions.
   insts: Vec<Cmd<'b>>,
+
+  /// Source map: the location of each instruction in the input
+  /// buffer.
+  locs: Vec<Loc>,
+
+  /// Initial contents of the data memory set with `.data`.
+  data: Vec<(u16, u16)>,
 }
 
 /// Possible errors returned from loading a HACK assembly program.
@@ -54,6 +87,17 @@ pub enum Err {
   #[from(ignore)]
   DuplicateLabel(String, Loc),
 
+  /// Invalid entry in a symbol table file.
+  #[display(fmt = "Invalid symbol table entry at {}", _0)]
+  InvalidSymbol(Loc),
+
+  /// A label is used as a jump target but never declared.
+  ///
+  /// Contains the name and location of the reference.
+  #[display(fmt = "Undefined label `{}` at `{}`", _0, _1)]
+  #[from(ignore)]
+  UndefinedLabel(String, Loc),
+
   /// Label or user-defined variable not found.
   #[display(fmt = "Label or variable `{}` not found", _0)]
   LabelNotFound(String),
@@ -61,6 +105,479 @@ pub enum Err {
   /// Instruction decoding error.
   #[display(fmt = "Decoding error: {}", _0)]
   Decode(CmdErr),
+
+  /// A macro call cannot be expanded.
+  ///
+  /// Contains the error and the location of the call.
+  #[display(fmt = "Macro error at {}: {}", _1, _0)]
+  #[from(ignore)]
+  Macro(MacroErr, Loc),
+
+  /// A macro is defined more than once or with the name of a
+  /// pseudo-instruction.
+  ///
+  /// Contains the name and location of the definition.
+  #[display(fmt = "Duplicate macro `{}` at `{}`", _0, _1)]
+  #[from(ignore)]
+  DuplicateMacro(String, Loc),
+
+  /// A macro definition is missing its `.endm`.
+  ///
+  /// Contains the name and location of the definition.
+  #[display(fmt = "Unclosed macro `{}` at `{}`", _0, _1)]
+  #[from(ignore)]
+  UnclosedMacro(String, Loc),
+
+  /// A macro definition inside another one.
+  #[display(fmt = "Nested macro definition at `{}`", _0)]
+  #[from(ignore)]
+  NestedMacro(Loc),
+
+  /// An `.endm` outside of a macro definition.
+  #[display(fmt = "Unexpected `.endm` at `{}`", _0)]
+  #[from(ignore)]
+  UnexpectedEndMacro(Loc),
+
+  /// An `.include` or `.equ` directive inside a macro definition.
+  ///
+  /// Contains the name and location of the directive.
+  #[display(fmt = "Directive `.{}` in macro definition at `{}`", _0, _1)]
+  #[from(ignore)]
+  DirectiveInMacro(String, Loc),
+
+  /// A constant is defined more than once or with the name of a
+  /// label.
+  ///
+  /// Contains the name and location of the definition.
+  #[display(fmt = "Duplicate constant `{}` at `{}`", _0, _1)]
+  #[from(ignore)]
+  DuplicateConst(String, Loc),
+
+  /// The value of a constant or an expression cannot be evaluated.
+  ///
+  /// Contains the error and the location of the definition or
+  /// instruction.
+  #[display(fmt = "Evaluation error at {}: {}", _1, _0)]
+  #[from(ignore)]
+  Eval(AddrErr, Loc),
+
+  /// An included file is not among the source files of the program.
+  ///
+  /// Contains the path of the file and the location of the directive.
+  #[display(fmt = "Missing included file `{}` at `{}`", _0, _1)]
+  #[from(ignore)]
+  MissingInclude(String, Loc),
+
+  /// A label declaration inside a macro definition.
+  ///
+  /// Contains the name and location of the label.
+  #[display(fmt = "Label `{}` declared in macro at `{}`", _0, _1)]
+  #[from(ignore)]
+  LabelInMacro(String, Loc),
+
+  /// A variable is declared more than once or with the name of a
+  /// label.
+  ///
+  /// Contains the name and location of the declaration.
+  #[display(fmt = "Duplicate variable `{}` at `{}`", _0, _1)]
+  #[from(ignore)]
+  DuplicateVar(String, Loc),
+
+  /// A variable or array does not fit in the data memory below the
+  /// screen memory map.
+  ///
+  /// Contains the name of the variable and the location of its
+  /// declaration or first use.
+  #[display(fmt = "Variable `{}` at `{}` overlaps the screen memory map", _0, _1)]
+  #[from(ignore)]
+  RamOverflow(String, Loc),
+
+  /// A `.data` directive without a preceding `.var` or `.reserve`.
+  #[display(fmt = "Data without a variable at `{}`", _0)]
+  #[from(ignore)]
+  DataWithoutVar(Loc),
+
+  /// A `.data` value past the end of its array or of the data memory.
+  ///
+  /// Contains the name of the variable and the location of the
+  /// directive.
+  #[display(fmt = "Data of `{}` at `{}` exceeds its reserved space", _0, _1)]
+  #[from(ignore)]
+  DataOverflow(String, Loc),
+
+  /// An array is reserved with a size of 0.
+  ///
+  /// Contains the name and location of the declaration.
+  #[display(fmt = "Array `{}` at `{}` has a size of 0", _0, _1)]
+  #[from(ignore)]
+  EmptyArray(String, Loc),
+
+  /// A variable is placed with `.var` at the address of another one.
+  ///
+  /// Contains the names of the variable and of the other one, and the
+  /// location of the declaration.
+  #[display(fmt = "Variable `{}` at `{}` overlaps `{}`", _0, _2, _1)]
+  #[from(ignore)]
+  RamOverlap(String, String, Loc),
+
+  /// The data of a variable placed with `.var` extends into another
+  /// variable or its data.
+  ///
+  /// Contains the names of the variable and of the other one, and the
+  /// location of the directive.
+  #[display(fmt = "Data of `{}` at `{}` overlaps `{}`", _0, _2, _1)]
+  #[from(ignore)]
+  DataOverlap(String, String, Loc),
+}
+
+impl Err {
+  /// Returns the [location](Loc) in the input buffer at which the
+  /// error occurred, if known.
+  pub fn loc(&self) -> Option<Loc> {
+    match self {
+      Err::Asm(e) => Some(e.loc()),
+      Err::DuplicateLabel(_, loc)
+      | Err::InvalidSymbol(loc)
+      | Err::UndefinedLabel(_, loc)
+      | Err::Macro(_, loc)
+      | Err::DuplicateMacro(_, loc)
+      | Err::UnclosedMacro(_, loc)
+      | Err::NestedMacro(loc)
+      | Err::UnexpectedEndMacro(loc)
+      | Err::DirectiveInMacro(_, loc)
+      | Err::DuplicateConst(_, loc)
+      | Err::Eval(_, loc)
+      | Err::MissingInclude(_, loc)
+      | Err::LabelInMacro(_, loc)
+      | Err::DuplicateVar(_, loc)
+      | Err::RamOverflow(_, loc)
+      | Err::DataWithoutVar(loc)
+      | Err::DataOverflow(_, loc)
+      | Err::EmptyArray(_, loc)
+      | Err::RamOverlap(_, _, loc)
+      | Err::DataOverlap(_, _, loc) => Some(*loc),
+      Err::Dis(_) | Err::LabelNotFound(_) | Err::Decode(_) => None,
+    }
+  }
+}
+
+/// State of the assembler shared by the source files of a program.
+struct Assembler<'s, 'b> {
+  /// The source files of the program.
+  sources: &'s [Source<'b>],
+
+  /// Whether each source file was already assembled.
+  visited: Vec<bool>,
+
+  /// The symbol table for forward declarations.
+  symtable: Symtable<'b>,
+
+  /// List of collected instructions.
+  insts: Vec<Cmd<'b>>,
+
+  /// Source map of the collected instructions.
+  locs: Vec<Loc>,
+
+  /// Errors found so far.
+  errs: Vec<Err>,
+
+  /// ROM address of the next instruction.
+  index: u16,
+
+  /// Macros defined so far.
+  macros: Macros<'b>,
+
+  /// Constants defined so far, with the locations of their
+  /// definitions.
+  consts: Map<Label<'b>, (u16, Loc)>,
+
+  /// ROM addresses of the anonymous labels declared so far, by
+  /// number.
+  anons: Map<u16, Vec<u16>>,
+
+  /// References to anonymous labels, with the index of their
+  /// instruction and the number of matching declarations before it.
+  anon_refs: Vec<(usize, Label<'b>, usize)>,
+
+  /// Variables declared with `.var`, with the locations of their
+  /// declarations.
+  placed: Vec<(Label<'b>, Loc)>,
+
+  /// Arrays declared with `.reserve`, with their sizes and the
+  /// locations of their declarations.
+  reserves: Vec<(Label<'b>, u16, Loc)>,
+
+  /// Values of `.data` directives, with the variable they initialize,
+  /// their offset from it and the location of the directive.
+  data: Vec<(Label<'b>, u16, Expr<'b>, Loc)>,
+
+  /// Constant expressions from macro expansions, with the index of
+  /// their instruction and the global label that local labels in them
+  /// are scoped to.
+  exprs: Vec<(usize, String, Option<Label<'b>>)>,
+}
+
+impl<'s, 'b> Assembler<'s, 'b> {
+  fn new(sources: &'s [Source<'b>]) -> Self {
+    Self {
+      sources,
+      visited: vec![false; sources.len()],
+      symtable: Map::new(),
+      insts: Vec::new(),
+      locs: Vec::new(),
+      errs: Vec::new(),
+      index: 0,
+      macros: Macros::new(),
+      consts: Map::new(),
+      anons: Map::new(),
+      anon_refs: Vec::new(),
+      placed: Vec::new(),
+      reserves: Vec::new(),
+      data: Vec::new(),
+      exprs: Vec::new(),
+    }
+  }
+
+  /// Declare a variable or array at `addr` in the symbol table.
+  ///
+  /// Returns false if the name is already taken.
+  fn declare(&mut self, name: Label<'b>, addr: u16, loc: Loc) -> bool {
+    if self.symtable.contains_key(&name) {
+      self.errs.push(Err::DuplicateVar(name.to_string(), loc));
+      return false;
+    }
+
+    self.symtable.insert(name, addr);
+    true
+  }
+
+  /// Add a command to the program, resolving its local labels in
+  /// `scope` and recording its references to anonymous labels.
+  fn push(&mut self, cmd: Cmd<'b>, loc: Loc, scope: Option<Label<'b>>) {
+    let cmd = match (cmd, scope) {
+      (Cmd::Addr(Addr::Label(label)), _) if label.anon().is_some() => {
+        let seen = label.anon().map_or(0, |anon| match anon {
+          Anon::Decl(n) | Anon::Forward(n) | Anon::Backward(n) => {
+            self.anons.get(&n).map_or(0, Vec::len)
+          }
+        });
+        self.anon_refs.push((self.insts.len(), label, seen));
+        cmd
+      }
+      (Cmd::Addr(Addr::Label(label)), Some(scope)) => {
+        Cmd::Addr(Addr::Label(label.scoped(scope)))
+      }
+      (Cmd::Addr(Addr::Expr(expr)), Some(scope)) => {
+        Cmd::Addr(Addr::Expr(expr.scoped(scope)))
+      }
+      (cmd, _) => cmd,
+    };
+
+    self.insts.push(cmd);
+    self.locs.push(loc);
+    self.index += 1;
+  }
+
+  /// Replace the references to anonymous labels with the ROM
+  /// addresses of the labels they refer to.
+  fn resolve_anons(&mut self) {
+    for &(i, label, seen) in &self.anon_refs {
+      let addrs = |n| self.anons.get(&n).map_or(&[][..], Vec::as_slice);
+      let addr = match label.anon() {
+        Some(Anon::Forward(n)) => addrs(n).get(seen),
+        Some(Anon::Backward(n)) => seen.checked_sub(1).and_then(|k| addrs(n).get(k)),
+        _ => None,
+      };
+
+      match addr {
+        Some(&addr) => self.insts[i] = Cmd::Addr(Addr::Num(addr)),
+        None => {
+          self.errs.push(Err::UndefinedLabel(label.to_string(), self.locs[i]));
+          // Keep the reference from being allocated as a variable.
+          self.insts[i] = Cmd::Addr(Addr::Num(0));
+        }
+      }
+    }
+  }
+
+  /// Assemble the source file with index `file`, including the files
+  /// it includes.
+  fn source(&mut self, file: usize) {
+    self.visited[file] = true;
+
+    let source = self.sources[file];
+    let buf = source.buf();
+    let mut locator = Locator::new(buf);
+    let mut current: Option<(Macro, Loc)> = None;
+    let mut scope: Option<Label> = None;
+    let mut last_var: Option<(Label, u16)> = None;
+
+    for token in Parser::from(buf) {
+      let token = match token {
+        Ok(token) => token,
+        Err(e) => {
+          self.errs.push(Err::Asm(e.in_file(file)));
+          continue;
+        }
+      };
+
+      let token_index = token.index();
+      let token_loc = || Loc::from_index(buf, token_index).in_file(file);
+
+      if let Some((mac, _)) = &mut current {
+        match token.kind() {
+          TokenKind::Label(label) => {
+            self.errs.push(Err::LabelInMacro(String::from(label.name()), token_loc()))
+          }
+          TokenKind::Addr(addr) => mac.push(Item::Cmd(Cmd::Addr(addr))),
+          TokenKind::Inst(inst) => mac.push(Item::Cmd(Cmd::Inst(inst))),
+          TokenKind::Call(call) => mac.push(Item::Call(call)),
+          TokenKind::Directive(Directive::Macro(..)) => {
+            self.errs.push(Err::NestedMacro(token_loc()))
+          }
+          TokenKind::Directive(Directive::EndMacro) => {
+            if let Some((mac, loc)) = current.take() {
+              let name = mac.name();
+
+              if macros::is_pseudo(name) || self.macros.insert(name, mac).is_some() {
+                self.errs.push(Err::DuplicateMacro(String::from(name.name()), loc));
+              }
+            }
+          }
+          TokenKind::Directive(directive) => self
+            .errs
+            .push(Err::DirectiveInMacro(String::from(directive.name()), token_loc())),
+        }
+
+        continue;
+      }
+
+      match token.kind() {
+        TokenKind::Label(label) => {
+          if let Some(Anon::Decl(n)) = label.anon() {
+            self.anons.entry(n).or_default().push(self.index);
+            continue;
+          }
+
+          let label = match scope {
+            Some(scope) if label.is_local() => label.scoped(scope),
+            _ if label.is_local() => label,
+            _ => *scope.insert(label),
+          };
+
+          if self.symtable.insert(label, self.index).is_some() {
+            self.errs.push(Err::DuplicateLabel(label.to_string(), token_loc()));
+          }
+        }
+        TokenKind::Addr(addr) => {
+          let loc = locator.locate(token_index).in_file(file);
+          self.push(Cmd::Addr(addr), loc, scope);
+        }
+        TokenKind::Inst(inst) => {
+          let loc = locator.locate(token_index).in_file(file);
+          self.push(Cmd::Inst(inst), loc, scope);
+        }
+        TokenKind::Directive(Directive::Macro(name, params)) => {
+          current = Some((Macro::new(name, params), token_loc()));
+        }
+        TokenKind::Directive(Directive::EndMacro) => {
+          self.errs.push(Err::UnexpectedEndMacro(token_loc()));
+        }
+        TokenKind::Directive(Directive::Include(path)) => {
+          let path = source.resolve(path);
+
+          match self.sources.iter().position(|s| source::normalize(s.path()) == path) {
+            Some(included) if !self.visited[included] => self.source(included),
+            Some(_) => {}
+            None => {
+              self.errs.push(Err::MissingInclude(path.display().to_string(), token_loc()))
+            }
+          }
+        }
+        TokenKind::Directive(Directive::Equ(name, value)) => {
+          let consts = &self.consts;
+
+          match value.value(|label| consts.get(&label).map(|&(value, _)| value)) {
+            Ok(_) if consts.contains_key(&name) => {
+              self.errs.push(Err::DuplicateConst(String::from(name.name()), token_loc()))
+            }
+            Ok(value) => {
+              self.consts.insert(name, (value, token_loc()));
+            }
+            Err(e) => self.errs.push(Err::Eval(e, token_loc())),
+          }
+        }
+        TokenKind::Directive(Directive::Var(name, addr)) => {
+          let name = scope.map_or(name, |scope| name.scoped(scope));
+          let consts = &self.consts;
+
+          match addr.value(|label| consts.get(&label).map(|&(value, _)| value)) {
+            Ok(addr) => {
+              if self.declare(name, addr, token_loc()) {
+                self.placed.push((name, token_loc()));
+              }
+            }
+            Err(e) => self.errs.push(Err::Eval(e, token_loc())),
+          }
+
+          last_var = Some((name, 0));
+        }
+        TokenKind::Directive(Directive::Reserve(name, size)) => {
+          let name = scope.map_or(name, |scope| name.scoped(scope));
+          let consts = &self.consts;
+
+          match size.value(|label| consts.get(&label).map(|&(value, _)| value)) {
+            Ok(size) => {
+              if size == 0 {
+                self.errs.push(Err::EmptyArray(name.to_string(), token_loc()));
+              }
+
+              if self.declare(name, 0, token_loc()) {
+                self.reserves.push((name, size, token_loc()));
+              }
+            }
+            Err(e) => self.errs.push(Err::Eval(e, token_loc())),
+          }
+
+          last_var = Some((name, 0));
+        }
+        TokenKind::Directive(Directive::Data(values)) => match &mut last_var {
+          Some((name, offset)) => {
+            for value in values {
+              let value = scope.map_or(value, |scope| value.scoped(scope));
+              self.data.push((*name, *offset, value, token_loc()));
+              *offset = offset.saturating_add(1);
+            }
+          }
+          None => self.errs.push(Err::DataWithoutVar(token_loc())),
+        },
+        TokenKind::Call(call) => {
+          let loc = locator.locate(token_index).in_file(file);
+
+          match macros::expand(&call, &self.macros) {
+            Ok(cmds) => {
+              for cmd in cmds {
+                match cmd {
+                  Expanded::Cmd(cmd) => self.push(cmd, loc, scope),
+                  Expanded::Expr(text) => {
+                    // The expression is evaluated once the program is
+                    // assembled.
+                    self.exprs.push((self.insts.len(), text, scope));
+                    self.push(Cmd::Addr(Addr::Num(0)), loc, None);
+                  }
+                }
+              }
+            }
+            Err(e) => self.errs.push(Err::Macro(e, loc)),
+          }
+        }
+      }
+    }
+
+    if let Some((mac, loc)) = current {
+      self.errs.push(Err::UnclosedMacro(String::from(mac.name().name()), loc));
+    }
+  }
 }
 
 impl<'b> Prog<'b> {
@@ -79,46 +596,245 @@ impl<'b> Prog<'b> {
   /// assert_eq!(prog.insts().len(), 2);
   /// ```
   pub fn from_source(buf: Buf<'b>) -> Result<Self, Err> {
-    let mut symtable = Map::new();
-    let mut insts = Vec::new();
-    let parser = Parser::from(buf);
-    let mut index = 0;
+    Self::from_source_all(buf).map_err(|mut errs| errs.swap_remove(0))
+  }
 
-    for token in parser {
-      let token = token.map_err(Err::Asm)?;
-      let token_index = token.index();
+  /// Create a program from a buffer containing HACK assembly code,
+  /// reporting all errors.
+  ///
+  /// Unlike [Prog::from_source], parsing continues on the next line
+  /// after an error, so that all parsing errors and duplicate labels
+  /// in the program are reported, ordered by their location.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  /// use has::Loc;
+  ///
+  /// let buf = "(LOOP)\n@-1\n(LOOP)\n@LOPO\n0;JMP".as_bytes();
+  /// let errs = Prog::from_source_all(buf).err().unwrap();
+  /// let locs = errs.iter().map(|e| e.loc()).collect::<Vec<_>>();
+  /// assert_eq!(locs, [Some(Loc::new(2, 1)), Some(Loc::new(3, 1))]);
+  /// ```
+  pub fn from_source_all(buf: Buf<'b>) -> Result<Self, Vec<Err>> {
+    Self::from_sources(&[Source::new(Path::new(""), buf)])
+  }
 
-      match token.kind() {
-        TokenKind::Label(label) => {
-          if symtable.insert(label, index).is_some() {
-            let token_loc = Loc::from_index(buf, token_index);
-            return Err(Err::DuplicateLabel(String::from(label.name()), token_loc));
+  /// Create a program from multiple source files, reporting all
+  /// errors.
+  ///
+  /// The files are assembled in order into one program with a single
+  /// symbol table, as if they were concatenated. An `.include`
+  /// directive assembles the included file in its place instead,
+  /// unless that file was already assembled. Included files must be
+  /// among the `sources` (see [source::load]).
+  ///
+  /// Errors are reported like in [Prog::from_source_all], with the
+  /// index of their source file in their [location](Loc::file).
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  /// use has::hack::Source;
+  /// use has::Loc;
+  /// use std::path::Path;
+  ///
+  /// let main = Source::new(Path::new("Main.asm"), "@END\n0;JMP\n.include \"End.asm\"".as_bytes());
+  /// let end = Source::new(Path::new("End.asm"), "(END)\n@END\n0;JMP".as_bytes());
+  /// let prog = Prog::from_sources(&[main, end]).unwrap();
+  /// assert_eq!(prog.insts().len(), 4);
+  /// assert_eq!(prog.source_map()[2], Loc::new(2, 1).in_file(1));
+  ///
+  /// let lib = Source::new(Path::new("Lib.asm"), "(END)\n@-1".as_bytes());
+  /// let errs = Prog::from_sources(&[main, lib]).err().unwrap();
+  /// let locs = errs.iter().map(|e| e.loc()).collect::<Vec<_>>();
+  /// assert_eq!(locs, [Some(Loc::new(3, 1)), Some(Loc::new(2, 1).in_file(1))]);
+  /// ```
+  pub fn from_sources(sources: &[Source<'b>]) -> Result<Self, Vec<Err>> {
+    let mut asm = Assembler::new(sources);
+
+    for file in 0..sources.len() {
+      if !asm.visited[file] {
+        asm.source(file);
+      }
+    }
+
+    asm.resolve_anons();
+
+    let Assembler {
+      mut symtable,
+      insts,
+      locs,
+      mut errs,
+      consts,
+      placed,
+      reserves,
+      data,
+      exprs,
+      ..
+    } = asm;
+
+    for (name, (_, loc)) in &consts {
+      if symtable.contains_key(name) {
+        errs.push(Err::DuplicateConst(String::from(name.name()), *loc));
+      }
+    }
+
+    let consts =
+      consts.into_iter().map(|(name, (value, _))| (name, value)).collect::<Map<_, _>>();
+
+    let mut var_index = 16;
+    let mut vars = placed.iter().map(|&(name, _)| name).collect::<Set<_>>();
+
+    // The words taken by variables placed with `.var` and their data,
+    // by the variables they belong to.
+    let mut owners = Map::<u16, Label>::new();
+
+    for &(name, loc) in &placed {
+      // Variables that could not be declared have already been reported.
+      if let Some(&addr) = symtable.get(&name) {
+        match owners.get(&addr) {
+          Some(owner) => {
+            errs.push(Err::RamOverlap(name.to_string(), owner.to_string(), loc))
+          }
+          None => {
+            owners.insert(addr, name);
           }
         }
-        TokenKind::Addr(addr) => {
-          insts.push(Cmd::Addr(addr));
-          index += 1;
+      }
+    }
+
+    for &(name, offset, _, loc) in &data {
+      let addr = match symtable.get(&name) {
+        Some(&base) if offset > 0 && vars.contains(&name) => base.checked_add(offset),
+        _ => continue,
+      };
+
+      // Data past the end of the data memory is reported below.
+      let addr = match addr.filter(|&addr| addr < u16::from(Sym::KBD)) {
+        Some(addr) => addr,
+        None => continue,
+      };
+
+      match owners.get(&addr) {
+        Some(owner) if *owner != name => {
+          errs.push(Err::DataOverlap(name.to_string(), owner.to_string(), loc))
         }
-        TokenKind::Inst(inst) => {
-          insts.push(Cmd::Inst(inst));
-          index += 1;
+        Some(_) => {}
+        None => {
+          owners.insert(addr, name);
         }
       }
     }
 
-    let mut var_index = 16;
+    let taken = owners.keys().copied().collect();
+
+    for &(name, size, loc) in &reserves {
+      vars.insert(name);
+
+      match Self::allocate(&mut var_index, size, &taken) {
+        Some(addr) => symtable.insert(name, addr),
+        None => {
+          errs.push(Err::RamOverflow(name.to_string(), loc));
+          continue;
+        }
+      };
+    }
 
-    for inst in &insts {
+    for (i, inst) in insts.iter().enumerate() {
       if let Cmd::Addr(Addr::Label(label)) = inst {
-        symtable.entry(*label).or_insert_with(|| {
-          let current_var_index = var_index;
-          var_index += 1;
-          current_var_index
+        if consts.contains_key(label) || symtable.contains_key(label) {
+          continue;
+        }
+
+        vars.insert(*label);
+
+        let addr = Self::allocate(&mut var_index, 1, &taken).unwrap_or_else(|| {
+          errs.push(Err::RamOverflow(label.to_string(), locs[i]));
+          0
         });
+
+        symtable.insert(*label, addr);
+      }
+    }
+
+    let mut prog = Self { symtable, vars, consts, insts, locs, data: Vec::new() };
+
+    for (i, inst) in prog.insts.iter().enumerate() {
+      if let Cmd::Addr(addr @ Addr::Expr(_)) = inst {
+        if let Err(e) = addr.value(|label| prog.lookup(label)) {
+          errs.push(Err::Eval(e, prog.locs[i]));
+        }
+      }
+    }
+
+    for (i, text, scope) in exprs {
+      let value = Expr::read_from(text.as_bytes()).map_err(AddrErr::Expr).and_then(
+        |(expr, _, _)| {
+          let expr = scope.map_or(expr, |scope| expr.scoped(scope));
+          Addr::Expr(expr).value(|label| prog.lookup(label))
+        },
+      );
+
+      match value {
+        Ok(value) => prog.insts[i] = Cmd::Addr(Addr::Num(value)),
+        Err(e) => errs.push(Err::Eval(e, prog.locs[i])),
+      }
+    }
+
+    let sizes =
+      reserves.iter().map(|&(name, size, _)| (name, size)).collect::<Map<_, _>>();
+
+    for (name, offset, value, loc) in data {
+      // Variables that could not be declared have already been reported.
+      let base = match prog.symtable.get(&name) {
+        Some(&base) => base,
+        None => continue,
+      };
+
+      let addr = u32::from(base) + u32::from(offset);
+
+      if sizes.get(&name).is_some_and(|&size| offset >= size)
+        || addr >= u32::from(u16::from(Sym::KBD))
+      {
+        errs.push(Err::DataOverflow(name.to_string(), loc));
+        continue;
+      }
+
+      match value.eval(|label| prog.lookup(label)) {
+        Ok(value) if (-32768..=65535).contains(&value) => {
+          prog.data.push((addr as u16, value as u16))
+        }
+        Ok(value) => errs.push(Err::Eval(AddrErr::Range(value), loc)),
+        Err(e) => errs.push(Err::Eval(AddrErr::Expr(e), loc)),
       }
     }
 
-    Ok(Self { symtable, insts })
+    if !errs.is_empty() {
+      errs.sort_by_key(|e| e.loc().map(|loc| (loc.file(), loc.line(), loc.col())));
+      return Err(errs);
+    }
+
+    Ok(prog)
+  }
+
+  /// Allocate `size` consecutive words of the data memory for
+  /// variables, starting at `next` and skipping `taken` addresses
+  /// (those of variables placed with `.var` and their data).
+  ///
+  /// Returns the address of the first word, or `None` if the words
+  /// would overlap the screen memory map.
+  fn allocate(next: &mut u16, size: u16, taken: &Set<u16>) -> Option<u16> {
+    loop {
+      let end = next.checked_add(size).filter(|&end| end <= u16::from(Sym::SCREEN))?;
+
+      match (*next..end).rev().find(|addr| taken.contains(addr)) {
+        Some(addr) => *next = addr + 1,
+        None => return Some(std::mem::replace(next, end)),
+      }
+    }
   }
 
   /// Create a program from a buffer containing HACK binary code.
@@ -126,12 +842,7 @@ impl<'b> Prog<'b> {
   /// This parses the input buffer and populates an empty symbol table.
   pub fn from_bin(buf: Buf<'b>) -> Result<Self, Err> {
     let parser: dec::Parser<dec::BinParser> = dec::Parser::from(buf);
-    let insts = parser
-      .collect::<Result<Vec<dec::Token>, _>>()?
-      .into_iter()
-      .map(|t| Cmd::new(t.value(), t.index(), buf))
-      .collect::<Result<Vec<_>, _>>()?;
-    Ok(Self { symtable: Symtable::new(), insts })
+    Self::from_tokens(parser, buf)
   }
 
   /// Create a program from a buffer containing HACK bintext code.
@@ -139,23 +850,260 @@ impl<'b> Prog<'b> {
   /// This parses the input buffer and populates an empty symbol table.
   pub fn from_bintext(buf: Buf<'b>) -> Result<Self, Err> {
     let parser: dec::Parser<dec::BinTextParser> = dec::Parser::from(buf);
-    let insts = parser
-      .collect::<Result<Vec<dec::Token>, _>>()?
-      .into_iter()
-      .map(|t| Cmd::new(t.value(), t.index(), buf))
-      .collect::<Result<Vec<_>, _>>()?;
-    Ok(Self { symtable: Symtable::new(), insts })
+    Self::from_tokens(parser, buf)
   }
 
-  /// Get the list of instructions in a program.
-  pub fn insts(&self) -> &[Cmd<'b>] {
-    &self.insts
+  /// Create a program from a buffer containing little-endian HACK
+  /// binary code.
+  ///
+  /// This parses the input buffer and populates an empty symbol table.
+  pub fn from_bin_le(buf: Buf<'b>) -> Result<Self, Err> {
+    let parser: dec::Parser<dec::BinLeParser> = dec::Parser::from(buf);
+    Self::from_tokens(parser, buf)
   }
 
-  /// Get the symbol table in a program.
-  pub fn symtable(&self) -> &Symtable<'b> {
-    &self.symtable
-  }
+  /// Create a program from a buffer containing HACK code in the Intel
+  /// HEX format.
+  ///
+  /// This parses the input buffer and populates an empty symbol table.
+  pub fn from_ihex(buf: Buf<'b>) -> Result<Self, Err> {
+    let parser: dec::Parser<dec::IhexParser> = dec::Parser::from(buf);
+    Self::from_tokens(parser, buf)
+  }
+
+  /// Create a program from a buffer containing a Logisim memory image
+  /// of HACK code.
+  ///
+  /// This parses the input buffer and populates an empty symbol table.
+  pub fn from_logisim(buf: Buf<'b>) -> Result<Self, Err> {
+    let parser: dec::Parser<dec::LogisimParser> = dec::Parser::from(buf);
+    Self::from_tokens(parser, buf)
+  }
+
+  /// Create a program from a buffer containing HACK code in the format
+  /// read by Verilog's `$readmemh`.
+  ///
+  /// This parses the input buffer and populates an empty symbol table.
+  pub fn from_readmemh(buf: Buf<'b>) -> Result<Self, Err> {
+    let parser: dec::Parser<dec::ReadmemhParser> = dec::Parser::from(buf);
+    Self::from_tokens(parser, buf)
+  }
+
+  /// Create a program from a buffer containing HACK code in the format
+  /// read by Verilog's `$readmemb`.
+  ///
+  /// This parses the input buffer and populates an empty symbol table.
+  pub fn from_readmemb(buf: Buf<'b>) -> Result<Self, Err> {
+    let parser: dec::Parser<dec::ReadmembParser> = dec::Parser::from(buf);
+    Self::from_tokens(parser, buf)
+  }
+
+  /// Create a program from a buffer containing compiled HACK code in
+  /// the given encoding.
+  ///
+  /// This parses the input buffer and populates an empty symbol table.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::dec;
+  /// use has::hack::Prog;
+  ///
+  /// let buf = "0000000000010000\n1110101010001000".as_bytes();
+  /// let prog = Prog::decode(buf, dec::detect(buf).unwrap()).unwrap();
+  /// assert_eq!(prog.to_readmemh().collect::<Result<Vec<_>, _>>().unwrap(), ["0010", "ea88"]);
+  /// ```
+  pub fn decode(buf: Buf<'b>, encoding: dec::Encoding) -> Result<Self, Err> {
+    match encoding {
+      dec::Encoding::Bin => Self::from_bin(buf),
+      dec::Encoding::BinLe => Self::from_bin_le(buf),
+      dec::Encoding::Bintext => Self::from_bintext(buf),
+      dec::Encoding::Ihex => Self::from_ihex(buf),
+      dec::Encoding::Logisim => Self::from_logisim(buf),
+      dec::Encoding::Readmemh => Self::from_readmemh(buf),
+      dec::Encoding::Readmemb => Self::from_readmemb(buf),
+    }
+  }
+
+  /// Create a program from decoded tokens.
+  fn from_tokens<T>(parser: dec::Parser<'b, T>, buf: Buf<'b>) -> Result<Self, Err>
+  where
+    T: dec::Impl<Item = Result<dec::Token, dec::Err>>,
+  {
+    let tokens = parser.collect::<Result<Vec<dec::Token>, _>>()?;
+    let mut locator = Locator::new(buf);
+    let locs = tokens.iter().map(|t| locator.locate(t.index())).collect();
+    let insts = tokens
+      .into_iter()
+      .map(|t| Cmd::new(t.value(), t.index(), buf))
+      .collect::<Result<Vec<_>, _>>()?;
+    let consts = Map::new();
+    let data = Vec::new();
+    Ok(Self { symtable: Symtable::new(), vars: Set::new(), consts, insts, locs, data })
+  }
+
+  /// Create a program from its parts.
+  ///
+  /// The parts are not checked and must have been taken from another
+  /// program.
+  pub(crate) fn from_parts(
+    symtable: Symtable<'b>,
+    vars: Set<Label<'b>>,
+    consts: Map<Label<'b>, u16>,
+    insts: Vec<Cmd<'b>>,
+    locs: Vec<Loc>,
+    data: Vec<(u16, u16)>,
+  ) -> Self {
+    Self { symtable, vars, consts, insts, locs, data }
+  }
+
+  /// Get the list of instructions in a program.
+  pub fn insts(&self) -> &[Cmd<'b>] {
+    &self.insts
+  }
+
+  /// Get the source map of a program.
+  ///
+  /// Contains the [location](Loc) in the input buffer of the
+  /// instruction at each ROM address.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  /// use has::Loc;
+  ///
+  /// let buf = "// Comment\n  @FOO\n(FOO)\n  D=A;JMP".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// assert_eq!(prog.source_map(), [Loc::new(2, 3), Loc::new(4, 3)]);
+  /// ```
+  pub fn source_map(&self) -> &[Loc] {
+    &self.locs
+  }
+
+  /// Get the [location](Loc) in the input buffer of the instruction
+  /// at ROM address `addr`.
+  pub fn loc(&self, addr: u16) -> Option<Loc> {
+    self.locs.get(usize::from(addr)).copied()
+  }
+
+  /// Get the symbol table in a program.
+  pub fn symtable(&self) -> &Symtable<'b> {
+    &self.symtable
+  }
+
+  /// Get the labels in the symbol table and their ROM addresses.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let buf = "@i\nM=0\n(LOOP)\n@LOOP\n0;JMP".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// let labels = prog.labels().map(|(l, &a)| (l.name(), a)).collect::<Vec<_>>();
+  /// assert_eq!(labels, [("LOOP", 2)]);
+  /// ```
+  pub fn labels(&self) -> impl Iterator<Item = (&Label<'b>, &u16)> + '_ {
+    self.symtable.iter().filter(move |(label, _)| !self.vars.contains(label))
+  }
+
+  /// Get the user-defined variables in the symbol table and their RAM
+  /// addresses.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let buf = "@i\nM=0\n(LOOP)\n@LOOP\n0;JMP".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// let vars = prog.vars().map(|(l, &a)| (l.name(), a)).collect::<Vec<_>>();
+  /// assert_eq!(vars, [("i", 16)]);
+  /// ```
+  pub fn vars(&self) -> impl Iterator<Item = (&Label<'b>, &u16)> + '_ {
+    self.symtable.iter().filter(move |(label, _)| self.vars.contains(label))
+  }
+
+  /// Get the constants defined with `.equ` and their values.
+  ///
+  /// Constants are not part of the symbol table.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let buf = ".equ ROW 3\n.equ OFFSET ROW*32\n@SCREEN+OFFSET\nD=A\n@ROW".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// let mut consts = prog.consts().map(|(l, &v)| (l.name(), v)).collect::<Vec<_>>();
+  /// consts.sort();
+  /// assert_eq!(consts, [("OFFSET", 96), ("ROW", 3)]);
+  /// assert_eq!(prog.to_words().collect::<Result<Vec<_>, _>>(), Ok(vec![16480, 0xEC10, 3]));
+  /// assert!(prog.symtable().is_empty());
+  /// ```
+  pub fn consts(&self) -> impl Iterator<Item = (&Label<'b>, &u16)> + '_ {
+    self.consts.iter()
+  }
+
+  /// Get the initial contents of the data memory, set with `.data`
+  /// directives.
+  ///
+  /// Contains the RAM address and value of each word, in order of
+  /// declaration.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let buf = ".var n @ 100\n.data 3\n.reserve buf 4\n.data 'H', 'i'\n@i".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// assert_eq!(prog.data(), [(100, 3), (16, 72), (17, 105)]);
+  ///
+  /// let vars = prog.to_symbols().collect::<Vec<_>>();
+  /// assert_eq!(vars, ["var buf 16", "var i 20", "var n 100"]);
+  /// ```
+  pub fn data(&self) -> &[(u16, u16)] {
+    &self.data
+  }
+
+  /// Get the warnings for likely mistakes that do not prevent the
+  /// program from being assembled, ordered by their location.
+  ///
+  /// A label used as a jump target (i.e. followed by a jumping
+  /// instruction) that is never declared becomes a variable, as with
+  /// any other undeclared label, and is reported as
+  /// [undefined](Err::UndefinedLabel).
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  /// use has::hack::ProgErr;
+  /// use has::Loc;
+  ///
+  /// let buf = "(LOOP)\n@LOPO\n0;JMP".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// assert_eq!(prog.vars().count(), 1);
+  /// assert_eq!(prog.warnings(), [ProgErr::UndefinedLabel(String::from("LOPO"), Loc::new(2, 1))]);
+  /// ```
+  pub fn warnings(&self) -> Vec<Err> {
+    let mut warnings = Vec::new();
+
+    for (i, inst) in self.insts.iter().enumerate() {
+      if let (Cmd::Addr(Addr::Label(label)), Some(Cmd::Inst(next))) =
+        (inst, self.insts.get(i + 1))
+      {
+        if !next.jump().is_null() && self.vars.contains(label) {
+          warnings.push(Err::UndefinedLabel(label.to_string(), self.locs[i]));
+        }
+      }
+    }
+
+    warnings.sort_by_key(|e| e.loc().map(|loc| (loc.file(), loc.line(), loc.col())));
+    warnings
+  }
 
   /// Get a mutable reference to the symbol table in a program.
   pub fn symtable_mut(&mut self) -> &mut Symtable<'b> {
@@ -164,34 +1112,820 @@ impl<'b> Prog<'b> {
 
   /// Create and return a bintext encoder to encode this program.
   pub fn to_bintext(&self) -> impl Iterator<Item = Result<[u8; 16], Err>> + '_ {
-    self.to_bin().map(|res| match res {
-      Ok(val) => Ok(conv::u16_bintext(u16::from(val[0]) << 8 | u16::from(val[1]))),
-      Err(e) => Err(e),
-    })
+    Encoder(self.to_words()).bintext()
   }
 
   /// Create and return a binary encoder to encode this program.
   pub fn to_bin(&self) -> impl Iterator<Item = Result<[u8; 2], Err>> + '_ {
-    self.insts.iter().copied().map(move |i| {
-      let addr = match i {
-        Cmd::Inst(inst) => u16::from(inst),
-        Cmd::Addr(Addr::Num(addr)) => addr,
-        Cmd::Addr(Addr::Sym(sym)) => u16::from(sym),
-        Cmd::Addr(Addr::Label(label)) => {
-          if let Some(&addr) = self.symtable.get(&label) {
-            addr
-          } else {
-            return Err(Err::LabelNotFound(String::from(label.name())));
-          }
-        }
-      };
+    Encoder(self.to_words()).bin()
+  }
+
+  /// Encode this program and write it to `writer`.
+  ///
+  /// Instructions are encoded and written one at a time, or one line
+  /// or record at a time for the formats that group them, so the
+  /// output can be streamed (e.g. to a pipe) without first encoding
+  /// the whole program in memory. The output is the same as that of
+  /// the corresponding encoder (e.g. [Prog::to_ihex]), with a newline
+  /// after each line.
+  ///
+  /// # Arguments
+  ///
+  /// * `encoding` - The output encoding.
+  ///
+  /// * `writer` - The output stream.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::dec::Encoding;
+  /// use has::hack::Prog;
+  ///
+  /// let prog = Prog::from_source("@2\nD=A".as_bytes()).unwrap();
+  ///
+  /// let mut out = Vec::new();
+  /// prog.encode(Encoding::Bin, &mut out).unwrap();
+  /// assert_eq!(out, [0x00, 0x02, 0xEC, 0x10]);
+  ///
+  /// let mut out = Vec::new();
+  /// prog.encode(Encoding::Logisim, &mut out).unwrap();
+  /// assert_eq!(out, b"v2.0 raw\n2 ec10\n");
+  /// ```
+  pub fn encode(
+    &self,
+    encoding: dec::Encoding,
+    writer: &mut impl io::Write,
+  ) -> io::Result<()> {
+    Encoder(self.to_words()).write(encoding, writer)
+  }
+
+  /// Create and return a little-endian binary encoder to encode this
+  /// program.
+  pub fn to_bin_le(&self) -> impl Iterator<Item = Result<[u8; 2], Err>> + '_ {
+    Encoder(self.to_words()).bin_le()
+  }
 
-      Ok([(addr >> 8) as u8, addr as u8])
+  /// Encode this program in the Intel HEX format.
+  ///
+  /// Returns the records, with up to 16 bytes per data record. Each
+  /// instruction is stored as two big-endian bytes at twice its ROM
+  /// address.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let prog = Prog::from_source("@2\nD=A".as_bytes()).unwrap();
+  /// let ihex = prog.to_ihex().unwrap();
+  /// assert_eq!(ihex, [":040000000002EC10FE", ":00000001FF"]);
+  ///
+  /// let ihex = ihex.join("\n");
+  /// let dis = Prog::from_ihex(ihex.as_bytes()).unwrap();
+  /// assert_eq!(dis.to_ihex().unwrap(), prog.to_ihex().unwrap());
+  /// ```
+  pub fn to_ihex(&self) -> Result<Vec<String>, Err> {
+    Encoder(self.to_words()).ihex().collect()
+  }
+
+  /// Encode this program as a Logisim `v2.0 raw` memory image.
+  ///
+  /// Returns the header line followed by lines of up to 8 instructions
+  /// in hexadecimal.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let prog = Prog::from_source("@2\nD=A".as_bytes()).unwrap();
+  /// assert_eq!(prog.to_logisim().unwrap(), ["v2.0 raw", "2 ec10"]);
+  /// ```
+  pub fn to_logisim(&self) -> Result<Vec<String>, Err> {
+    Encoder(self.to_words()).logisim().collect()
+  }
+
+  /// Create and return an encoder producing the lines of a file to be
+  /// read by Verilog's `$readmemh`.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let prog = Prog::from_source("@2\nD=A".as_bytes()).unwrap();
+  /// let lines = prog.to_readmemh().collect::<Result<Vec<_>, _>>().unwrap();
+  /// assert_eq!(lines, ["0002", "ec10"]);
+  /// ```
+  pub fn to_readmemh(&self) -> impl Iterator<Item = Result<String, Err>> + '_ {
+    Encoder(self.to_words()).readmemh()
+  }
+
+  /// Create and return an encoder producing the lines of a file to be
+  /// read by Verilog's `$readmemb`.
+  ///
+  /// This is the same as a bintext file.
+  pub fn to_readmemb(&self) -> impl Iterator<Item = Result<String, Err>> + '_ {
+    Encoder(self.to_words()).readmemb()
+  }
+
+  /// Create and return an encoder producing the 16-bit words of this
+  /// program.
+  pub fn to_words(&self) -> impl Iterator<Item = Result<u16, Err>> + '_ {
+    self.insts.iter().copied().enumerate().map(move |(i, cmd)| match cmd {
+      Cmd::Inst(inst) => Ok(u16::from(inst)),
+      Cmd::Addr(Addr::Label(label)) => {
+        self.lookup(label).ok_or_else(|| Err::LabelNotFound(label.to_string()))
+      }
+      Cmd::Addr(addr) => addr
+        .value(|label| self.lookup(label))
+        .map_err(|e| Err::Eval(e, self.locs.get(i).copied().unwrap_or_default())),
     })
   }
 
+  /// Returns the value of a label, variable or constant.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Label;
+  /// use has::hack::Prog;
+  /// use std::convert::TryFrom;
+  ///
+  /// let buf = ".equ N 5\n@i\n(LOOP)\n@LOOP".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// let value = |name: &str| prog.lookup(Label::try_from(name.as_bytes()).unwrap());
+  /// assert_eq!((value("N"), value("i"), value("LOOP"), value("END")), (Some(5), Some(16), Some(1), None));
+  /// ```
+  pub fn lookup(&self, label: Label<'b>) -> Option<u16> {
+    self.symtable.get(&label).or_else(|| self.consts.get(&label)).copied()
+  }
+
   /// Produce HACK assembly out of the instructions in a program.
+  ///
+  /// Labels in the symbol table are defined before the instructions
+  /// they point to.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let buf = "(LOOP)\n@LOOP\n0;JMP\n(END)".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// let source = prog.to_source().collect::<Vec<_>>();
+  /// assert_eq!(source, ["(LOOP)", "@LOOP", "0;JMP", "(END)"]);
+  /// ```
   pub fn to_source(&self) -> impl Iterator<Item = String> + '_ {
-    self.insts.iter().map(|i| format!("{}", i))
+    let mut labels = self.sorted(self.labels()).into_iter().peekable();
+    let mut lines = Vec::with_capacity(self.insts.len());
+
+    for (addr, inst) in self.insts.iter().enumerate() {
+      while let Some((_, label)) = labels.next_if(|&(a, _)| usize::from(a) <= addr) {
+        lines.push(format!("({})", label));
+      }
+
+      lines.push(format!("{}", inst));
+    }
+
+    lines.extend(labels.map(|(_, label)| format!("({})", label)));
+    lines.into_iter()
+  }
+
+  /// Produce the symbol table of a program.
+  ///
+  /// Each line is either `label NAME ADDRESS` for labels (with ROM
+  /// addresses) or `var NAME ADDRESS` for user-defined variables
+  /// (with RAM addresses). Labels come first, then variables, each
+  /// ordered by address and name.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let buf = "@i\nM=0\n(LOOP)\n@LOOP\n0;JMP".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// let symbols = prog.to_symbols().collect::<Vec<_>>();
+  /// assert_eq!(symbols, ["label LOOP 2", "var i 16"]);
+  /// ```
+  pub fn to_symbols(&self) -> impl Iterator<Item = String> + '_ {
+    let labels = self.sorted(self.labels()).into_iter();
+    let vars = self.sorted(self.vars()).into_iter();
+
+    labels
+      .map(|(addr, label)| format!("label {} {}", label, addr))
+      .chain(vars.map(|(addr, var)| format!("var {} {}", var, addr)))
+  }
+
+  /// Produce a listing of a program assembled from `sources`.
+  ///
+  /// After a header line, each instruction is listed with its ROM
+  /// address, its encoding in binary and hexadecimal, the instruction
+  /// decoded back from its encoding, and the location (`LINE:COLUMN`)
+  /// and text of the source line it was assembled from. The text is
+  /// only listed once for lines that expand to several instructions.
+  /// Locations start with the path of the source file when there is
+  /// more than one. Labels are listed before the instructions they
+  /// point to.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  /// use has::hack::Source;
+  /// use std::path::Path;
+  ///
+  /// let buf = "@i // Counter\nM=0\n(END)\nGOTO END".as_bytes();
+  /// let prog = Prog::from_source(buf).unwrap();
+  /// let listing = prog.to_listing(&[Source::new(Path::new("Main.asm"), buf)]).unwrap();
+  /// assert_eq!(listing[1], "    0  0000000000010000  0x0010  @16                   1:1       @i // Counter");
+  /// assert_eq!(listing[2], "    1  1110101010001000  0xEA88  M=0                   2:1       M=0");
+  /// assert_eq!(listing[3], "                                 (END)");
+  /// assert_eq!(listing[4], "    2  0000000000000010  0x0002  @2                    4:1       GOTO END");
+  /// assert_eq!(listing[5], "    3  1110101010000111  0xEA87  0;JMP");
+  /// ```
+  pub fn to_listing(&self, sources: &[Source]) -> Result<Vec<String>, Err> {
+    let words = self.to_words().collect::<Result<Vec<_>, _>>()?;
+    let lines = sources
+      .iter()
+      .map(|source| source.buf().split(|&b| b == b'\n').collect::<Vec<_>>())
+      .collect::<Vec<_>>();
+    let mut labels = self.sorted(self.labels()).into_iter().peekable();
+    let mut listing = vec![format!(
+      "{:>5}  {:16}  {:6}  {:20}  {:8}  {}",
+      "ADDR", "BINARY", "HEX", "INSTRUCTION", "LOCATION", "SOURCE"
+    )];
+    let mut prev_loc = None;
+
+    for (addr, (&word, inst)) in words.iter().zip(&self.insts).enumerate() {
+      while let Some((_, label)) = labels.next_if(|&(a, _)| usize::from(a) <= addr) {
+        listing.push(format!("{:33}({})", "", label));
+      }
+
+      let cmd =
+        Cmd::try_from(word).map_or_else(|_| inst.to_string(), |cmd| cmd.to_string());
+      let mut line = format!("{:5}  {:016b}  {:#06X}  {:20}", addr, word, word, cmd);
+
+      if let Some(loc) = self.locs.get(addr).filter(|&&loc| Some(loc) != prev_loc) {
+        let location = match sources.get(loc.file()) {
+          Some(source) if sources.len() > 1 => {
+            format!("{}:{}:{}", source.path().display(), loc.line(), loc.col())
+          }
+          _ => format!("{}:{}", loc.line(), loc.col()),
+        };
+        let text = lines.get(loc.file()).and_then(|lines| lines.get(loc.line() - 1));
+        let text =
+          text.map_or_else(String::new, |text| String::from_utf8_lossy(text).into());
+        line.push_str(&format!("  {:8}  {}", location, text.trim_end()));
+        prev_loc = Some(*loc);
+      }
+
+      listing.push(line.trim_end().to_owned());
+    }
+
+    listing.extend(labels.map(|(_, label)| format!("{:33}({})", "", label)));
+    Ok(listing)
+  }
+
+  /// Load a symbol table as produced by [Prog::to_symbols] into a
+  /// program.
+  ///
+  /// The labels and variables are inserted into the symbol table, and
+  /// numerical A-instructions are replaced with references to them:
+  /// an A-instruction followed by a jump refers to a label, and one
+  /// followed by an instruction that reads or writes `M` refers to a
+  /// variable. Other A-instructions are left untouched.
+  ///
+  /// # Arguments
+  ///
+  /// * `buf` - The contents of the symbol table file.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let bintext = "0000000000010000\n1110101010001000\n0000000000000010\n1110101010000111";
+  /// let mut prog = Prog::from_bintext(bintext.as_bytes()).unwrap();
+  /// prog.load_symbols("label LOOP 2\nvar i 16\n".as_bytes()).unwrap();
+  ///
+  /// let source = prog.to_source().collect::<Vec<_>>();
+  /// assert_eq!(source, ["@i", "M=0", "(LOOP)", "@LOOP", "0;JMP"]);
+  /// ```
+  pub fn load_symbols(&mut self, buf: Buf<'b>) -> Result<(), Err> {
+    let mut index = 0;
+
+    for line in buf.split(|&b| b == b'\n') {
+      let line_index = index;
+      index += line.len() + 1;
+
+      let invalid = || Err::InvalidSymbol(Loc::from_index(buf, line_index));
+      let mut words = line.split(|b| b.is_ascii_whitespace()).filter(|w| !w.is_empty());
+
+      let is_var = match words.next() {
+        Some(b"label") => false,
+        Some(b"var") => true,
+        Some(_) => return Err(invalid()),
+        None => continue,
+      };
+
+      let label =
+        words.next().and_then(|name| Label::try_from(name).ok()).ok_or_else(invalid)?;
+      let addr = words.next().and_then(|addr| match u16::from_radix_10_checked(addr) {
+        (Some(value), used) if used == addr.len() && value <= 32767 => Some(value),
+        _ => None,
+      });
+      let addr = addr.ok_or_else(invalid)?;
+
+      if words.next().is_some() {
+        return Err(invalid());
+      }
+
+      if self.symtable.insert(label, addr).is_some() {
+        let loc = Loc::from_index(buf, line_index);
+        return Err(Err::DuplicateLabel(String::from(label.name()), loc));
+      }
+
+      if is_var {
+        self.vars.insert(label);
+      }
+    }
+
+    let labels = self.first_by_addr(self.labels());
+    let vars = self.first_by_addr(self.vars());
+
+    for i in 0..self.insts.len() {
+      if let Cmd::Addr(Addr::Num(addr)) = self.insts[i] {
+        let label = match self.insts.get(i + 1) {
+          Some(Cmd::Inst(inst)) if !inst.jump().is_null() => labels.get(&addr),
+          Some(Cmd::Inst(inst)) if inst.comp().has_m() || inst.dest().has_m() => {
+            vars.get(&addr)
+          }
+          _ => None,
+        };
+
+        if let Some(&label) = label {
+          self.insts[i] = Cmd::Addr(Addr::Label(label));
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Recover labels and predefined symbols in a disassembled program.
+  ///
+  /// A numerical A-instruction followed by a jump refers to a ROM
+  /// address: it is replaced with a reference to a label at that
+  /// address, which is reused from the symbol table if present or
+  /// otherwise [generated](Label::generated) as `LABEL_<addr>`. A
+  /// numerical A-instruction followed by an instruction that reads or
+  /// writes `M` and whose address is a register or memory-mapped
+  /// device is replaced with the [predefined symbol](Sym::from_addr).
+  /// Other A-instructions are left untouched.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use has::hack::Prog;
+  ///
+  /// let bintext = "0000000000000001\n1111110000010000\n0000000000000000\n1110101010000111";
+  /// let mut prog = Prog::from_bintext(bintext.as_bytes()).unwrap();
+  /// prog.recover_labels();
+  ///
+  /// let source = prog.to_source().collect::<Vec<_>>();
+  /// assert_eq!(source, ["(LABEL_0)", "@R1", "D=M", "@LABEL_0", "0;JMP"]);
+  /// ```
+  pub fn recover_labels(&mut self) {
+    let mut labels = self.first_by_addr(self.labels());
+
+    for i in 0..self.insts.len() {
+      if let Cmd::Addr(Addr::Num(addr)) = self.insts[i] {
+        let recovered = match self.insts.get(i + 1) {
+          Some(Cmd::Inst(inst))
+            if !inst.jump().is_null() && usize::from(addr) <= self.insts.len() =>
+          {
+            let label = *labels.entry(addr).or_insert_with(|| Label::generated(addr));
+
+            // Skip generated names that are taken by another symbol.
+            match *self.symtable.entry(label).or_insert(addr) {
+              label_addr if label_addr == addr => Some(Addr::Label(label)),
+              _ => None,
+            }
+          }
+          Some(Cmd::Inst(inst)) if inst.comp().has_m() || inst.dest().has_m() => {
+            Sym::from_addr(addr).map(Addr::Sym)
+          }
+          _ => None,
+        };
+
+        if let Some(recovered) = recovered {
+          self.insts[i] = Cmd::Addr(recovered);
+        }
+      }
+    }
+  }
+
+  /// Sort symbol table entries by address and name.
+  fn sorted<'s>(
+    &self,
+    entries: impl Iterator<Item = (&'s Label<'b>, &'s u16)>,
+  ) -> Vec<(u16, Label<'b>)>
+  where
+    'b: 's,
+  {
+    let mut entries = entries.map(|(&label, &addr)| (addr, label)).collect::<Vec<_>>();
+    entries.sort_by_cached_key(|&(addr, label)| (addr, label.to_string()));
+    entries
+  }
+
+  /// Map addresses to the first (by name) of the symbol table entries
+  /// pointing to them.
+  fn first_by_addr<'s>(
+    &self,
+    entries: impl Iterator<Item = (&'s Label<'b>, &'s u16)>,
+  ) -> Map<u16, Label<'b>>
+  where
+    'b: 's,
+  {
+    let mut map = Map::new();
+
+    for (addr, label) in self.sorted(entries) {
+      map.entry(addr).or_insert(label);
+    }
+
+    map
+  }
+}
+
+/// Encoder of the 16-bit words of a program.
+///
+/// Implements the output formats once for both [Prog] and
+/// [owned programs](crate::hack::OwnedProg).
+pub(crate) struct Encoder<I>(pub(crate) I);
+
+impl<I: Iterator<Item = Result<u16, Err>>> Encoder<I> {
+  /// Encode the words as big-endian pairs of bytes.
+  pub(crate) fn bin(self) -> impl Iterator<Item = Result<[u8; 2], Err>> {
+    self.0.map(|res| res.map(u16::to_be_bytes))
+  }
+
+  /// Encode the words as little-endian pairs of bytes.
+  pub(crate) fn bin_le(self) -> impl Iterator<Item = Result<[u8; 2], Err>> {
+    self.0.map(|res| res.map(u16::to_le_bytes))
+  }
+
+  /// Encode the words as 16 binary digits.
+  pub(crate) fn bintext(self) -> impl Iterator<Item = Result<[u8; 16], Err>> {
+    self.0.map(|res| res.map(conv::u16_bintext))
+  }
+
+  /// Encode the words as lines of 4 hexadecimal digits.
+  pub(crate) fn readmemh(self) -> impl Iterator<Item = Result<String, Err>> {
+    self.0.map(|res| res.map(|word| format!("{:04x}", word)))
+  }
+
+  /// Encode the words as lines of 16 binary digits.
+  pub(crate) fn readmemb(self) -> impl Iterator<Item = Result<String, Err>> {
+    self.0.map(|res| res.map(|word| format!("{:016b}", word)))
+  }
+
+  /// Encode the words as Intel HEX records, with up to 16 bytes per
+  /// data record and a final end of file record.
+  pub(crate) fn ihex(self) -> impl Iterator<Item = Result<String, Err>> {
+    Self::chunks(self.bin(), 8)
+      .enumerate()
+      .map(|(i, words)| {
+        words.map(|words| conv::ihex_record(0x00, (i * 16) as u16, &words.concat()))
+      })
+      .chain(std::iter::once(Ok(conv::ihex_record(0x01, 0, &[]))))
+  }
+
+  /// Encode the words as the lines of a Logisim `v2.0 raw` memory
+  /// image, with up to 8 words per line after the header.
+  pub(crate) fn logisim(self) -> impl Iterator<Item = Result<String, Err>> {
+    let lines = Self::chunks(self.0, 8).map(|words| {
+      words.map(|words| {
+        words.iter().map(|word| format!("{:x}", word)).collect::<Vec<_>>().join(" ")
+      })
+    });
+    std::iter::once(Ok(String::from("v2.0 raw"))).chain(lines)
+  }
+
+  /// Encode the words and write them to `writer`, with a newline after
+  /// each line of the text formats.
+  pub(crate) fn write(
+    self,
+    encoding: dec::Encoding,
+    writer: &mut impl io::Write,
+  ) -> io::Result<()> {
+    match encoding {
+      dec::Encoding::Bin => Self::write_all(writer, self.bin(), b""),
+      dec::Encoding::BinLe => Self::write_all(writer, self.bin_le(), b""),
+      dec::Encoding::Bintext => Self::write_all(writer, self.bintext(), b"\n"),
+      dec::Encoding::Readmemb => Self::write_all(writer, self.readmemb(), b"\n"),
+      dec::Encoding::Readmemh => Self::write_all(writer, self.readmemh(), b"\n"),
+      dec::Encoding::Logisim => Self::write_all(writer, self.logisim(), b"\n"),
+      dec::Encoding::Ihex => Self::write_all(writer, self.ihex(), b"\n"),
+    }
+  }
+
+  /// Write each of the encoded `items` to `writer`, followed by `sep`,
+  /// stopping at the first encoding error.
+  fn write_all<T: AsRef<[u8]>>(
+    writer: &mut impl io::Write,
+    items: impl Iterator<Item = Result<T, Err>>,
+    sep: &[u8],
+  ) -> io::Result<()> {
+    for item in items {
+      let item =
+        item.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+      writer.write_all(item.as_ref())?;
+      writer.write_all(sep)?;
+    }
+
+    Ok(())
+  }
+
+  /// Group the encoded `items` into chunks of up to `size` items,
+  /// stopping after the first encoding error.
+  fn chunks<T>(
+    mut items: impl Iterator<Item = Result<T, Err>>,
+    size: usize,
+  ) -> impl Iterator<Item = Result<Vec<T>, Err>> {
+    let mut end = false;
+
+    std::iter::from_fn(move || {
+      if end {
+        return None;
+      }
+
+      match items.by_ref().take(size).collect::<Result<Vec<_>, _>>() {
+        Ok(chunk) if chunk.is_empty() => None,
+        Ok(chunk) => Some(Ok(chunk)),
+        Err(e) => {
+          end = true;
+          Some(Err(e))
+        }
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hack::ExprErr;
+
+  #[test]
+  fn load_symbols_errors() {
+    let mut prog = Prog::from_bintext(b"0000000000000000").unwrap();
+    assert_eq!(prog.load_symbols(b"\n  \nlabel A 0\n"), Ok(()));
+
+    let invalid = |buf: &'static [u8]| {
+      let mut prog = Prog::from_bintext(b"0000000000000000").unwrap();
+      prog.load_symbols(buf).unwrap_err()
+    };
+
+    assert_eq!(invalid(b"\nfoo A 0"), Err::InvalidSymbol(Loc::new(2, 1)));
+    assert_eq!(invalid(b"label 1A 0"), Err::InvalidSymbol(Loc::new(1, 1)));
+    assert_eq!(invalid(b"label R0 0"), Err::InvalidSymbol(Loc::new(1, 1)));
+    assert_eq!(invalid(b"label A"), Err::InvalidSymbol(Loc::new(1, 1)));
+    assert_eq!(invalid(b"label A 32768"), Err::InvalidSymbol(Loc::new(1, 1)));
+    assert_eq!(invalid(b"var A 1x"), Err::InvalidSymbol(Loc::new(1, 1)));
+    assert_eq!(invalid(b"var A 1 2"), Err::InvalidSymbol(Loc::new(1, 1)));
+    assert_eq!(
+      invalid(b"label A 0\nvar A 16"),
+      Err::DuplicateLabel(String::from("A"), Loc::new(2, 1))
+    );
+  }
+
+  #[test]
+  fn from_source_all() {
+    let buf = b"@LOOP\n0;JMP\n(END)\n@END\n0;JMP\n(END)\nD=Q\n@i\nD;JGT\n@i\nM=0";
+    let errs = Prog::from_source_all(buf).err().unwrap();
+
+    assert_eq!(errs.len(), 2);
+    assert_eq!(errs[0], Err::DuplicateLabel(String::from("END"), Loc::new(6, 1)));
+    assert!(matches!(errs[1], Err::Asm(ref e) if e.loc() == Loc::new(7, 1)));
+
+    assert_eq!(Prog::from_source(buf).err(), Some(errs[0].clone()));
+
+    let prog = Prog::from_source_all(b"@i\nM=0\n(END)\n@END\n0;JMP").unwrap();
+    assert_eq!(prog.vars().count(), 1);
+    assert!(prog.warnings().is_empty());
+  }
+
+  #[test]
+  fn warnings() {
+    let buf = b"@LOOP\n0;JMP\n(END)\n@END\n0;JMP\n@i\nD;JGT\n@i\nM=0\n@j\nM=0\n@N\n0;JMP\n.equ N 3";
+    let prog = Prog::from_source(buf).unwrap();
+    let words = prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(words[..2], [16, 0xEA87]);
+
+    let warnings = prog.warnings();
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(warnings[0], Err::UndefinedLabel(String::from("LOOP"), Loc::new(1, 1)));
+    assert_eq!(warnings[1], Err::UndefinedLabel(String::from("i"), Loc::new(6, 1)));
+  }
+
+  #[test]
+  fn macros() {
+    let buf = b".macro PUSH value\n@value\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n.endm\n\
+                (START)\nPUSH 7\nSET R0, 2\nGOTO START";
+    let prog = Prog::from_source(buf).unwrap();
+    let insts = prog.insts().iter().map(|i| i.to_string()).collect::<Vec<_>>();
+    assert_eq!(insts[..6], ["@7", "D=A", "@SP", "AM=M+1", "A=A-1", "M=D"]);
+    assert_eq!(insts[6..], ["@2", "D=A", "@R0", "M=D", "@START", "0;JMP"]);
+    assert_eq!(prog.source_map()[5], Loc::new(10, 1));
+    assert_eq!(prog.source_map()[6], Loc::new(11, 1));
+    assert_eq!(prog.symtable().len(), 1);
+
+    let buf =
+      b".macro A\n(L)\n.macro B\n.endm\n.endm\nFOO\nGOTO\n.macro SET\n.endm\n.macro C";
+    let errs = Prog::from_source_all(buf).err().unwrap();
+    assert_eq!(errs.len(), 7);
+    assert_eq!(errs[0], Err::LabelInMacro(String::from("L"), Loc::new(2, 1)));
+    assert_eq!(errs[1], Err::NestedMacro(Loc::new(3, 1)));
+    assert_eq!(errs[2], Err::UnexpectedEndMacro(Loc::new(5, 1)));
+    assert_eq!(
+      errs[3],
+      Err::Macro(MacroErr::Unknown(String::from("FOO")), Loc::new(6, 1))
+    );
+    assert_eq!(
+      errs[4],
+      Err::Macro(MacroErr::Arity(String::from("GOTO"), 1, 0), Loc::new(7, 1))
+    );
+    assert_eq!(errs[5], Err::DuplicateMacro(String::from("SET"), Loc::new(8, 1)));
+    assert_eq!(errs[6], Err::UnclosedMacro(String::from("C"), Loc::new(10, 1)));
+  }
+
+  #[test]
+  fn macro_exprs() {
+    let buf = b".macro INC n\n@n+1\nD=A\n.endm\n.macro ROW r\nINC SCREEN+r*32\n.endm\n\
+                (n)\nINC 5\nROW 2\n(MULT)\n(.loop)\nINC .loop\nINC END\n(END)\nINC n";
+    let prog = Prog::from_source(buf).unwrap();
+    let words = prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(words[..4], [6, 0xEC10, 16384 + 64 + 1, 0xEC10]);
+    assert_eq!(words[4..], [5, 0xEC10, 9, 0xEC10, 1, 0xEC10]);
+    assert_eq!(prog.source_map()[2], Loc::new(10, 1));
+
+    let buf = b".macro INC n\n@n+1\n.endm\nINC x\nINC 32767";
+    let errs = Prog::from_source_all(buf).err().unwrap();
+    let undefined = AddrErr::Expr(ExprErr::Undefined(String::from("x")));
+    assert_eq!(
+      errs,
+      [
+        Err::Eval(undefined, Loc::new(4, 1)),
+        Err::Eval(AddrErr::Range(32768), Loc::new(5, 1))
+      ]
+    );
+  }
+
+  #[test]
+  fn includes() {
+    let main = Source::new(
+      Path::new("prog/Main.asm"),
+      b".include \"lib/Mult.asm\"\n@R0\nD=M\nGOTO MULT\n.include \"./lib/Mult.asm\"",
+    );
+    let mult = Source::new(
+      Path::new("prog/lib/Mult.asm"),
+      b".include \"../Main.asm\"\n.macro MUL\n@R2\n.endm\n(MULT)\nMUL",
+    );
+    let end = Source::new(Path::new("End.asm"), b"(END)\nGOTO END");
+
+    let prog = Prog::from_sources(&[main, mult, end]).unwrap();
+    let insts = prog.insts().iter().map(|i| i.to_string()).collect::<Vec<_>>();
+    assert_eq!(insts, ["@R2", "@R0", "D=M", "@MULT", "0;JMP", "@END", "0;JMP"]);
+    assert_eq!(prog.source_map()[0], Loc::new(6, 1).in_file(1));
+    assert_eq!(prog.source_map()[1], Loc::new(2, 1));
+    assert_eq!(prog.source_map()[5], Loc::new(2, 1).in_file(2));
+    assert_eq!(prog.symtable()[&Label::try_from(&b"MULT"[..]).unwrap()], 0);
+    assert_eq!(prog.symtable()[&Label::try_from(&b"END"[..]).unwrap()], 5);
+
+    let errs = Prog::from_sources(&[main, end]).err().unwrap();
+    assert_eq!(errs.len(), 2);
+    let path = Path::new("prog/lib/Mult.asm").display().to_string();
+    assert_eq!(errs[0], Err::MissingInclude(path.clone(), Loc::new(1, 1)));
+    assert_eq!(errs[1], Err::MissingInclude(path, Loc::new(5, 1)));
+
+    let lib = Source::new(Path::new("Lib.asm"), b"(A)\n.macro M\n.include \"Main.asm\"");
+    let errs = Prog::from_sources(&[end, lib]).err().unwrap();
+    assert_eq!(errs[0], Err::UnclosedMacro(String::from("M"), Loc::new(2, 1).in_file(1)));
+    let name = String::from("include");
+    assert_eq!(errs[1], Err::DirectiveInMacro(name, Loc::new(3, 1).in_file(1)));
+  }
+
+  #[test]
+  fn listing() {
+    let main = Source::new(Path::new("Main.asm"), b"GOTO END\n(LOOP)\n");
+    let end = Source::new(Path::new("End.asm"), b"  (END)\n  @LOOP // Back");
+
+    let prog = Prog::from_sources(&[main, end]).unwrap();
+    let listing = prog.to_listing(&[main, end]).unwrap();
+    let path = Path::new("End.asm").display();
+    assert_eq!(listing.len(), 6);
+    assert!(listing[0].starts_with(" ADDR  BINARY"));
+    assert!(listing[1].ends_with("  Main.asm:1:1  GOTO END"));
+    assert!(listing[2].ends_with("0;JMP"));
+    assert_eq!(listing[3].trim(), "(END)");
+    assert_eq!(listing[4].trim(), "(LOOP)");
+    assert!(listing[5].starts_with("    2  0000000000000010  0x0002  @2"));
+    assert!(listing[5].ends_with(&format!("  {}:2:3    @LOOP // Back", path)));
+  }
+
+  #[test]
+  fn consts() {
+    let buf =
+      b".equ ROW 2\n.equ COL ROW+1\n@SCREEN+ROW*32+COL\nD=A\n@ROW\n@'A'\n@x\n@x+1\n\
+                @LOOP+2\n(LOOP)\n@WIDTH\n.equ WIDTH 0b10000";
+    let prog = Prog::from_source(buf).unwrap();
+    let words = prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(words, [16384 + 67, 0xEC10, 2, 65, 16, 17, 9, 16]);
+    assert_eq!(prog.vars().map(|(l, &a)| (l.name(), a)).collect::<Vec<_>>(), [("x", 16)]);
+    assert_eq!(prog.insts()[0].to_string(), "@SCREEN+ROW*32+COL");
+
+    let buf = b".equ A 1\n.equ A 2\n(B)\n.equ B 3\n.equ C X\n@A-2\n@D*2\n@1/(A-1)\n\
+                .macro M\n.equ E 1\n.endm";
+    let errs = Prog::from_source_all(buf).err().unwrap();
+    assert_eq!(errs.len(), 7);
+    assert_eq!(errs[0], Err::DuplicateConst(String::from("A"), Loc::new(2, 1)));
+    assert_eq!(errs[1], Err::DuplicateConst(String::from("B"), Loc::new(4, 1)));
+    let undefined = |name: &str| AddrErr::Expr(ExprErr::Undefined(String::from(name)));
+    assert_eq!(errs[2], Err::Eval(undefined("X"), Loc::new(5, 1)));
+    assert_eq!(errs[3], Err::Eval(AddrErr::Range(-1), Loc::new(6, 1)));
+    assert_eq!(errs[4], Err::Eval(undefined("D"), Loc::new(7, 1)));
+    assert_eq!(errs[5], Err::Eval(AddrErr::Expr(ExprErr::DivZero), Loc::new(8, 1)));
+    assert_eq!(errs[6], Err::DirectiveInMacro(String::from("equ"), Loc::new(10, 1)));
+  }
+
+  #[test]
+  fn local_labels() {
+    let buf = b"(MULT)\n@.loop\n0;JMP\n(.loop)\n@.end\nD;JEQ\n@.loop\n0;JMP\n(.end)\n\
+                (DIV)\n(.loop)\n@1f\n0;JMP\n(1)\nGOTO 1b\n(1)\n@1b\nD;JGT\n\
+                @DIV.loop\n@MULT.end+1\n@.loop+1";
+    let prog = Prog::from_source(buf).unwrap();
+    let words = prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(words[..6], [2, 0xEA87, 6, 0xE302, 2, 0xEA87]);
+    assert_eq!(words[6..12], [8, 0xEA87, 8, 0xEA87, 10, 0xE301]);
+    assert_eq!(words[12..], [6, 7, 7]);
+
+    let symbols = prog.to_symbols().collect::<Vec<_>>();
+    assert_eq!(symbols[..3], ["label MULT 0", "label MULT.loop 2", "label DIV 6"]);
+    assert_eq!(symbols[3..], ["label DIV.loop 6", "label MULT.end 6"]);
+
+    let buf = b"(A)\n(.x)\n(.x)\n(B)\n(.x)\n@3f\n0;JMP\n(1)\n@2b\n0;JMP\n@.y\nM=0";
+    let errs = Prog::from_source_all(buf).err().unwrap();
+    assert_eq!(errs.len(), 3);
+    assert_eq!(errs[0], Err::DuplicateLabel(String::from("A.x"), Loc::new(3, 1)));
+    assert_eq!(errs[1], Err::UndefinedLabel(String::from("3f"), Loc::new(6, 1)));
+    assert_eq!(errs[2], Err::UndefinedLabel(String::from("2b"), Loc::new(9, 1)));
+
+    let prog = Prog::from_source(b"(A)\n@.y\nM=0").unwrap();
+    let vars = prog.vars().map(|(l, &a)| (l.to_string(), a)).collect::<Vec<_>>();
+    assert_eq!(vars, [(String::from("A.y"), 16)]);
+  }
+
+  #[test]
+  fn memory_layout() {
+    let buf = b".var counter @ 16\n.reserve buf 3\n.data 1, 2\n.data 3\n(LOOP)\n@x\n\
+                @buf+1\n@counter\n.equ N 2\n.var .tmp @ N*4\n.reserve y N\n@i";
+    let prog = Prog::from_source(buf).unwrap();
+    let symbols = prog.to_symbols().collect::<Vec<_>>();
+    assert_eq!(symbols[1..3], ["var LOOP.tmp 8", "var counter 16"]);
+    assert_eq!(symbols[3..], ["var buf 17", "var y 20", "var x 22", "var i 23"]);
+    assert_eq!(prog.data(), [(17, 1), (18, 2), (19, 3)]);
+
+    let buf = b".data 1\n.reserve a 2\n.data 1, 2, 3\n.var a @ 5\n(a)\n\
+                .var v @ 24575\n.data 1, 2\n.var w @ 0\n.data X, 0x10000\n\
+                .reserve big 16366\n@y\nM=0\n.macro M\n.var z @ 0\n.endm";
+    let errs = Prog::from_source_all(buf).err().unwrap();
+    assert_eq!(errs.len(), 9);
+    assert_eq!(errs[0], Err::DataWithoutVar(Loc::new(1, 1)));
+    assert_eq!(errs[1], Err::DataOverflow(String::from("a"), Loc::new(3, 1)));
+    assert_eq!(errs[2], Err::DuplicateVar(String::from("a"), Loc::new(4, 1)));
+    assert_eq!(errs[3], Err::DuplicateLabel(String::from("a"), Loc::new(5, 1)));
+    assert_eq!(errs[4], Err::DataOverflow(String::from("v"), Loc::new(7, 1)));
+    let undefined = AddrErr::Expr(ExprErr::Undefined(String::from("X")));
+    assert_eq!(errs[5], Err::Eval(undefined, Loc::new(9, 1)));
+    assert_eq!(errs[6], Err::Eval(AddrErr::Range(65536), Loc::new(9, 1)));
+    assert_eq!(errs[7], Err::RamOverflow(String::from("y"), Loc::new(11, 1)));
+    assert_eq!(errs[8], Err::DirectiveInMacro(String::from("var"), Loc::new(14, 1)));
+
+    let prog =
+      Prog::from_source(b".var a @ 16\n.data 1, 2\n@i\n.var b @ 19\n@j").unwrap();
+    let symbols = prog.to_symbols().collect::<Vec<_>>();
+    assert_eq!(symbols, ["var a 16", "var i 18", "var b 19", "var j 20"]);
+
+    let buf = b".var a @ 100\n.data 1, 2\n.var b @ 101\n.var c @ 100\n.reserve d 0\n\
+                .var e @ 99\n.data 1, 2";
+    let errs = Prog::from_source_all(buf).err().unwrap();
+    let name = String::from;
+    assert_eq!(errs.len(), 4);
+    assert_eq!(errs[0], Err::DataOverlap(name("a"), name("b"), Loc::new(2, 1)));
+    assert_eq!(errs[1], Err::RamOverlap(name("c"), name("a"), Loc::new(4, 1)));
+    assert_eq!(errs[2], Err::EmptyArray(name("d"), Loc::new(5, 1)));
+    assert_eq!(errs[3], Err::DataOverlap(name("e"), name("a"), Loc::new(7, 1)));
   }
 }
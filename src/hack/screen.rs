@@ -0,0 +1,186 @@
+//! The memory-mapped screen of the HACK computer.
+//!
+//! The screen is a 512x256 monochrome display that is mapped to the
+//! 8K words of data memory starting at [SCREEN](Sym::SCREEN). Each
+//! row of pixels is represented by 32 consecutive words, and the
+//! least significant bit of each word is the leftmost of its 16
+//! pixels. A set bit is a black pixel.
+//!
+//! [Screen] provides a read-only view of the screen memory and can
+//! encode it as a PBM or PNG image.
+
+use crate::hack::Sym;
+use std::io;
+
+/// Width of the screen in pixels.
+pub const WIDTH: usize = 512;
+
+/// Height of the screen in pixels.
+pub const HEIGHT: usize = 256;
+
+/// Number of words of data memory mapped to the screen.
+pub const SIZE: usize = WIDTH * HEIGHT / 16;
+
+/// Number of words in a row of pixels.
+const ROW_WORDS: usize = WIDTH / 16;
+
+/// A view of the screen memory of a HACK computer.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::Emu;
+/// use has::hack::Screen;
+///
+/// let mut emu = Emu::new();
+/// emu.ram_mut()[16384 + 32] = 0b101;
+///
+/// let screen = Screen::new(emu.ram());
+/// assert!(screen.pixel(0, 1));
+/// assert!(!screen.pixel(1, 1));
+/// assert!(screen.pixel(2, 1));
+/// assert!(!screen.pixel(0, 0));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Screen<'m> {
+  /// The memory mapped to the screen.
+  mem: &'m [u16],
+}
+
+impl<'m> Screen<'m> {
+  /// Create a view of the screen in data memory.
+  ///
+  /// # Arguments
+  ///
+  /// * `ram` - The data memory of the HACK computer.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `ram` is too small to contain the screen memory.
+  pub fn new(ram: &'m [u16]) -> Self {
+    let start = usize::from(u16::from(Sym::SCREEN));
+    Self { mem: &ram[start..start + SIZE] }
+  }
+
+  /// Returns the memory mapped to the screen.
+  pub fn mem(&self) -> &'m [u16] {
+    self.mem
+  }
+
+  /// Returns whether the pixel at column `x` and row `y` is black.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the pixel is outside of the screen.
+  pub fn pixel(&self, x: usize, y: usize) -> bool {
+    assert!(x < WIDTH && y < HEIGHT, "pixel ({}, {}) is outside of the screen", x, y);
+    self.mem[y * ROW_WORDS + x / 16] & (1 << (x % 16)) != 0
+  }
+
+  /// Returns the pixels of row `y` packed into bytes, with the
+  /// leftmost pixel of each byte in its most significant bit and set
+  /// bits for black pixels.
+  fn row_bytes(&self, y: usize) -> impl Iterator<Item = u8> + 'm {
+    self.mem[y * ROW_WORDS..(y + 1) * ROW_WORDS]
+      .iter()
+      .flat_map(|&word| [(word as u8).reverse_bits(), ((word >> 8) as u8).reverse_bits()])
+  }
+
+  /// Encode the screen as a binary PBM (`P4`) image.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Emu;
+  /// use has::hack::Screen;
+  ///
+  /// let mut emu = Emu::new();
+  /// emu.ram_mut()[16384] = 0b1;
+  ///
+  /// let pbm = Screen::new(emu.ram()).to_pbm();
+  /// assert!(pbm.starts_with(b"P4\n512 256\n"));
+  /// assert_eq!(pbm[11], 0b1000_0000);
+  /// assert_eq!(pbm.len(), 11 + 512 * 256 / 8);
+  /// ```
+  pub fn to_pbm(&self) -> Vec<u8> {
+    let header = format!("P4\n{} {}\n", WIDTH, HEIGHT);
+    let mut pbm = Vec::with_capacity(header.len() + WIDTH * HEIGHT / 8);
+    pbm.extend_from_slice(header.as_bytes());
+
+    for y in 0..HEIGHT {
+      pbm.extend(self.row_bytes(y));
+    }
+
+    pbm
+  }
+
+  /// Encode the screen as a 1-bit grayscale PNG image into `writer`.
+  ///
+  /// # Arguments
+  ///
+  /// * `writer` - The writer to encode the image into.
+  pub fn write_png<W: io::Write>(&self, writer: W) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+
+    // In grayscale PNG images, 0 is black.
+    let data =
+      (0..HEIGHT).flat_map(|y| self.row_bytes(y)).map(|b| !b).collect::<Vec<_>>();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hack::emu::RAM_SIZE;
+
+  #[test]
+  fn pixel() {
+    let mut ram = vec![0; RAM_SIZE];
+    ram[16384 + 31] = 0b1000_0000_0000_0000;
+    ram[16384 + SIZE - 1] = 0b1000_0000_0000_0000;
+
+    let screen = Screen::new(&ram);
+    assert!(screen.pixel(511, 0));
+    assert!(!screen.pixel(510, 0));
+    assert!(!screen.pixel(0, 1));
+    assert!(screen.pixel(511, 255));
+    assert_eq!(screen.mem().iter().filter(|&&w| w != 0).count(), 2);
+  }
+
+  #[test]
+  fn pbm() {
+    let mut ram = vec![0; RAM_SIZE];
+    ram[16384] = 0b0000_0001_1000_0011;
+
+    let pbm = Screen::new(&ram).to_pbm();
+    assert_eq!(&pbm[11..13], [0b1100_0001, 0b1000_0000]);
+    assert!(pbm[13..].iter().all(|&b| b == 0));
+  }
+
+  #[test]
+  fn png() {
+    let mut ram = vec![0; RAM_SIZE];
+    ram[16384] = 0b1;
+    ram[16384 + 32 + 1] = 0xFFFF;
+
+    let mut buf = Vec::new();
+    Screen::new(&ram).write_png(&mut buf).unwrap();
+
+    let decoder = png::Decoder::new(buf.as_slice());
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+
+    assert_eq!((info.width, info.height), (512, 256));
+    assert_eq!(info.bit_depth, png::BitDepth::One);
+    assert_eq!(data[0], 0b0111_1111);
+    assert_eq!(&data[64 + 2..64 + 4], [0, 0]);
+    assert_eq!(data[64 + 4], 0xFF);
+  }
+}
@@ -0,0 +1,218 @@
+//! Source files of HACK assembly programs.
+//!
+//! A program can span multiple [Source] files, either passed to the
+//! assembler together or pulled in with the `.include "file.asm"`
+//! [directive](crate::hack::Directive::Include). [load] collects the
+//! files of a program by following their includes, and
+//! [Prog::from_sources](crate::hack::Prog::from_sources) assembles
+//! them with a single symbol table.
+
+use crate::hack::Directive;
+use crate::hack::Parser;
+use crate::hack::TokenKind;
+use crate::Buf;
+use std::collections::VecDeque;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A HACK assembly source file.
+#[derive(new, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Source<'b> {
+  /// Path of the file.
+  path: &'b Path,
+
+  /// Contents of the file.
+  buf: Buf<'b>,
+}
+
+impl<'b> Source<'b> {
+  /// Returns the path of the source file.
+  pub fn path(&self) -> &'b Path {
+    self.path
+  }
+
+  /// Returns the contents of the source file.
+  pub fn buf(&self) -> Buf<'b> {
+    self.buf
+  }
+
+  /// Returns the path of a file included by the source file.
+  ///
+  /// Relative paths are resolved against the directory of the source
+  /// file, and `.` and `..` components are removed lexically so that
+  /// the same file is always resolved to the same path.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Source;
+  /// use std::path::Path;
+  ///
+  /// let source = Source::new(Path::new("prog/Main.asm"), &[]);
+  /// assert_eq!(source.resolve("lib/Mult.asm"), Path::new("prog/lib/Mult.asm"));
+  /// assert_eq!(source.resolve("/lib/Mult.asm"), Path::new("/lib/Mult.asm"));
+  /// assert_eq!(source.resolve("./../Mult.asm"), Path::new("Mult.asm"));
+  /// assert_eq!(source.resolve("../../Mult.asm"), Path::new("../Mult.asm"));
+  /// ```
+  pub fn resolve(&self, include: &str) -> PathBuf {
+    normalize(&self.path.parent().unwrap_or_else(|| Path::new("")).join(include))
+  }
+
+  /// Returns the resolved paths of the files included by the source
+  /// file, in order of appearance.
+  ///
+  /// Lines that cannot be parsed are skipped.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Source;
+  /// use std::path::Path;
+  ///
+  /// let buf = ".include \"Mult.asm\"\n@R0\n.include \"../Div.asm\"".as_bytes();
+  /// let source = Source::new(Path::new("prog/Main.asm"), buf);
+  /// assert_eq!(source.includes(), [Path::new("prog/Mult.asm"), Path::new("Div.asm")]);
+  /// ```
+  pub fn includes(&self) -> Vec<PathBuf> {
+    Parser::from(self.buf)
+      .filter_map(|token| match token.ok()?.kind() {
+        TokenKind::Directive(Directive::Include(path)) => Some(self.resolve(path)),
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+/// Load the source files of a program.
+///
+/// Reads the `roots` and, transitively, the files they include. Each
+/// file is read once, even if it is included more than once.
+///
+/// Returns the path and contents of each file, starting with the
+/// `roots` in order. Paths are normalized like [Source::resolve].
+///
+/// Fails if one of the `roots` cannot be read. Included files that
+/// cannot be read are left out, so that
+/// [Prog::from_sources](crate::hack::Prog::from_sources) reports
+/// them at the location of their `.include` directive.
+///
+/// # Arguments
+///
+/// * `roots` - The files passed to the assembler.
+///
+/// * `read` - Reads the contents of a file.
+///
+/// # Examples
+///
+/// ```
+/// use has::hack::source;
+/// use std::path::Path;
+/// use std::path::PathBuf;
+///
+/// let read = |path: &Path| match path.to_str() {
+///   Some("Main.asm") => Ok(b".include \"Mult.asm\"\n.include \"Mult.asm\"".to_vec()),
+///   Some("Mult.asm") => Ok(b"@R0".to_vec()),
+///   _ => Err(()),
+/// };
+///
+/// let files = source::load(&[PathBuf::from("Main.asm")], read).unwrap();
+/// let paths = files.iter().map(|(path, _)| path.as_path()).collect::<Vec<_>>();
+/// assert_eq!(paths, [Path::new("Main.asm"), Path::new("Mult.asm")]);
+///
+/// assert_eq!(source::load(&[PathBuf::from("Div.asm")], read), Err(()));
+///
+/// let read = |path: &Path| match path.to_str() {
+///   Some("Main.asm") => Ok(b".include \"Div.asm\"".to_vec()),
+///   _ => Err(()),
+/// };
+///
+/// let files = source::load(&[PathBuf::from("Main.asm")], read).unwrap();
+/// assert_eq!(files.len(), 1);
+/// ```
+pub fn load<E, F>(roots: &[PathBuf], mut read: F) -> Result<Vec<(PathBuf, Vec<u8>)>, E>
+where
+  F: FnMut(&Path) -> Result<Vec<u8>, E>,
+{
+  let mut files: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+  let mut queue =
+    roots.iter().map(|root| (normalize(root), true)).collect::<VecDeque<_>>();
+
+  while let Some((path, root)) = queue.pop_front() {
+    if files.iter().any(|(file, _)| *file == path) {
+      continue;
+    }
+
+    let buf = match read(&path) {
+      Ok(buf) => buf,
+      Err(e) if root => return Err(e),
+      Err(_) => continue,
+    };
+
+    queue
+      .extend(Source::new(&path, &buf).includes().into_iter().map(|path| (path, false)));
+    files.push((path, buf));
+  }
+
+  Ok(files)
+}
+
+/// Remove the `.` and `..` components of a path lexically, without
+/// accessing the file system.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+
+  for component in path.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir
+        if matches!(normalized.components().next_back(), Some(Component::Normal(_))) =>
+      {
+        normalized.pop();
+      }
+      _ => normalized.push(component),
+    }
+  }
+
+  normalized
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hack::Prog;
+  use crate::Loc;
+
+  #[test]
+  fn cycle() {
+    let read = |path: &Path| match path.to_str() {
+      Some("a/A.asm") => Ok(b".include \"b/B.asm\"".to_vec()),
+      Some("a/b/B.asm") => Ok(b".include \"../A.asm\"\n.include \"C.asm\"".to_vec()),
+      Some("a/b/C.asm") => Ok(b"".to_vec()),
+      _ => Err(path.to_owned()),
+    };
+
+    let files = load(&[PathBuf::from("./a/A.asm"), PathBuf::from("a/b/C.asm")], read);
+    let paths = files.unwrap().into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+    assert_eq!(paths, ["a/A.asm", "a/b/C.asm", "a/b/B.asm"].map(PathBuf::from));
+  }
+
+  #[test]
+  fn missing_include() {
+    let read = |path: &Path| match path.to_str() {
+      Some("A.asm") => Ok(b"@0\n.include \"B.asm\"\n".to_vec()),
+      _ => Err(path.to_owned()),
+    };
+
+    let files = load(&[PathBuf::from("A.asm")], read).unwrap();
+    assert_eq!(files.len(), 1);
+
+    let sources =
+      files.iter().map(|(path, buf)| Source::new(path, buf)).collect::<Vec<_>>();
+    let errs = Prog::from_sources(&sources).err().unwrap();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].loc(), Some(Loc::new(2, 1)));
+
+    assert_eq!(load(&[PathBuf::from("B.asm")], read), Err(PathBuf::from("B.asm")));
+  }
+}
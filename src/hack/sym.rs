@@ -114,6 +114,57 @@ pub enum Sym {
   KBD,
 }
 
+impl Sym {
+  /// Returns the register or memory-mapped device symbol for a data
+  /// memory address, if any.
+  ///
+  /// Addresses `0` to `15` map to the registers `R0` to `R15` rather
+  /// than their virtual machine aliases (e.g. `SP`).
+  ///
+  /// # Arguments
+  ///
+  /// * `addr` - The data memory address.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::Sym;
+  ///
+  /// assert_eq!(Sym::from_addr(0), Some(Sym::R0));
+  /// assert_eq!(Sym::from_addr(15), Some(Sym::R15));
+  /// assert_eq!(Sym::from_addr(16), None);
+  /// assert_eq!(Sym::from_addr(16384), Some(Sym::SCREEN));
+  /// assert_eq!(Sym::from_addr(24576), Some(Sym::KBD));
+  /// ```
+  pub fn from_addr(addr: u16) -> Option<Self> {
+    const REGISTERS: [Sym; 16] = [
+      Sym::R0,
+      Sym::R1,
+      Sym::R2,
+      Sym::R3,
+      Sym::R4,
+      Sym::R5,
+      Sym::R6,
+      Sym::R7,
+      Sym::R8,
+      Sym::R9,
+      Sym::R10,
+      Sym::R11,
+      Sym::R12,
+      Sym::R13,
+      Sym::R14,
+      Sym::R15,
+    ];
+
+    match addr {
+      0x0000..=0x000F => Some(REGISTERS[usize::from(addr)]),
+      0x4000 => Some(Sym::SCREEN),
+      0x6000 => Some(Sym::KBD),
+      _ => None,
+    }
+  }
+}
+
 /// Serialize a [Sym] object to [u16].
 ///
 /// # Examples
@@ -0,0 +1,894 @@
+//! Runner for Nand2Tetris test scripts targeting the HACK CPU.
+//!
+//! Test scripts (`.tst` files) load a HACK program into the
+//! [emulator](Emu), set up registers and memory, run the program for
+//! a number of clock cycles and output selected values into a table
+//! that is compared line by line to a compare file (`.cmp` file).
+//!
+//! [Script] parses a test script and [run] executes it. The
+//! following subset of the test script language is supported:
+//!
+//! * `load FILE` loads a `.asm` or `.hack` (bintext) file.
+//! * `output-file FILE` and `compare-to FILE`.
+//! * `output-list VAR%FMT ...` where `VAR` is one of `RAM[N]`, `A`,
+//!   `D` or `PC` and `FMT` is `B`, `D` or `X` followed by the left
+//!   padding, length and right padding (e.g. `RAM[0]%D2.6.2`).
+//! * `set VAR VALUE` where `VALUE` is a decimal or `%B`, `%D` or `%X`
+//!   prefixed value.
+//! * `repeat N { ... }`, `ticktock`, `output` and `echo "TEXT"`.
+
+use crate::hack::source;
+use crate::hack::Emu;
+use crate::hack::EmuErr;
+use crate::hack::Prog;
+use crate::hack::Source;
+use crate::parser;
+use crate::Buf;
+use crate::Index;
+use crate::Loc;
+use derive_more::Display;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A variable of the emulator that can be set or output.
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Var {
+  /// A cell in the data memory.
+  #[display(fmt = "RAM[{}]", _0)]
+  Ram(u16),
+
+  /// The Address register.
+  #[display(fmt = "A")]
+  A,
+
+  /// The Data register.
+  #[display(fmt = "D")]
+  D,
+
+  /// The program counter.
+  #[display(fmt = "PC")]
+  Pc,
+}
+
+impl Var {
+  /// Parse a variable name (e.g. `RAM[16]` or `PC`).
+  fn parse(txt: &str) -> Option<Self> {
+    match txt {
+      "A" => Some(Var::A),
+      "D" => Some(Var::D),
+      "PC" => Some(Var::Pc),
+      _ => {
+        let addr = txt.strip_prefix("RAM[")?.strip_suffix(']')?.parse::<u16>().ok()?;
+
+        if usize::from(addr) < crate::hack::emu::RAM_SIZE {
+          Some(Var::Ram(addr))
+        } else {
+          None
+        }
+      }
+    }
+  }
+
+  /// Returns the value of the variable in `emu`.
+  fn get(&self, emu: &Emu) -> u16 {
+    match *self {
+      Var::Ram(addr) => emu.ram()[usize::from(addr)],
+      Var::A => emu.a(),
+      Var::D => emu.d(),
+      Var::Pc => emu.pc(),
+    }
+  }
+
+  /// Set the value of the variable in `emu`.
+  fn set(&self, emu: &mut Emu, value: u16) {
+    match *self {
+      Var::Ram(addr) => emu.ram_mut()[usize::from(addr)] = value,
+      Var::A => emu.set_a(value),
+      Var::D => emu.set_d(value),
+      Var::Pc => emu.set_pc(value),
+    }
+  }
+}
+
+/// The radix used to output a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+  /// Binary.
+  Bin,
+
+  /// Signed decimal.
+  Dec,
+
+  /// Hexadecimal.
+  Hex,
+}
+
+/// A column in the output table as defined by `output-list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column<'b> {
+  /// The name of the column as written in the script.
+  name: &'b str,
+
+  /// The variable output in the column.
+  var: Var,
+
+  /// The radix the variable is output in.
+  radix: Radix,
+
+  /// Spaces to the left of the value.
+  pad_l: usize,
+
+  /// Width of the value.
+  len: usize,
+
+  /// Spaces to the right of the value.
+  pad_r: usize,
+}
+
+impl<'b> Column<'b> {
+  /// Parse a column specification (e.g. `RAM[0]%D2.6.2`).
+  ///
+  /// Columns without a format default to `%B1.16.1`.
+  fn parse(txt: &'b str) -> Option<Self> {
+    let (name, fmt) = match txt.split_once('%') {
+      Some((name, fmt)) => (name, fmt),
+      None => (txt, "B1.16.1"),
+    };
+
+    let var = Var::parse(name)?;
+    let radix = match fmt.as_bytes().first()? {
+      b'B' => Radix::Bin,
+      b'D' => Radix::Dec,
+      b'X' => Radix::Hex,
+      _ => return None,
+    };
+
+    let mut widths = fmt[1..].split('.').map(|w| w.parse::<usize>().ok());
+    let pad_l = widths.next()??;
+    let len = widths.next()??;
+    let pad_r = widths.next()??;
+
+    if widths.next().is_some() {
+      return None;
+    }
+
+    Some(Self { name, var, radix, pad_l, len, pad_r })
+  }
+
+  /// Returns the header of the column, centered in its width.
+  fn header(&self) -> String {
+    let width = self.pad_l + self.len + self.pad_r;
+    let name = &self.name[..self.name.len().min(width)];
+    let left = (width - name.len()) / 2;
+    let right = width - left - name.len();
+    format!("{:left$}{}{:right$}", "", name, "", left = left, right = right)
+  }
+
+  /// Returns the formatted value of the column's variable in `emu`.
+  fn value(&self, emu: &Emu) -> String {
+    let value = self.var.get(emu);
+    let txt = match self.radix {
+      Radix::Dec => format!("{:>len$}", value as i16, len = self.len),
+      Radix::Bin => format!("{:0len$b}", value, len = self.len),
+      Radix::Hex => format!("{:0len$X}", value, len = self.len),
+    };
+    let txt = &txt[txt.len().saturating_sub(self.len)..];
+    format!("{:pad_l$}{}{:pad_r$}", "", txt, "", pad_l = self.pad_l, pad_r = self.pad_r)
+  }
+}
+
+/// A command in a test script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command<'b> {
+  /// Load a program into the emulator.
+  Load(&'b str),
+
+  /// Set the file the output table is written to.
+  OutputFile(&'b str),
+
+  /// Set the file the output table is compared to.
+  CompareTo(&'b str),
+
+  /// Set the columns of the output table.
+  OutputList(Vec<Column<'b>>),
+
+  /// Set a variable to a value.
+  Set(Var, u16),
+
+  /// Repeat a list of commands.
+  Repeat(usize, Vec<Command<'b>>),
+
+  /// Execute a single clock cycle.
+  TickTock,
+
+  /// Output a row into the output table.
+  Output,
+
+  /// Print a message.
+  Echo(&'b str),
+}
+
+/// Kind of test script parsing error.
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum ErrKind {
+  /// Unknown or unsupported command.
+  #[display(fmt = "unknown command `{}`", _0)]
+  UnknownCommand(String),
+
+  /// Missing a command argument.
+  #[display(fmt = "missing argument for `{}`", _0)]
+  MissingArg(String),
+
+  /// Invalid output-list column.
+  #[display(fmt = "invalid output column `{}`", _0)]
+  InvalidColumn(String),
+
+  /// Invalid variable.
+  #[display(fmt = "invalid variable `{}`", _0)]
+  InvalidVar(String),
+
+  /// Invalid value.
+  #[display(fmt = "invalid value `{}`", _0)]
+  InvalidValue(String),
+
+  /// Expected a command terminator.
+  #[display(fmt = "expected `,`, `;` or `!` after command")]
+  ExpectedTerminator,
+
+  /// Expected an opening brace.
+  #[display(fmt = "expected `{{` after repeat count")]
+  ExpectedLBrace,
+
+  /// Missing the closing brace of a block.
+  #[display(fmt = "expected a closing brace `}}`")]
+  MissingRBrace,
+
+  /// Missing the closing quote of a string.
+  #[display(fmt = "expected a closing quote `\"`")]
+  MissingQuote,
+
+  /// Missing the end of a block comment.
+  #[display(fmt = "expected the end of the comment `*/`")]
+  UnterminatedComment,
+
+  /// A word or string that is not valid UTF-8.
+  #[display(fmt = "invalid UTF-8 text")]
+  InvalidText,
+}
+
+/// Error during parsing of a test script.
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+#[display(fmt = "Test script error at {}: {}", loc, kind)]
+pub struct Err {
+  /// [Location](Loc) of the error in the test script.
+  loc: Loc,
+
+  /// The type of error.
+  kind: ErrKind,
+}
+
+impl Err {
+  /// Create a new test script parsing error.
+  fn new(buf: Buf, index: Index, kind: ErrKind) -> Self {
+    Self { loc: Loc::from_index(buf, index), kind }
+  }
+
+  /// Returns the [location](Loc) in the test script at which the
+  /// error occurred.
+  pub fn loc(&self) -> Loc {
+    self.loc
+  }
+
+  /// Returns the kind of parsing error.
+  pub fn kind(&self) -> &ErrKind {
+    &self.kind
+  }
+}
+
+/// Lexical units of test scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tok<'b> {
+  /// A word (command name or argument).
+  Word(&'b str),
+
+  /// A quoted string without the quotes.
+  Str(&'b str),
+
+  /// A command terminator (`,`, `;` or `!`).
+  End,
+
+  /// `{`.
+  LBrace,
+
+  /// `}`.
+  RBrace,
+}
+
+/// Split a test script into [tokens](Tok) and their indices.
+fn lex(buf: Buf) -> Result<Vec<(Index, Tok)>, Err> {
+  let is_word = |b: u8| !b.is_ascii_whitespace() && !b",;!{}\"".contains(&b);
+  let text = |txt, index| {
+    std::str::from_utf8(txt)
+      .map_err(|e| Err::new(buf, index + e.valid_up_to(), ErrKind::InvalidText))
+  };
+  let mut toks = Vec::new();
+  let mut index = 0;
+
+  while let Some(&b) = buf.get(index) {
+    let rem = &buf[index..];
+
+    if b.is_ascii_whitespace() {
+      index += parser::read_ws(rem).0;
+    } else if rem.starts_with(b"//") {
+      index += parser::read_until_nl(rem).0.len();
+    } else if rem.starts_with(b"/*") {
+      match rem.windows(2).position(|w| w == b"*/") {
+        Some(end) => index += end + 2,
+        None => return Err(Err::new(buf, index, ErrKind::UnterminatedComment)),
+      }
+    } else if b == b'"' {
+      let (txt, rest) = parser::read_while(&rem[1..], |b| b != b'"');
+
+      if rest.is_empty() {
+        return Err(Err::new(buf, index, ErrKind::MissingQuote));
+      }
+
+      toks.push((index, Tok::Str(text(txt, index + 1)?)));
+      index += txt.len() + 2;
+    } else if is_word(b) {
+      let (txt, _) = parser::read_while(rem, is_word);
+      toks.push((index, Tok::Word(text(txt, index)?)));
+      index += txt.len();
+    } else {
+      let tok = match b {
+        b'{' => Tok::LBrace,
+        b'}' => Tok::RBrace,
+        _ => Tok::End,
+      };
+
+      toks.push((index, tok));
+      index += 1;
+    }
+  }
+
+  Ok(toks)
+}
+
+/// Parse a value as used in `set` commands.
+///
+/// Values are either decimal (possibly negative) or prefixed with
+/// `%B` (binary), `%D` (decimal) or `%X` (hexadecimal).
+fn parse_value(txt: &str) -> Option<u16> {
+  let (radix, digits) = match txt.strip_prefix('%') {
+    Some(rest) => match rest.as_bytes().first()? {
+      b'B' => (2, &rest[1..]),
+      b'D' => (10, &rest[1..]),
+      b'X' => (16, &rest[1..]),
+      _ => return None,
+    },
+    None => (10, txt),
+  };
+
+  if radix == 10 {
+    match digits.parse::<i16>() {
+      Ok(value) => Some(value as u16),
+      Err(_) => digits.parse::<u16>().ok(),
+    }
+  } else {
+    u16::from_str_radix(digits, radix).ok()
+  }
+}
+
+/// A parsed test script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script<'b> {
+  /// The top-level commands of the script.
+  cmds: Vec<Command<'b>>,
+}
+
+/// Token stream used while parsing a [Script].
+struct Toks<'b, 't> {
+  /// The original test script buffer.
+  buf: Buf<'b>,
+
+  /// The tokens and their indices.
+  toks: &'t [(Index, Tok<'b>)],
+
+  /// The index of the next token.
+  pos: usize,
+}
+
+impl<'b, 't> Toks<'b, 't> {
+  /// Returns the next token without consuming it.
+  fn peek(&self) -> Option<Tok<'b>> {
+    self.toks.get(self.pos).map(|&(_, tok)| tok)
+  }
+
+  /// Consumes and returns the next token.
+  fn next(&mut self) -> Option<Tok<'b>> {
+    let tok = self.peek();
+    self.pos += 1;
+    tok
+  }
+
+  /// Create an error at the current token (or at the end of the
+  /// script).
+  fn err(&self, kind: ErrKind) -> Err {
+    let index = self.toks.get(self.pos).map_or(self.buf.len(), |&(index, _)| index);
+    Err::new(self.buf, index, kind)
+  }
+
+  /// Create an error at the previous token.
+  fn err_prev(&self, kind: ErrKind) -> Err {
+    let index = self.toks[self.pos.saturating_sub(1)].0;
+    Err::new(self.buf, index, kind)
+  }
+
+  /// Consumes the next token as a command argument.
+  fn arg(&mut self, cmd: &str) -> Result<&'b str, Err> {
+    match self.peek() {
+      Some(Tok::Word(word)) => {
+        self.pos += 1;
+        Ok(word)
+      }
+      _ => Err(self.err(ErrKind::MissingArg(String::from(cmd)))),
+    }
+  }
+
+  /// Parse commands until the end of the script or a closing brace.
+  fn cmds(&mut self) -> Result<Vec<Command<'b>>, Err> {
+    let mut cmds = Vec::new();
+
+    loop {
+      match self.peek() {
+        None | Some(Tok::RBrace) => return Ok(cmds),
+        Some(Tok::End) => self.pos += 1,
+        Some(_) => cmds.push(self.cmd()?),
+      }
+    }
+  }
+
+  /// Parse a single command.
+  fn cmd(&mut self) -> Result<Command<'b>, Err> {
+    let name = match self.next() {
+      Some(Tok::Word(name)) => name,
+      Some(Tok::Str(txt)) => {
+        return Err(self.err_prev(ErrKind::UnknownCommand(txt.into())))
+      }
+      _ => return Err(self.err_prev(ErrKind::UnknownCommand(String::new()))),
+    };
+
+    let cmd = match name {
+      "load" => Command::Load(self.arg(name)?),
+      "output-file" => Command::OutputFile(self.arg(name)?),
+      "compare-to" => Command::CompareTo(self.arg(name)?),
+      "output-list" => {
+        let mut columns = Vec::new();
+
+        while let Some(Tok::Word(word)) = self.peek() {
+          let column = Column::parse(word);
+          self.pos += 1;
+          columns.push(
+            column.ok_or_else(|| self.err_prev(ErrKind::InvalidColumn(word.into())))?,
+          );
+        }
+
+        Command::OutputList(columns)
+      }
+      "set" => {
+        let var = self.arg(name)?;
+        let var = Var::parse(var)
+          .ok_or_else(|| self.err_prev(ErrKind::InvalidVar(var.into())))?;
+        let value = self.arg(name)?;
+        let value = parse_value(value)
+          .ok_or_else(|| self.err_prev(ErrKind::InvalidValue(value.into())))?;
+        Command::Set(var, value)
+      }
+      "repeat" => {
+        let count = self.arg(name)?;
+        let count = count
+          .parse::<usize>()
+          .map_err(|_| self.err_prev(ErrKind::InvalidValue(count.into())))?;
+
+        if self.next() != Some(Tok::LBrace) {
+          return Err(self.err_prev(ErrKind::ExpectedLBrace));
+        }
+
+        let body = self.cmds()?;
+
+        if self.next() != Some(Tok::RBrace) {
+          return Err(self.err(ErrKind::MissingRBrace));
+        }
+
+        return Ok(Command::Repeat(count, body));
+      }
+      "ticktock" => Command::TickTock,
+      "output" => Command::Output,
+      "echo" => match self.next() {
+        Some(Tok::Str(txt)) | Some(Tok::Word(txt)) => Command::Echo(txt),
+        _ => return Err(self.err_prev(ErrKind::MissingArg(String::from(name)))),
+      },
+      _ => return Err(self.err_prev(ErrKind::UnknownCommand(String::from(name)))),
+    };
+
+    match self.peek() {
+      Some(Tok::End) => {
+        self.pos += 1;
+        Ok(cmd)
+      }
+      _ => Err(self.err(ErrKind::ExpectedTerminator)),
+    }
+  }
+}
+
+impl<'b> Script<'b> {
+  /// Parse a test script.
+  ///
+  /// # Arguments
+  ///
+  /// * `buf` - The contents of the test script.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::hack::tst::Script;
+  ///
+  /// let buf = "load Max.asm, set RAM[0] 3, repeat 14 { ticktock; } output;";
+  /// let script = Script::parse(buf.as_bytes()).unwrap();
+  /// assert_eq!(script.cmds().len(), 4);
+  ///
+  /// assert!(Script::parse("repeat 2 { ticktock;".as_bytes()).is_err());
+  /// ```
+  pub fn parse(buf: Buf<'b>) -> Result<Self, Err> {
+    let toks = lex(buf)?;
+    let mut toks = Toks { buf, toks: &toks, pos: 0 };
+    let cmds = toks.cmds()?;
+
+    if toks.peek().is_some() {
+      return Err(toks.err(ErrKind::UnknownCommand(String::from("}"))));
+    }
+
+    Ok(Self { cmds })
+  }
+
+  /// Returns the top-level commands of the script.
+  pub fn cmds(&self) -> &[Command<'b>] {
+    &self.cmds
+  }
+}
+
+/// Possible errors when running a test script.
+#[derive(Display, Debug)]
+pub enum RunErr {
+  /// Failed to read a file referenced by the script.
+  #[display(fmt = "Could not read `{}`: {}", "_0.display()", _1)]
+  Io(PathBuf, io::Error),
+
+  /// Failed to load a program.
+  #[display(fmt = "Could not load `{}`: {}", "_0.display()", _1)]
+  Load(PathBuf, EmuErr),
+
+  /// Emulator error while executing the program.
+  #[display(fmt = "{}", _0)]
+  Emu(EmuErr),
+
+  /// Output was requested before an `output-list` command.
+  #[display(fmt = "No output list was defined")]
+  NoOutputList,
+
+  /// The program was executed before a `load` command.
+  #[display(fmt = "No program was loaded")]
+  NoProgram,
+}
+
+/// A mismatch between the output table and the compare file.
+#[derive(Debug, Clone, PartialEq, Eq, new)]
+pub struct Mismatch {
+  /// The 1-based line number of the mismatch.
+  line: usize,
+
+  /// The expected line from the compare file.
+  expected: String,
+
+  /// The actual line in the output table.
+  actual: String,
+}
+
+impl Mismatch {
+  /// Returns the 1-based line number of the mismatch.
+  pub fn line(&self) -> usize {
+    self.line
+  }
+
+  /// Returns the expected line from the compare file.
+  pub fn expected(&self) -> &str {
+    &self.expected
+  }
+
+  /// Returns the actual line in the output table.
+  pub fn actual(&self) -> &str {
+    &self.actual
+  }
+}
+
+/// The result of running a test script.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Report {
+  /// The file the output table should be written to.
+  output_file: Option<PathBuf>,
+
+  /// The lines of the output table.
+  output: Vec<String>,
+
+  /// The first mismatch against the compare file, if any.
+  mismatch: Option<Mismatch>,
+
+  /// Messages printed with `echo`.
+  echo: Vec<String>,
+}
+
+impl Report {
+  /// Returns the file the output table should be written to, if the
+  /// script contained an `output-file` command.
+  pub fn output_file(&self) -> Option<&Path> {
+    self.output_file.as_deref()
+  }
+
+  /// Returns the lines of the output table.
+  pub fn output(&self) -> &[String] {
+    &self.output
+  }
+
+  /// Returns the first mismatch against the compare file, if any.
+  pub fn mismatch(&self) -> Option<&Mismatch> {
+    self.mismatch.as_ref()
+  }
+
+  /// Returns the messages printed with `echo`.
+  pub fn echo(&self) -> &[String] {
+    &self.echo
+  }
+
+  /// Returns whether the output table matched the compare file.
+  pub fn passed(&self) -> bool {
+    self.mismatch.is_none()
+  }
+}
+
+/// State of a running test script.
+struct Runner<'b, 'd> {
+  /// The directory files are resolved against.
+  dir: &'d Path,
+
+  /// The emulator, if a program was loaded.
+  emu: Option<Emu>,
+
+  /// The columns of the output table.
+  columns: Option<Vec<Column<'b>>>,
+
+  /// The lines of the compare file.
+  cmp: Option<Vec<String>>,
+
+  /// The report being built.
+  report: Report,
+}
+
+impl<'b, 'd> Runner<'b, 'd> {
+  /// Returns the loaded emulator.
+  fn emu(&mut self) -> Result<&mut Emu, RunErr> {
+    self.emu.as_mut().ok_or(RunErr::NoProgram)
+  }
+
+  /// Read a file relative to the script's directory.
+  fn read(&self, file: &str) -> Result<(PathBuf, Vec<u8>), RunErr> {
+    let path = self.dir.join(file);
+
+    match fs::read(&path) {
+      Ok(contents) => Ok((path, contents)),
+      Err(e) => Err(RunErr::Io(path, e)),
+    }
+  }
+
+  /// Append a line to the output table and compare it.
+  ///
+  /// Returns `false` if the line did not match the compare file.
+  fn emit(&mut self, line: String) -> bool {
+    let index = self.report.output.len();
+
+    if let Some(cmp) = &self.cmp {
+      let expected = cmp.get(index).map_or("", |l| l.trim_end());
+      let actual = line.trim_end();
+
+      let matches = expected.len() == actual.len()
+        && expected.bytes().zip(actual.bytes()).all(|(e, a)| e == b'*' || e == a);
+
+      if !matches {
+        let mismatch =
+          Mismatch::new(index + 1, String::from(expected), String::from(actual));
+        self.report.mismatch = Some(mismatch);
+      }
+    }
+
+    self.report.output.push(line);
+    self.report.mismatch.is_none()
+  }
+
+  /// Execute a list of commands.
+  ///
+  /// Returns `false` if execution should stop due to a mismatch.
+  fn exec(&mut self, cmds: &[Command<'b>]) -> Result<bool, RunErr> {
+    for cmd in cmds {
+      match cmd {
+        Command::Load(file) => {
+          let path = self.dir.join(file);
+          let extension = path.extension().and_then(|ext| ext.to_str());
+          let read =
+            |path: &Path| fs::read(path).map_err(|e| RunErr::Io(path.to_owned(), e));
+          let files = match extension {
+            Some("asm") => source::load(std::slice::from_ref(&path), read)?,
+            _ => vec![self.read(file)?],
+          };
+          let sources = files.iter().map(|(path, buf)| Source::new(path, buf));
+          let sources = sources.collect::<Vec<_>>();
+          let contents = sources[0].buf();
+          let prog = match extension {
+            Some("asm") => {
+              Prog::from_sources(&sources).map_err(|mut errs| errs.swap_remove(0))
+            }
+            Some("hack") => Prog::from_bintext(contents),
+            _ => Prog::from_bin(contents),
+          };
+
+          match prog.map_err(EmuErr::from).and_then(|prog| Emu::from_prog(&prog)) {
+            Ok(emu) => self.emu = Some(emu),
+            Err(e) => return Err(RunErr::Load(path, e)),
+          }
+        }
+        Command::OutputFile(file) => self.report.output_file = Some(self.dir.join(file)),
+        Command::CompareTo(file) => {
+          let (_, contents) = self.read(file)?;
+          let cmp =
+            String::from_utf8_lossy(&contents).lines().map(String::from).collect();
+          self.cmp = Some(cmp);
+        }
+        Command::OutputList(columns) => {
+          let header = columns.iter().fold(String::from("|"), |mut line, column| {
+            line.push_str(&column.header());
+            line.push('|');
+            line
+          });
+
+          self.columns = Some(columns.clone());
+
+          if !self.emit(header) {
+            return Ok(false);
+          }
+        }
+        Command::Set(var, value) => var.set(self.emu()?, *value),
+        Command::Repeat(count, body) => {
+          for _ in 0..*count {
+            if !self.exec(body)? {
+              return Ok(false);
+            }
+          }
+        }
+        Command::TickTock => self.emu()?.step().map_err(RunErr::Emu)?,
+        Command::Output => {
+          let emu = self.emu.as_ref().ok_or(RunErr::NoProgram)?;
+          let columns = self.columns.as_ref().ok_or(RunErr::NoOutputList)?;
+          let line = columns.iter().fold(String::from("|"), |mut line, column| {
+            line.push_str(&column.value(emu));
+            line.push('|');
+            line
+          });
+
+          if !self.emit(line) {
+            return Ok(false);
+          }
+        }
+        Command::Echo(txt) => self.report.echo.push(String::from(*txt)),
+      }
+    }
+
+    Ok(true)
+  }
+}
+
+/// Run a test script.
+///
+/// Files referenced by the script are resolved relative to `dir`.
+/// Execution stops at the first line of the output table that does
+/// not match the compare file. The output table is not written to the
+/// output file, this is left to the caller (see
+/// [Report::output_file]).
+///
+/// # Arguments
+///
+/// * `script` - The parsed test script.
+///
+/// * `dir` - The directory containing the test script.
+pub fn run(script: &Script, dir: &Path) -> Result<Report, RunErr> {
+  let mut runner =
+    Runner { dir, emu: None, columns: None, cmp: None, report: Report::default() };
+
+  runner.exec(&script.cmds)?;
+  Ok(runner.report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn column() {
+    let column = Column::parse("RAM[0]%D2.6.2").unwrap();
+    assert_eq!(column.var, Var::Ram(0));
+    assert_eq!(column.header(), "  RAM[0]  ");
+
+    let column = Column::parse("PC%X1.4.1").unwrap();
+    assert_eq!(column.header(), "  PC  ");
+
+    assert!(Column::parse("RAM[0]%Q1.2.3").is_none());
+    assert!(Column::parse("RAM[0]%D1.2").is_none());
+    assert!(Column::parse("FOO%D1.2.3").is_none());
+  }
+
+  #[test]
+  fn value() {
+    let mut emu = Emu::new();
+    emu.ram_mut()[0] = (-42i16) as u16;
+
+    let column = Column::parse("RAM[0]%D2.6.2").unwrap();
+    assert_eq!(column.value(&emu), "     -42  ");
+
+    let column = Column::parse("RAM[0]%X1.4.1").unwrap();
+    assert_eq!(column.value(&emu), " FFD6 ");
+
+    let column = Column::parse("RAM[0]%B0.4.0").unwrap();
+    assert_eq!(column.value(&emu), "0110");
+  }
+
+  #[test]
+  fn set_value() {
+    assert_eq!(parse_value("42"), Some(42));
+    assert_eq!(parse_value("-1"), Some(0xFFFF));
+    assert_eq!(parse_value("%X7FFF"), Some(0x7FFF));
+    assert_eq!(parse_value("%B101"), Some(5));
+    assert_eq!(parse_value("%D-2"), Some(0xFFFE));
+    assert_eq!(parse_value("%Q1"), None);
+  }
+
+  #[test]
+  fn parse() {
+    let buf = "// Comment\n/* Block\ncomment */ load Max.hack,\nrepeat 2 { ticktock; }\necho \"Hi there\";";
+    let script = Script::parse(buf.as_bytes()).unwrap();
+    assert_eq!(
+      script.cmds(),
+      [
+        Command::Load("Max.hack"),
+        Command::Repeat(2, vec![Command::TickTock]),
+        Command::Echo("Hi there"),
+      ]
+    );
+
+    let err = Script::parse("load Max.hack\nticktock;".as_bytes()).unwrap_err();
+    assert_eq!(err.kind(), &ErrKind::ExpectedTerminator);
+    assert_eq!(err.loc(), Loc::new(2, 1));
+
+    let err = Script::parse("tick;".as_bytes()).unwrap_err();
+    assert_eq!(err.kind(), &ErrKind::UnknownCommand(String::from("tick")));
+
+    let err = Script::parse("set RAM[0] foo;".as_bytes()).unwrap_err();
+    assert_eq!(err.kind(), &ErrKind::InvalidValue(String::from("foo")));
+
+    let err = Script::parse(b"echo \"ok\";\nload M\xFFx.hack;").unwrap_err();
+    assert_eq!(err.kind(), &ErrKind::InvalidText);
+    assert_eq!(err.loc(), Loc::new(2, 7));
+
+    let err = Script::parse(b"echo \"\xC3\";").unwrap_err();
+    assert_eq!(err.kind(), &ErrKind::InvalidText);
+    assert_eq!(err.loc(), Loc::new(1, 7));
+  }
+}
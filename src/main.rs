@@ -4,13 +4,27 @@ use clap::Parser;
 use derive_more::Display;
 use derive_more::From;
 use has::hack;
+use has::hack::dbg::Command as DbgCommand;
+use has::hack::dbg::Stop;
 use has::hack::dec;
+use has::hack::emu;
+use has::hack::source;
+use has::hack::tst;
+use has::hack::Debugger;
+use has::hack::Diagnostic;
+use has::hack::Emu;
+use has::hack::Keyboard;
+use has::hack::LspServer;
+use has::hack::Source;
+use has::hack::TstScript;
 use has::HackProg;
 use has::HackProgErr;
 use log::{debug, info, trace};
 use std::fmt;
 use std::fs::File;
 use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
 use std::io::BufWriter;
 use std::io::Read;
 use std::io::Write;
@@ -26,11 +40,48 @@ enum Err {
   #[display(fmt = "Assembler error: {}", _0)]
   Asm(HackProgErr),
 
+  #[display(fmt = "Aborting due to {} error(s)", _0)]
+  #[from(ignore)]
+  Failed(usize),
+
   #[display(fmt = "Disassembler error: {}", _0)]
   Dis(dec::Err),
 
   #[display(fmt = "Decoding error: {}", _0)]
   Decode(hack::CmdErr),
+
+  #[display(fmt = "Emulator error: {}", _0)]
+  Emu(hack::EmuErr),
+
+  #[display(fmt = "Debugger error: {}", _0)]
+  Dbg(hack::DbgErr),
+
+  #[display(fmt = "Keyboard script error: {}", _0)]
+  Kbd(hack::KbdErr),
+
+  #[display(fmt = "PNG encoding error: {}", _0)]
+  Png(png::EncodingError),
+
+  #[display(fmt = "{}", _0)]
+  Tst(hack::TstErr),
+
+  #[display(fmt = "Test script error: {}", _0)]
+  TstRun(hack::TstRunErr),
+
+  #[display(fmt = "Language server error: {}", _0)]
+  Lsp(hack::LspErr),
+
+  #[display(fmt = "Comparison failure at line {}", _0)]
+  #[from(ignore)]
+  Compare(usize),
+
+  #[display(fmt = "{} file(s) not formatted", _0)]
+  #[from(ignore)]
+  Unformatted(usize),
+
+  #[display(fmt = "`-` can only be used for one {}", _0)]
+  #[from(ignore)]
+  Stdio(&'static str),
 }
 
 impl fmt::Debug for Err {
@@ -54,19 +105,46 @@ struct Opt {
 
 #[derive(Debug, clap::Subcommand)]
 enum Command {
-  /// Assemble a HACK file.
+  /// Assemble HACK files into one program.
   Asm {
     /// Output a bintext instead of binary file.
     #[clap(short, long)]
     bintext: bool,
 
-    /// Output file (must not exist).
+    /// The output format.
+    #[clap(short, long, value_enum, default_value_t = Encoding::Bin, conflicts_with = "bintext")]
+    format: Encoding,
+
+    /// Output file (must not exist, `-` for the standard output).
     #[clap(short, long, name = "OUT")]
     out: PathBuf,
 
-    /// Hack assembly file to compile.
-    #[clap(name = "FILE")]
-    file: PathBuf,
+    /// Write a source map with one `ADDRESS LINE COLUMN` entry per
+    /// instruction, followed by `FILE` when assembling more than one
+    /// file (must not exist).
+    #[clap(short, long, name = "MAP")]
+    source_map: Option<PathBuf>,
+
+    /// Write the symbol table with one `label NAME ADDRESS` or `var
+    /// NAME ADDRESS` entry per line (must not exist).
+    #[clap(long, name = "SYMBOLS")]
+    symbols: Option<PathBuf>,
+
+    /// Write a listing with the address, binary and hexadecimal
+    /// encoding, decoded instruction, and source location and line of
+    /// each instruction (must not exist).
+    #[clap(long, name = "LISTING")]
+    listing: Option<PathBuf>,
+
+    /// Format of error messages.
+    #[clap(long, value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
+
+    /// Hack assembly files to compile, sharing one symbol table (`-`
+    /// for the standard input). Files included with `.include` are
+    /// added automatically.
+    #[clap(name = "FILE", required = true)]
+    files: Vec<PathBuf>,
   },
 
   /// Disassemble a HACK file.
@@ -75,34 +153,308 @@ enum Command {
     #[clap(short, long)]
     bintext: bool,
 
-    /// Output file (must not exist).
+    /// The input format (detected from the contents by default).
+    #[clap(short, long, value_enum, conflicts_with = "bintext")]
+    format: Option<Encoding>,
+
+    /// Output file (must not exist, `-` for the standard output).
     #[clap(short, long, name = "OUT")]
     out: PathBuf,
 
-    /// Hack file to disassemble.
+    /// Symbol table file (as written by `has asm --symbols`) to
+    /// restore labels and variables from.
+    #[clap(long, name = "SYMBOLS")]
+    symbols: Option<PathBuf>,
+
+    /// Recover labels at jump targets and predefined symbols for
+    /// registers and memory-mapped devices.
+    #[clap(short, long)]
+    labels: bool,
+
+    /// Format of error messages.
+    #[clap(long, value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
+
+    /// Hack file to disassemble (`-` for the standard input).
+    #[clap(name = "FILE")]
+    file: PathBuf,
+  },
+
+  /// Run a HACK program.
+  Run {
+    /// The input format (inferred from the file extension by default).
+    #[clap(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// Maximum number of clock cycles to run for.
+    #[clap(short, long, default_value_t = 1_000_000)]
+    cycles: u64,
+
+    /// Initial RAM contents (e.g. `--ram 0=3 --ram 1=5`).
+    #[clap(short, long, value_parser = parse_ram_init)]
+    ram: Vec<(u16, u16)>,
+
+    /// Keyboard script with one `CYCLE KEY` event per line.
+    #[clap(short, long, name = "SCRIPT")]
+    keys: Option<PathBuf>,
+
+    /// Write the screen to a PNG (`.png`) or PBM image after the run
+    /// (must not exist).
+    #[clap(short, long, name = "IMAGE")]
+    screen: Option<PathBuf>,
+
+    /// Hack assembly, bintext or binary file to run.
+    #[clap(name = "FILE")]
+    file: PathBuf,
+  },
+
+  /// Debug a HACK program interactively.
+  Debug {
+    /// The input format (inferred from the file extension by default).
+    #[clap(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// Maximum number of clock cycles to run for on `continue`.
+    #[clap(short, long, default_value_t = 1_000_000)]
+    cycles: u64,
+
+    /// Initial RAM contents (e.g. `--ram 0=3 --ram 1=5`).
+    #[clap(short, long, value_parser = parse_ram_init)]
+    ram: Vec<(u16, u16)>,
+
+    /// Keyboard script with one `CYCLE KEY` event per line.
+    #[clap(short, long, name = "SCRIPT")]
+    keys: Option<PathBuf>,
+
+    /// Hack assembly, bintext or binary file to debug.
     #[clap(name = "FILE")]
     file: PathBuf,
   },
+
+  /// Run a Nand2Tetris test script against a HACK program.
+  Test {
+    /// Test script (`.tst`) file.
+    #[clap(name = "FILE")]
+    file: PathBuf,
+  },
+
+  /// Run a Language Server Protocol server over standard input and
+  /// output.
+  Lsp,
+
+  /// Format HACK assembly files in place.
+  Fmt {
+    /// Only check whether the files are formatted, listing those that
+    /// are not, without modifying them.
+    #[clap(short, long)]
+    check: bool,
+
+    /// Hack assembly files to format.
+    #[clap(name = "FILE", required = true)]
+    files: Vec<PathBuf>,
+  },
+}
+
+/// Formats of HACK program files.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Format {
+  /// HACK assembly (inferred from the `.asm` extension).
+  Asm,
+
+  /// Bintext (inferred from the `.hack` extension).
+  Bintext,
+
+  /// Binary (inferred from any other extension).
+  Bin,
+
+  /// Little-endian binary.
+  BinLe,
+
+  /// Intel HEX (inferred from the `.hex` extension).
+  Ihex,
+
+  /// Logisim `v2.0 raw` memory image.
+  Logisim,
+
+  /// Verilog memory file in hexadecimal (as read by `$readmemh`).
+  Readmemh,
+
+  /// Verilog memory file in binary (as read by `$readmemb`).
+  Readmemb,
+}
+
+/// Formats of compiled HACK programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Encoding {
+  /// Binary with two big-endian bytes per instruction.
+  Bin,
+
+  /// Binary with two little-endian bytes per instruction.
+  BinLe,
+
+  /// Bintext with one instruction in binary per line.
+  Bintext,
+
+  /// Intel HEX with two big-endian bytes per instruction.
+  Ihex,
+
+  /// Logisim `v2.0 raw` memory image.
+  Logisim,
+
+  /// Verilog memory file in hexadecimal (as read by `$readmemh`).
+  Readmemh,
+
+  /// Verilog memory file in binary (as read by `$readmemb`).
+  Readmemb,
+}
+
+/// Formats of error messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum MessageFormat {
+  /// Human-readable diagnostics with source snippets.
+  Human,
+
+  /// One JSON object per line and error, on the standard error.
+  Json,
+}
+
+impl Format {
+  fn infer(file: &Path) -> Self {
+    match file.extension().and_then(|ext| ext.to_str()) {
+      Some("asm") => Format::Asm,
+      Some("hack") => Format::Bintext,
+      Some("hex") => Format::Ihex,
+      _ => Format::Bin,
+    }
+  }
+
+  fn encoding(self) -> Option<Encoding> {
+    match self {
+      Format::Asm => None,
+      Format::Bintext => Some(Encoding::Bintext),
+      Format::Bin => Some(Encoding::Bin),
+      Format::BinLe => Some(Encoding::BinLe),
+      Format::Ihex => Some(Encoding::Ihex),
+      Format::Logisim => Some(Encoding::Logisim),
+      Format::Readmemh => Some(Encoding::Readmemh),
+      Format::Readmemb => Some(Encoding::Readmemb),
+    }
+  }
+}
+
+impl From<Encoding> for dec::Encoding {
+  fn from(encoding: Encoding) -> Self {
+    match encoding {
+      Encoding::Bin => dec::Encoding::Bin,
+      Encoding::BinLe => dec::Encoding::BinLe,
+      Encoding::Bintext => dec::Encoding::Bintext,
+      Encoding::Ihex => dec::Encoding::Ihex,
+      Encoding::Logisim => dec::Encoding::Logisim,
+      Encoding::Readmemh => dec::Encoding::Readmemh,
+      Encoding::Readmemb => dec::Encoding::Readmemb,
+    }
+  }
+}
+
+fn parse_ram_init(init: &str) -> Result<(u16, u16), String> {
+  let (addr, value) =
+    init.split_once('=').ok_or_else(|| format!("expected ADDR=VALUE, got `{}`", init))?;
+  let addr =
+    addr.parse::<u16>().map_err(|e| format!("invalid address `{}`: {}", addr, e))?;
+
+  if usize::from(addr) >= emu::RAM_SIZE {
+    return Err(format!("address `{}` is outside of RAM", addr));
+  }
+
+  let value = match value.parse::<u16>() {
+    Ok(value) => value,
+    Err(_) => value
+      .parse::<i16>()
+      .map_err(|e| format!("invalid value `{}`: {}", value, e))? as u16,
+  };
+
+  Ok((addr, value))
 }
 
 impl Command {
   fn exec(self) -> Result<(), Err> {
     match self {
-      Command::Asm { bintext, out, file } => exec_asm(bintext, out, file),
-      Command::Dis { bintext, out, file } => exec_dis(bintext, out, file),
+      Command::Asm {
+        bintext,
+        format,
+        out,
+        source_map,
+        symbols,
+        listing,
+        message_format,
+        files,
+      } => {
+        let format = if bintext { Encoding::Bintext } else { format };
+        exec_asm(format, out, source_map, symbols, listing, message_format, files)
+      }
+      Command::Dis { bintext, format, out, symbols, labels, message_format, file } => {
+        let format = if bintext { Some(Encoding::Bintext) } else { format };
+        exec_dis(format, out, symbols, labels, message_format, file)
+      }
+      Command::Run { format, cycles, ram, keys, screen, file } => {
+        exec_run(format, cycles, ram, keys, screen, file)
+      }
+      Command::Debug { format, cycles, ram, keys, file } => {
+        exec_debug(format, cycles, ram, keys, file)
+      }
+      Command::Test { file } => exec_test(file),
+      Command::Lsp => exec_lsp(),
+      Command::Fmt { check, files } => exec_fmt(check, files),
     }
   }
 }
 
+/// Whether `file` is `-`, which stands for the standard input or
+/// output.
+fn is_stdio(file: &Path) -> bool {
+  file == Path::new("-")
+}
+
+/// Ensure that at most one of `files` is `-`, since the standard input
+/// can only be read once and outputs would be interleaved on the
+/// standard output.
+fn ensure_single_stdio<'p>(
+  files: impl IntoIterator<Item = &'p Path>,
+  what: &'static str,
+) -> Result<(), Err> {
+  if files.into_iter().filter(|file| is_stdio(file)).count() > 1 {
+    return Err(Err::Stdio(what));
+  }
+
+  Ok(())
+}
+
+fn open_file(file: &Path) -> Result<Box<dyn BufRead>, Err> {
+  let reader: Box<dyn BufRead> = if is_stdio(file) {
+    Box::new(io::stdin().lock())
+  } else {
+    let file = File::open(file)
+      .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file.display(), e)))?;
+    Box::new(BufReader::new(file))
+  };
+  info!("Reading from file {}", file.display());
+  Ok(reader)
+}
+
 fn read_file(file: &Path) -> Result<Vec<u8>, Err> {
   let mut buf = Vec::with_capacity(1024);
-  let bytes = File::open(file)?.read_to_end(&mut buf)?;
+  let bytes = if is_stdio(file) {
+    io::stdin().lock().read_to_end(&mut buf)
+  } else {
+    File::open(file).and_then(|mut f| f.read_to_end(&mut buf))
+  }
+  .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file.display(), e)))?;
   info!("Read {} bytes from {}", bytes, file.display());
   Ok(buf)
 }
 
 fn ensure_available_outfile(out: &Path) -> Result<(), Err> {
-  if out.exists() {
+  if !is_stdio(out) && out.exists() {
     return Err(Err::Io(io::Error::new(
       io::ErrorKind::AlreadyExists,
       format!("File {} already exists", out.display()),
@@ -112,43 +464,141 @@ fn ensure_available_outfile(out: &Path) -> Result<(), Err> {
   Ok(())
 }
 
-fn create_outfile(out: &Path) -> Result<BufWriter<File>, Err> {
-  let output = File::create(out)?;
+fn create_outfile(out: &Path) -> Result<BufWriter<Box<dyn Write>>, Err> {
+  let output: Box<dyn Write> = if is_stdio(out) {
+    Box::new(io::stdout().lock())
+  } else {
+    Box::new(File::create(out)?)
+  };
   let writer = BufWriter::new(output);
   info!("Writing to file {}", out.display());
   Ok(writer)
 }
 
-fn exec_asm(text: bool, out: PathBuf, file: PathBuf) -> Result<(), Err> {
+fn exec_asm(
+  format: Encoding,
+  out: PathBuf,
+  source_map: Option<PathBuf>,
+  symbols: Option<PathBuf>,
+  listing: Option<PathBuf>,
+  message_format: MessageFormat,
+  files: Vec<PathBuf>,
+) -> Result<(), Err> {
+  ensure_single_stdio(files.iter().map(PathBuf::as_path), "input")?;
+  let outs =
+    [Some(out.as_path()), source_map.as_deref(), symbols.as_deref(), listing.as_deref()];
+  ensure_single_stdio(outs.iter().flatten().copied(), "output")?;
   ensure_available_outfile(&out)?;
-  let buf = read_file(&file)?;
 
-  info!("Parsing {}", file.display());
-  let prog = HackProg::from_source(buf.as_slice())?;
+  if let Some(source_map) = &source_map {
+    ensure_available_outfile(source_map)?;
+  }
+
+  if let Some(symbols) = &symbols {
+    ensure_available_outfile(symbols)?;
+  }
+
+  if let Some(listing) = &listing {
+    ensure_available_outfile(listing)?;
+  }
+
+  let files = source::load(&files, read_file)?;
+  let sources = to_sources(&files);
+
+  for source in &sources {
+    info!("Parsing {}", source.path().display());
+  }
+
+  let prog = HackProg::from_sources(&sources)
+    .map_err(|errs| report(message_format, &sources, errs))?;
+  print_diags(message_format, &sources, &prog.warnings(), true);
   let mut writer = create_outfile(&out)?;
+  prog.encode(format.into(), &mut writer)?;
+  writer.flush()?;
+
+  if let Some(source_map) = source_map {
+    let mut writer = create_outfile(&source_map)?;
+
+    for (addr, loc) in prog.source_map().iter().enumerate() {
+      if sources.len() > 1 {
+        let file = sources[loc.file()].path().display();
+        writeln!(writer, "{} {} {} {}", addr, loc.line(), loc.col(), file)?;
+      } else {
+        writeln!(writer, "{} {} {}", addr, loc.line(), loc.col())?;
+      }
+    }
+  }
 
-  if text {
-    for inst in prog.to_bintext() {
-      let inst = inst?;
-      writer.write_all(&inst)?;
-      writer.write_all(&[b'\n'])?;
+  if let Some(symbols) = symbols {
+    let mut writer = create_outfile(&symbols)?;
+
+    for entry in prog.to_symbols() {
+      writeln!(writer, "{}", entry)?;
     }
-  } else {
-    for inst in prog.to_bin() {
-      let inst = inst?;
-      writer.write_all(&inst)?;
+  }
+
+  if let Some(listing) = listing {
+    let mut writer = create_outfile(&listing)?;
+
+    for line in prog.to_listing(&sources)? {
+      writeln!(writer, "{}", line)?;
     }
   }
 
   Ok(())
 }
 
-fn exec_dis(text: bool, out: PathBuf, file: PathBuf) -> Result<(), Err> {
+fn exec_dis(
+  format: Option<Encoding>,
+  out: PathBuf,
+  symbols: Option<PathBuf>,
+  labels: bool,
+  message_format: MessageFormat,
+  file: PathBuf,
+) -> Result<(), Err> {
+  let inputs = [Some(file.as_path()), symbols.as_deref()];
+  ensure_single_stdio(inputs.iter().flatten().copied(), "input")?;
   ensure_available_outfile(&out)?;
+
+  // Without symbols and label recovery, instructions are written to
+  // the standard output as they are decoded. Output files are only
+  // created once the whole input has decoded.
+  if let (Some(format), None, false, true) = (format, &symbols, labels, is_stdio(&out)) {
+    return stream_dis(format.into(), message_format, &file);
+  }
+
   let buf = read_file(&file)?;
+  let symbols_buf = match &symbols {
+    Some(symbols) => read_file(symbols)?,
+    None => Vec::new(),
+  };
+
+  let source = Source::new(&file, &buf);
+  let encoding = match format {
+    Some(format) => format.into(),
+    None => {
+      let encoding = dec::detect(&buf)
+        .map_err(|e| report(message_format, &[source], vec![HackProgErr::from(e)]))?;
+      info!("Detected {} encoding", encoding);
+      encoding
+    }
+  };
 
   info!("Parsing {}", file.display());
-  let prog = if text { HackProg::from_bintext(&buf)? } else { HackProg::from_bin(&buf)? };
+  let mut prog = HackProg::decode(&buf, encoding)
+    .map_err(|e| report(message_format, &[source], vec![e]))?;
+
+  if let Some(symbols) = &symbols {
+    info!("Loading symbols from {}", symbols.display());
+    prog.load_symbols(&symbols_buf).map_err(|e| {
+      report(message_format, &[Source::new(symbols, &symbols_buf)], vec![e])
+    })?;
+  }
+
+  if labels {
+    info!("Recovering labels");
+    prog.recover_labels();
+  }
 
   let mut writer = create_outfile(&out)?;
 
@@ -157,6 +607,392 @@ fn exec_dis(text: bool, out: PathBuf, file: PathBuf) -> Result<(), Err> {
     writer.write_all(&[b'\n'])?;
   }
 
+  writer.flush()?;
+  Ok(())
+}
+
+fn stream_dis(
+  encoding: dec::Encoding,
+  message_format: MessageFormat,
+  file: &Path,
+) -> Result<(), Err> {
+  let reader = dec::Reader::new(open_file(file)?, encoding);
+  let mut writer = create_outfile(Path::new("-"))?;
+  info!("Decoding {} as {}", file.display(), encoding);
+
+  for cmd in reader {
+    let err = match cmd {
+      Ok(cmd) => {
+        writeln!(writer, "{}", cmd)?;
+        continue;
+      }
+      Err(dec::ReadErr::Io(e)) => {
+        return Err(Err::Io(io::Error::new(
+          e.kind(),
+          format!("{}: {}", file.display(), e),
+        )))
+      }
+      Err(dec::ReadErr::Dis(e)) => HackProgErr::from(e),
+      Err(dec::ReadErr::Decode(e)) => HackProgErr::from(e),
+    };
+
+    // The input is not kept, so the error is reported without its line.
+    writer.flush()?;
+    return Err(report(message_format, &[Source::new(file, &[])], vec![err]));
+  }
+
+  writer.flush()?;
+  Ok(())
+}
+
+fn to_sources(files: &[(PathBuf, Vec<u8>)]) -> Vec<Source<'_>> {
+  files.iter().map(|(path, buf)| Source::new(path, buf)).collect()
+}
+
+fn read_prog(
+  format: Option<Format>,
+  file: &Path,
+) -> Result<Vec<(PathBuf, Vec<u8>)>, Err> {
+  match format.unwrap_or_else(|| Format::infer(file)) {
+    Format::Asm => source::load(&[file.to_owned()], read_file),
+    _ => Ok(vec![(file.to_owned(), read_file(file)?)]),
+  }
+}
+
+fn parse_prog<'b>(
+  format: Option<Format>,
+  files: &'b [(PathBuf, Vec<u8>)],
+) -> Result<HackProg<'b>, Err> {
+  let sources = to_sources(files);
+  let (file, buf) = &files[0];
+  info!("Parsing {}", file.display());
+  match format.unwrap_or_else(|| Format::infer(file)).encoding() {
+    None => HackProg::from_sources(&sources),
+    Some(encoding) => HackProg::decode(buf, encoding.into()).map_err(|e| vec![e]),
+  }
+  .map_err(|errs| report(MessageFormat::Human, &sources, errs))
+}
+
+fn report(
+  message_format: MessageFormat,
+  sources: &[Source],
+  errs: Vec<HackProgErr>,
+) -> Err {
+  print_diags(message_format, sources, &errs, false);
+  Err::Failed(errs.len())
+}
+
+fn print_diags(
+  message_format: MessageFormat,
+  sources: &[Source],
+  errs: &[HackProgErr],
+  warning: bool,
+) {
+  for err in errs {
+    let source = sources[err.loc().map_or(0, |loc| loc.file())];
+    let name = source.path().display().to_string();
+    let diag = Diagnostic::new(source.buf(), err);
+    let diag = if warning { diag.into_warning() } else { diag };
+
+    match message_format {
+      MessageFormat::Human => eprintln!("{}", diag.render(&name)),
+      MessageFormat::Json => {
+        let json = serde_json::json!({
+          "file": name,
+          "line": diag.loc().map(|loc| loc.line()),
+          "column": diag.loc().map(|loc| loc.col()),
+          "length": diag.len(),
+          "severity": diag.severity(),
+          "kind": diag.kind(),
+          "code": diag.code(),
+          "message": diag.message(),
+          "hint": diag.hint(),
+        });
+
+        eprintln!("{}", json);
+      }
+    }
+  }
+}
+
+fn init_emu(
+  emu: &mut Emu,
+  ram: Vec<(u16, u16)>,
+  keys: Option<PathBuf>,
+) -> Result<(), Err> {
+  for (addr, value) in ram {
+    emu.ram_mut()[usize::from(addr)] = value;
+  }
+
+  if let Some(keys) = keys {
+    let buf = read_file(&keys)?;
+
+    info!("Parsing keyboard script {}", keys.display());
+    emu.set_keyboard(Keyboard::parse(&buf)?);
+  }
+
+  Ok(())
+}
+
+fn exec_run(
+  format: Option<Format>,
+  cycles: u64,
+  ram: Vec<(u16, u16)>,
+  keys: Option<PathBuf>,
+  screen: Option<PathBuf>,
+  file: PathBuf,
+) -> Result<(), Err> {
+  let inputs = [Some(file.as_path()), keys.as_deref()];
+  ensure_single_stdio(inputs.iter().flatten().copied(), "input")?;
+
+  if let Some(screen) = &screen {
+    // The state of the emulator is printed to the standard output.
+    ensure_single_stdio([Path::new("-"), screen], "output")?;
+    ensure_available_outfile(screen)?;
+  }
+
+  let files = read_prog(format, &file)?;
+  let prog = parse_prog(format, &files)?;
+  let mut emu = Emu::from_prog(&prog)?;
+  init_emu(&mut emu, ram, keys)?;
+
+  info!("Running {} for at most {} cycles", file.display(), cycles);
+  if emu.run(cycles)? {
+    println!("Halted after {} cycles", emu.cycles());
+  } else {
+    println!("Stopped after {} cycles", emu.cycles());
+  }
+
+  println!("A: {}", emu.a() as i16);
+  println!("D: {}", emu.d() as i16);
+  println!("PC: {}", emu.pc());
+
+  for (addr, &value) in emu.ram().iter().enumerate().filter(|(_, &value)| value != 0) {
+    println!("RAM[{}]: {}", addr, value as i16);
+  }
+
+  if let Some(screen) = screen {
+    let mut writer = create_outfile(&screen)?;
+
+    if screen.extension().and_then(|ext| ext.to_str()) == Some("png") {
+      emu.screen().write_png(&mut writer)?;
+    } else {
+      writer.write_all(&emu.screen().to_pbm())?;
+    }
+
+    writer.flush()?;
+  }
+
+  Ok(())
+}
+
+fn print_inst(dbg: &Debugger, addr: u16) {
+  for label in dbg.labels_at(addr) {
+    println!("({})", label);
+  }
+
+  let marker = if addr == dbg.emu().pc() { '>' } else { ' ' };
+  let bp = if dbg.breakpoints().any(|bp| bp == addr) { '*' } else { ' ' };
+
+  match (dbg.inst(addr), dbg.loc(addr)) {
+    (Some(inst), Some(loc)) => {
+      println!("{}{} {:5}: {:20} // {}", marker, bp, addr, inst.to_string(), loc)
+    }
+    (Some(inst), None) => println!("{}{} {:5}: {}", marker, bp, addr, inst),
+    (None, _) => println!(
+      "{}{} {:5}: {:#018b}",
+      marker,
+      bp,
+      addr,
+      dbg.emu().rom()[usize::from(addr)]
+    ),
+  }
+}
+
+fn print_ram(dbg: &Debugger, addr: u16) {
+  let value = dbg.emu().ram()[usize::from(addr)];
+  let vars = dbg.vars_at(addr).collect::<Vec<_>>();
+
+  if vars.is_empty() {
+    println!("RAM[{}]: {}", addr, value as i16);
+  } else {
+    println!("RAM[{}] ({}): {}", addr, vars.join(", "), value as i16);
+  }
+}
+
+fn exec_debug_cmd(dbg: &mut Debugger, cmd: DbgCommand, cycles: u64) -> Result<bool, Err> {
+  match cmd {
+    DbgCommand::Step(count) => {
+      let stop = dbg.cont(count)?;
+
+      if stop != Stop::Limit {
+        println!("{}", stop);
+      }
+
+      print_inst(dbg, dbg.emu().pc());
+    }
+    DbgCommand::Continue => {
+      println!("{}", dbg.cont(cycles)?);
+      print_inst(dbg, dbg.emu().pc());
+    }
+    DbgCommand::Break(name) => {
+      println!("Breakpoint at ROM[{}]", dbg.add_breakpoint(name)?)
+    }
+    DbgCommand::Delete(name) => {
+      if !dbg.remove_breakpoint(name)? {
+        println!("No breakpoint at `{}`", name);
+      }
+    }
+    DbgCommand::Watch(name) => {
+      println!("Watchpoint at RAM[{}]", dbg.add_watchpoint(name)?)
+    }
+    DbgCommand::Unwatch(name) => {
+      if !dbg.remove_watchpoint(name)? {
+        println!("No watchpoint at `{}`", name);
+      }
+    }
+    DbgCommand::Print(name) => print_ram(dbg, dbg.resolve_ram(name)?),
+    DbgCommand::Info => {
+      let emu = dbg.emu();
+      println!("A: {}", emu.a() as i16);
+      println!("D: {}", emu.d() as i16);
+      println!("PC: {}", emu.pc());
+      println!("Cycles: {}", emu.cycles());
+      print_inst(dbg, emu.pc());
+    }
+    DbgCommand::List => {
+      let pc = dbg.emu().pc();
+
+      for addr in pc.saturating_sub(4)..pc.saturating_add(6).min(emu::ROM_SIZE as u16) {
+        if addr == pc || dbg.inst(addr).is_some() {
+          print_inst(dbg, addr);
+        }
+      }
+    }
+    DbgCommand::Reset => {
+      dbg.emu_mut().reset();
+      print_inst(dbg, dbg.emu().pc());
+    }
+    DbgCommand::Help => println!("{}", DbgCommand::HELP),
+    DbgCommand::Quit => return Ok(false),
+  }
+
+  Ok(true)
+}
+
+fn exec_debug(
+  format: Option<Format>,
+  cycles: u64,
+  ram: Vec<(u16, u16)>,
+  keys: Option<PathBuf>,
+  file: PathBuf,
+) -> Result<(), Err> {
+  // Debugger commands are read from the standard input.
+  let inputs = [Some(Path::new("-")), Some(file.as_path()), keys.as_deref()];
+  ensure_single_stdio(inputs.iter().flatten().copied(), "input")?;
+
+  let files = read_prog(format, &file)?;
+  let prog = parse_prog(format, &files)?;
+  let mut dbg = Debugger::new(&prog)?;
+  init_emu(dbg.emu_mut(), ram, keys)?;
+
+  print_inst(&dbg, dbg.emu().pc());
+
+  let stdin = io::stdin();
+  let mut line = String::new();
+
+  loop {
+    print!("(has) ");
+    io::stdout().flush()?;
+
+    line.clear();
+    if stdin.read_line(&mut line)? == 0 {
+      println!();
+      return Ok(());
+    }
+
+    let result = match DbgCommand::parse(&line) {
+      Ok(Some(cmd)) => exec_debug_cmd(&mut dbg, cmd, cycles),
+      Ok(None) => Ok(true),
+      Err(e) => Err(Err::Dbg(e)),
+    };
+
+    match result {
+      Ok(true) => {}
+      Ok(false) => return Ok(()),
+      Err(e) => println!("{}", e),
+    }
+  }
+}
+
+fn exec_test(file: PathBuf) -> Result<(), Err> {
+  let buf = read_file(&file)?;
+
+  info!("Parsing {}", file.display());
+  let script = TstScript::parse(&buf)?;
+  let dir = file.parent().unwrap_or_else(|| Path::new(""));
+
+  info!("Running {}", file.display());
+  let report = tst::run(&script, dir)?;
+
+  for msg in report.echo() {
+    println!("{}", msg);
+  }
+
+  if let Some(out) = report.output_file() {
+    let mut writer = create_outfile(out)?;
+
+    for line in report.output() {
+      writer.write_all(line.as_bytes())?;
+      writer.write_all(b"\n")?;
+    }
+  }
+
+  if let Some(mismatch) = report.mismatch() {
+    println!("Expected: {}", mismatch.expected());
+    println!("Actual:   {}", mismatch.actual());
+    return Err(Err::Compare(mismatch.line()));
+  }
+
+  println!("End of script - Comparison ended successfully");
+  Ok(())
+}
+
+fn exec_lsp() -> Result<(), Err> {
+  info!("Serving the language server protocol over stdio");
+  let stdin = io::stdin();
+  let stdout = io::stdout();
+  LspServer::new().run(stdin.lock(), stdout.lock())?;
+  Ok(())
+}
+
+fn exec_fmt(check: bool, files: Vec<PathBuf>) -> Result<(), Err> {
+  let mut unformatted = 0;
+
+  for file in files {
+    let buf = read_file(&file)?;
+
+    info!("Formatting {}", file.display());
+    let out = hack::format::format(&buf)
+      .map_err(|errs| report(MessageFormat::Human, &[Source::new(&file, &buf)], errs))?;
+
+    if out.as_bytes() == buf.as_slice() {
+      continue;
+    }
+
+    if check {
+      println!("{}", file.display());
+      unformatted += 1;
+    } else {
+      info!("Writing to file {}", file.display());
+      std::fs::write(&file, out)?;
+    }
+  }
+
+  if unformatted > 0 {
+    return Err(Err::Unformatted(unformatted));
+  }
+
   Ok(())
 }
 
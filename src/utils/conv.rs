@@ -27,3 +27,25 @@ pub fn u16_bintext(val: u16) -> [u8; 16] {
     VALS[(val & 1) as usize],
   ]
 }
+
+/// Encodes an Intel HEX record as a line of text.
+///
+/// # Arguments
+///
+/// * `kind` - The record type (e.g. `0x00` for data).
+///
+/// * `addr` - The address field of the record.
+///
+/// * `data` - The data bytes of the record (at most 255).
+pub fn ihex_record(kind: u8, addr: u16, data: &[u8]) -> String {
+  let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+  bytes.extend_from_slice(data);
+  let checksum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+  bytes.push(checksum);
+
+  let mut record = String::from(":");
+  for b in bytes {
+    record.push_str(&format!("{:02X}", b));
+  }
+  record
+}
@@ -5,6 +5,9 @@ use crate::Byte;
 use derive_more::Display;
 
 /// Locations in source code.
+///
+/// For programs that span multiple source files, a location also
+/// carries the index of its file (`0` by default).
 #[derive(new, Display, Debug, PartialEq, Eq, Clone, Copy)]
 #[display(fmt = "line {}, column {}", line, col)]
 pub struct Loc {
@@ -13,6 +16,10 @@ pub struct Loc {
 
   /// Column in line.
   col: usize,
+
+  /// Index of the source file.
+  #[new(default)]
+  file: usize,
 }
 
 /// Create a default location at line `1`, column `1`.
@@ -26,7 +33,7 @@ pub struct Loc {
 /// ```
 impl Default for Loc {
   fn default() -> Self {
-    Self { line: 1, col: 1 }
+    Self { line: 1, col: 1, file: 0 }
   }
 }
 
@@ -74,10 +81,91 @@ impl Loc {
   pub fn col(&self) -> usize {
     self.col
   }
+
+  /// Returns the index of the location's source file.
+  pub fn file(&self) -> usize {
+    self.file
+  }
+
+  /// Returns the same location in the source file with index `file`.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use has::Loc;
+  ///
+  /// let loc = Loc::new(2, 3).in_file(1);
+  /// assert_eq!((loc.line(), loc.col(), loc.file()), (2, 3, 1));
+  /// assert_ne!(loc, Loc::new(2, 3));
+  /// ```
+  pub fn in_file(self, file: usize) -> Self {
+    Self { file, ..self }
+  }
+}
+
+/// Calculates the [locations](Loc) of increasing indices in a [Buf]
+/// in a single pass.
+///
+/// # Examples
+///
+/// ```
+/// use has::utils::loc::Locator;
+/// use has::Loc;
+///
+/// let mut locator = Locator::new("ab\ncd\ne".as_bytes());
+/// assert_eq!(locator.locate(1), Loc::new(1, 2));
+/// assert_eq!(locator.locate(4), Loc::new(2, 2));
+/// assert_eq!(locator.locate(6), Loc::new(3, 1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Locator<'b> {
+  /// The buffer.
+  buf: Buf<'b>,
+
+  /// The index of the last located byte.
+  index: Index,
+
+  /// The location of the last located byte.
+  loc: Loc,
+}
+
+impl<'b> Locator<'b> {
+  /// Create a locator for `buf`.
+  pub fn new(buf: Buf<'b>) -> Self {
+    Self { buf, index: 0, loc: Loc::default() }
+  }
+
+  /// Calculate the location of `index`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the index is out of the buffer's bounds or is smaller
+  /// than the previously located index.
+  pub fn locate(&mut self, index: Index) -> Loc {
+    for &b in &self.buf[self.index..index] {
+      self.loc.inc(b)
+    }
+
+    self.index = index;
+    self.loc
+  }
 }
 
 #[cfg(test)]
 mod tests {
+  #[test]
+  fn locator() {
+    use crate::utils::loc::Locator;
+    use crate::Loc;
+
+    let buf = "a\n\nbc\nd".as_bytes();
+    let mut locator = Locator::new(buf);
+
+    for index in 0..buf.len() {
+      assert_eq!(locator.locate(index), Loc::from_index(buf, index));
+    }
+  }
+
   #[test]
   fn display() {
     use crate::Loc;
@@ -96,3 +96,77 @@ where
 pub fn read_digit(buf: Buf) -> Option<(Byte, Buf)> {
   read_one(buf, |b| b.is_ascii_digit())
 }
+
+/// Whether a byte is whitespace other than a newline.
+fn is_blank(b: Byte) -> bool {
+  b.is_ascii_whitespace() && b != b'\n'
+}
+
+/// Consume a comma-separated list of items on the current line, such
+/// as the arguments of a macro call.
+///
+/// Items end at whitespace, a comma or a `/`, and the list ends at the
+/// first item that is not followed by a comma. Items may be empty
+/// (e.g. in `a,,b`).
+///
+/// Returns the items with their offsets from the start of `buf`, the
+/// remainder of the buffer after the last item and the number of
+/// consumed bytes.
+///
+/// # Arguments
+///
+/// * `buf` - The input buffer.
+///
+/// # Examples
+///
+/// ```
+/// use has::parser::read_list;
+///
+/// let (items, rem, len) = read_list(" R0 , 5 // Set".as_bytes());
+/// assert_eq!(items, [(1, "R0".as_bytes()), (6, "5".as_bytes())]);
+/// assert_eq!((rem, len), (" // Set".as_bytes(), 7));
+///
+/// let (items, rem, len) = read_list("  \n@1".as_bytes());
+/// assert!(items.is_empty());
+/// assert_eq!((rem, len), ("  \n@1".as_bytes(), 0));
+/// ```
+pub fn read_list(buf: Buf) -> (Vec<(usize, Buf)>, Buf, usize) {
+  let mut items = Vec::new();
+  let (blank, mut rem) = read_while(buf, is_blank);
+
+  if matches!(rem.first(), None | Some(b'\n') | Some(b'/')) {
+    return (items, buf, 0);
+  }
+
+  let mut offset = blank.len();
+
+  loop {
+    let (item, after) =
+      read_while(rem, |b| !b.is_ascii_whitespace() && b != b',' && b != b'/');
+    items.push((offset, item));
+    offset += item.len();
+
+    let end = offset;
+    let (blank, after) = read_while(after, is_blank);
+
+    match read_one(after, |b| b == b',') {
+      Some((_, after)) => {
+        let (blank2, after) = read_while(after, is_blank);
+        offset += blank.len() + 1 + blank2.len();
+        rem = after;
+      }
+      None => return (items, &buf[end..], end),
+    }
+  }
+}
+
+/// Whether the rest of the current line of `buf` is blank or a
+/// comment.
+///
+/// # Arguments
+///
+/// * `buf` - The input buffer.
+pub fn is_line_end(buf: Buf) -> bool {
+  let (_, rem) = read_while(buf, is_blank);
+  matches!(rem.first(), None | Some(b'\n') | Some(b'/'))
+}
@@ -2,12 +2,21 @@
 
 #[cfg(test)]
 mod programs {
+  use has::hack::dec;
+  use has::hack::dec::Encoding;
+  use has::hack::format;
+  use has::hack::source;
+  use has::hack::tst;
+  use has::hack::Emu;
+  use has::hack::Source;
+  use has::hack::TstScript;
   use has::HackProg;
   use std::fs;
   use std::fs::File;
   use std::io::BufWriter;
   use std::io::Read;
   use std::io::Write;
+  use std::path::Path;
 
   #[test]
   fn assembler() {
@@ -43,6 +52,156 @@ mod programs {
     }
   }
 
+  #[test]
+  fn source_map() {
+    for file in fs::read_dir("tests/programs").unwrap() {
+      let file = file.unwrap();
+      let file_path = file.path();
+      let file_ext = file_path.extension().unwrap().to_str().unwrap();
+
+      if file_ext == "asm" {
+        println!("Testing source map with fixture {}", file_path.display());
+
+        let input = fs::read_to_string(&file_path).unwrap();
+        let lines = input.lines().collect::<Vec<_>>();
+        let prog = HackProg::from_source(input.as_bytes()).unwrap();
+        assert_eq!(prog.source_map().len(), prog.insts().len());
+
+        for (inst, loc) in prog.insts().iter().zip(prog.source_map()) {
+          let line = lines[loc.line() - 1];
+          let text = &line[loc.col() - 1..];
+          let inst = inst.to_string();
+          let inst = inst.split(['=', ';']).next().unwrap();
+          assert!(text.starts_with(inst), "`{}` does not start with `{}`", text, inst);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn symbols() {
+    for file in fs::read_dir("tests/programs").unwrap() {
+      let file = file.unwrap();
+      let mut file_path = file.path();
+      let file_ext = file_path.extension().unwrap().to_str().unwrap();
+
+      if file_ext == "asm" {
+        println!("Testing symbol tables with fixture {}", file_path.display());
+
+        let input = fs::read(&file_path).unwrap();
+        let prog = HackProg::from_source(input.as_slice()).unwrap();
+        let words = prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+        let symbols = prog.to_symbols().collect::<Vec<_>>().join("\n");
+
+        file_path.set_extension("hack");
+        let bintext = fs::read(&file_path).unwrap();
+        let mut dis = HackProg::from_bintext(bintext.as_slice()).unwrap();
+        dis.load_symbols(symbols.as_bytes()).unwrap();
+        assert_eq!(dis.to_symbols().collect::<Vec<_>>().join("\n"), symbols);
+
+        let source = dis.to_source().collect::<Vec<_>>().join("\n");
+        let reassembled = HackProg::from_source(source.as_bytes()).unwrap();
+        let reassembled = reassembled.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(reassembled, words);
+      }
+    }
+  }
+
+  #[test]
+  fn recover_labels() {
+    for file in fs::read_dir("tests/programs").unwrap() {
+      let file = file.unwrap();
+      let file_path = file.path();
+      let file_ext = file_path.extension().unwrap().to_str().unwrap();
+
+      if file_ext == "hack" {
+        println!("Testing label recovery with fixture {}", file_path.display());
+
+        let input = fs::read(&file_path).unwrap();
+        let mut prog = HackProg::from_bintext(input.as_slice()).unwrap();
+        let words = prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+        prog.recover_labels();
+
+        let source = prog.to_source().collect::<Vec<_>>();
+        for (line, next) in source.iter().zip(source.iter().skip(1)) {
+          if next.contains(';') {
+            let numeric =
+              line.strip_prefix('@').is_some_and(|a| a.parse::<u16>().is_ok());
+            assert!(!numeric, "{} before {}", line, next);
+          }
+        }
+
+        let source = source.join("\n");
+        let reassembled = HackProg::from_source(source.as_bytes()).unwrap();
+        let reassembled = reassembled.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(reassembled, words);
+      }
+    }
+  }
+
+  #[test]
+  fn formatter() {
+    for file in fs::read_dir("tests/programs").unwrap() {
+      let file = file.unwrap();
+      let file_path = file.path();
+      let file_ext = file_path.extension().unwrap().to_str().unwrap();
+
+      if file_ext == "asm" {
+        println!("Testing formatter with fixture {}", file_path.display());
+
+        let input = fs::read(&file_path).unwrap();
+        let formatted = format::format(&input).unwrap();
+        assert_eq!(format::format(formatted.as_bytes()).unwrap(), formatted);
+
+        let comments = |buf: &[u8]| String::from_utf8_lossy(buf).matches("//").count();
+        assert_eq!(comments(formatted.as_bytes()), comments(&input));
+
+        let words = |buf| {
+          let prog = HackProg::from_source(buf).unwrap();
+          prog.to_words().collect::<Result<Vec<_>, _>>().unwrap()
+        };
+        assert_eq!(words(formatted.as_bytes()), words(&input));
+      }
+    }
+  }
+
+  #[test]
+  fn includes() {
+    for file in fs::read_dir("tests/programs").unwrap() {
+      let file = file.unwrap();
+      let file_path = file.path();
+      let file_ext = file_path.extension().unwrap().to_str().unwrap();
+
+      if file_ext == "asm" {
+        println!("Testing includes with fixture {}", file_path.display());
+
+        let name = file_path.file_name().unwrap().to_str().unwrap();
+        let main = format!("// Main\n.include \"{}\"\n.include \"./{}\"\n", name, name);
+        let main_path = file_path.with_file_name("Main.asm");
+        let read = |path: &Path| {
+          if path == main_path {
+            Ok(main.clone().into_bytes())
+          } else {
+            fs::read(path)
+          }
+        };
+
+        let files = source::load(std::slice::from_ref(&main_path), read).unwrap();
+        assert_eq!(files.len(), 2);
+
+        let sources = files.iter().map(|(path, buf)| Source::new(path, buf));
+        let prog = HackProg::from_sources(&sources.collect::<Vec<_>>()).unwrap();
+        assert!(prog.source_map().iter().all(|loc| loc.file() == 1));
+
+        let input = fs::read(&file_path).unwrap();
+        let fixture = HackProg::from_source(&input).unwrap();
+        let words =
+          |prog: &HackProg| prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(words(&prog), words(&fixture));
+      }
+    }
+  }
+
   #[test]
   fn disassembler_text() {
     for file in fs::read_dir("tests/programs").unwrap() {
@@ -108,4 +267,128 @@ mod programs {
       }
     }
   }
+
+  #[test]
+  fn output_formats() {
+    for file in fs::read_dir("tests/programs").unwrap() {
+      let file = file.unwrap();
+      let file_path = file.path();
+      let file_ext = file_path.extension().unwrap().to_str().unwrap();
+
+      if file_ext == "hack" {
+        println!("Testing output formats with fixture {}", file_path.display());
+
+        let mut input = Vec::with_capacity(1024);
+        File::open(&file_path).unwrap().read_to_end(&mut input).unwrap();
+        let prog = HackProg::from_bintext(input.as_slice()).unwrap();
+        let words = prog.to_words().collect::<Result<Vec<_>, _>>().unwrap();
+
+        let bin_le = prog.to_bin_le().collect::<Result<Vec<_>, _>>().unwrap().concat();
+        let ihex = prog.to_ihex().unwrap().join("\n");
+        let logisim = prog.to_logisim().unwrap().join("\n");
+        let readmemh =
+          prog.to_readmemh().collect::<Result<Vec<_>, _>>().unwrap().join("\n");
+        let readmemb =
+          prog.to_readmemb().collect::<Result<Vec<_>, _>>().unwrap().join("\n");
+
+        for prog in [
+          HackProg::from_bin_le(&bin_le),
+          HackProg::from_ihex(ihex.as_bytes()),
+          HackProg::from_logisim(logisim.as_bytes()),
+          HackProg::from_readmemh(readmemh.as_bytes()),
+          HackProg::from_readmemb(readmemb.as_bytes()),
+        ] {
+          let output = prog.unwrap().to_words().collect::<Result<Vec<_>, _>>().unwrap();
+          assert_eq!(output, words);
+        }
+
+        for (encoding, expected) in [
+          (Encoding::Bin, prog.to_bin().collect::<Result<Vec<_>, _>>().unwrap().concat()),
+          (Encoding::BinLe, bin_le),
+          (Encoding::Bintext, input.clone()),
+          (Encoding::Ihex, format!("{}\n", ihex).into_bytes()),
+          (Encoding::Logisim, format!("{}\n", logisim).into_bytes()),
+          (Encoding::Readmemh, format!("{}\n", readmemh).into_bytes()),
+          (Encoding::Readmemb, format!("{}\n", readmemb).into_bytes()),
+        ] {
+          let mut output = Vec::new();
+          prog.encode(encoding, &mut output).unwrap();
+          assert_eq!(output, expected);
+
+          let cmds = dec::Reader::new(expected.as_slice(), encoding).map(Result::unwrap);
+          let output = cmds.map(|cmd| cmd.to_string()).collect::<Vec<_>>();
+          assert_eq!(output, prog.to_source().collect::<Vec<_>>());
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn encoding_detection() {
+    for file in fs::read_dir("tests/programs").unwrap() {
+      let file = file.unwrap();
+      let file_path = file.path();
+      let file_ext = file_path.extension().unwrap().to_str().unwrap();
+
+      let encoding = match file_ext {
+        "hack" => Encoding::Bintext,
+        "hack_bin" => Encoding::Bin,
+        _ => continue,
+      };
+
+      println!("Testing encoding detection with fixture {}", file_path.display());
+
+      let mut input = Vec::with_capacity(1024);
+      File::open(&file_path).unwrap().read_to_end(&mut input).unwrap();
+      assert_eq!(dec::detect(&input), Ok(encoding));
+
+      let prog = HackProg::decode(&input, encoding).unwrap();
+      let ihex = prog.to_ihex().unwrap().join("\n");
+      assert_eq!(dec::detect(ihex.as_bytes()), Ok(Encoding::Ihex));
+      let logisim = prog.to_logisim().unwrap().join("\n");
+      assert_eq!(dec::detect(logisim.as_bytes()), Ok(Encoding::Logisim));
+      let readmemh =
+        prog.to_readmemh().collect::<Result<Vec<_>, _>>().unwrap().join("\n");
+      assert_eq!(dec::detect(readmemh.as_bytes()), Ok(Encoding::Readmemh));
+      let bin_le = prog.to_bin_le().collect::<Result<Vec<_>, _>>().unwrap().concat();
+      assert_eq!(dec::detect(&bin_le), Ok(Encoding::BinLe));
+    }
+  }
+
+  #[test]
+  fn test_scripts() {
+    for file in fs::read_dir("tests/programs").unwrap() {
+      let file = file.unwrap();
+      let file_path = file.path();
+      let file_ext = file_path.extension().unwrap().to_str().unwrap();
+
+      if file_ext == "tst" {
+        println!("Testing script {}", file_path.display());
+
+        let mut input = Vec::with_capacity(1024);
+        File::open(&file_path).unwrap().read_to_end(&mut input).unwrap();
+        let script = TstScript::parse(input.as_slice()).unwrap();
+        let report = tst::run(&script, file_path.parent().unwrap()).unwrap();
+
+        assert_eq!(report.mismatch(), None);
+        assert!(report.output().len() > 1);
+      }
+    }
+  }
+
+  #[test]
+  fn screen() {
+    let mut input = Vec::with_capacity(1024);
+    File::open("tests/programs/Rect.asm").unwrap().read_to_end(&mut input).unwrap();
+    let prog = HackProg::from_source(input.as_slice()).unwrap();
+
+    let mut emu = Emu::from_prog(&prog).unwrap();
+    emu.ram_mut()[0] = 40;
+    assert!(emu.run(10_000).unwrap());
+
+    let mut fixture = Vec::with_capacity(16 * 1024);
+    File::open("tests/programs/Rect.pbm").unwrap().read_to_end(&mut fixture).unwrap();
+
+    assert_eq!(emu.screen().to_pbm(), fixture);
+  }
 }
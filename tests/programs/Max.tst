@@ -0,0 +1,30 @@
+// Test script for Max.asm: computes RAM[2] = max(RAM[0], RAM[1]).
+
+load Max.asm,
+output-file Max.out,
+compare-to Max.cmp,
+output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+
+set PC 0,
+set RAM[0] 3,   // Set test arguments
+set RAM[1] 5;
+repeat 14 {
+  ticktock;
+}
+output;
+
+set PC 0,
+set RAM[0] 23456,
+set RAM[1] 12345;
+repeat 14 {
+  ticktock;
+}
+output;
+
+set PC 0,
+set RAM[0] -7,
+set RAM[1] -3;
+repeat 14 {
+  ticktock;
+}
+output;